@@ -0,0 +1,95 @@
+use bevy::prelude::*;
+use half::f16;
+
+/// How the accumulated samples of each pixel are stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AccumulationPrecision {
+    /// 16 bytes per pixel
+    #[default]
+    Full,
+    /// 8 bytes per pixel.
+    ///
+    /// The average is stored with 11 bits of precision, so after a few thousand samples a new
+    /// sample barely changes it and the remaining noise can show up as banding.
+    Half,
+}
+
+impl AccumulationPrecision {
+    pub const ALL: [Self; 2] = [Self::Full, Self::Half];
+}
+
+/// The samples accumulated in each pixel
+#[derive(Debug, Clone)]
+pub enum AccumulationBuffer {
+    /// The sum of the samples
+    Full(Vec<Vec4>),
+    /// The average of the samples.
+    /// A sum would overflow the largest `f16` after a few thousand bright samples.
+    Half(Vec<[f16; 4]>),
+}
+
+impl AccumulationBuffer {
+    pub fn new(precision: AccumulationPrecision, len: usize) -> Self {
+        match precision {
+            AccumulationPrecision::Full => Self::Full(vec![Vec4::ZERO; len]),
+            AccumulationPrecision::Half => Self::Half(vec![[f16::ZERO; 4]; len]),
+        }
+    }
+
+    pub fn precision(&self) -> AccumulationPrecision {
+        match self {
+            Self::Full(_) => AccumulationPrecision::Full,
+            Self::Half(_) => AccumulationPrecision::Half,
+        }
+    }
+
+    /// Memory used by the buffer, in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Full(data) => data.len() * std::mem::size_of::<Vec4>(),
+            Self::Half(data) => data.len() * std::mem::size_of::<[f16; 4]>(),
+        }
+    }
+
+    /// The sum of the `sample_count` samples accumulated in a pixel
+    pub fn sum(&self, pixel_index: usize, sample_count: u32) -> Vec4 {
+        match self {
+            Self::Full(data) => data[pixel_index],
+            Self::Half(data) => {
+                Vec4::from_array(data[pixel_index].map(f16::to_f32)) * sample_count as f32
+            }
+        }
+    }
+
+    /// The average of the `sample_count` samples accumulated in a pixel
+    pub fn average(&self, pixel_index: usize, sample_count: u32) -> Vec4 {
+        match self {
+            Self::Full(data) => data[pixel_index] / sample_count.max(1) as f32,
+            Self::Half(data) => Vec4::from_array(data[pixel_index].map(f16::to_f32)),
+        }
+    }
+
+    pub fn set_sum(&mut self, pixel_index: usize, sum: Vec4, sample_count: u32) {
+        match self {
+            Self::Full(data) => data[pixel_index] = sum,
+            Self::Half(data) => {
+                let average = sum / sample_count.max(1) as f32;
+                data[pixel_index] = average.to_array().map(f16::from_f32);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        match self {
+            Self::Full(data) => data.fill(Vec4::ZERO),
+            Self::Half(data) => data.fill([f16::ZERO; 4]),
+        }
+    }
+
+    pub fn resize(&mut self, len: usize) {
+        match self {
+            Self::Full(data) => data.resize(len, Vec4::ZERO),
+            Self::Half(data) => data.resize(len, [f16::ZERO; 4]),
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Renders a saved project without opening a window, for long or unattended renders.
+//!
+//! Meshes aren't saved in projects, so only the spheres of the project are rendered.
+//!
+//! A heavy render can be split in buckets rendered separately, for example on several machines,
+//! and merged afterwards:
+//! ```text
+//! headless project.ron --bucket 0,0,256,512 --bucket-out left.exr
+//! headless project.ron --bucket 256,0,256,512 --bucket-out right.exr
+//! headless --merge render.exr left.exr right.exr
+//! ```
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::Instant,
+};
+
+use bevy_tracing::{
+    camera::CustomCamera,
+    environment_map::EnvironmentMap,
+    export::{merge_bucket_exrs, write_bucket_exr, write_png},
+    load_error::LoadError,
+    project::load_project,
+    renderer::{Renderer, Tile},
+    scene::{Scene, SkyMode},
+};
+
+const USAGE: &str = "Usage: headless <project.ron> [options]
+       headless --merge <output.exr> <bucket.exr>...
+
+Options:
+  --width <pixels>           Width of the image, 512 by default
+  --height <pixels>          Height of the image, 512 by default
+  --samples <count>          Samples per pixel, 64 by default
+  --seed <offset>            Renders another noise pattern, see the Seed Offset setting
+  --output <path>            PNG written when the render is done, render.png by default
+  --save-interval <samples>  Also writes the output every time this many samples are done
+  --bucket <x,y,w,h>         Only renders this rectangle of the image, in pixels from the top left
+  --bucket-out <path>        EXR written instead of the PNG when rendering a bucket";
+
+#[derive(Debug)]
+struct Options {
+    project: String,
+    width: usize,
+    height: usize,
+    samples: usize,
+    seed: Option<u32>,
+    output: PathBuf,
+    save_interval: Option<usize>,
+    bucket: Option<Tile>,
+    bucket_out: Option<PathBuf>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut project = None;
+        let mut options = Self {
+            project: String::new(),
+            width: 512,
+            height: 512,
+            samples: 64,
+            seed: None,
+            output: PathBuf::from("render.png"),
+            save_interval: None,
+            bucket: None,
+            bucket_out: None,
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+            match arg.as_str() {
+                "--width" => options.width = parse_number(&arg, &value()?)?,
+                "--height" => options.height = parse_number(&arg, &value()?)?,
+                "--samples" => options.samples = parse_number(&arg, &value()?)?,
+                "--seed" => options.seed = Some(parse_number(&arg, &value()?)?),
+                "--output" => options.output = PathBuf::from(value()?),
+                "--save-interval" => options.save_interval = Some(parse_number(&arg, &value()?)?),
+                "--bucket" => options.bucket = Some(parse_bucket(&value()?)?),
+                "--bucket-out" => options.bucket_out = Some(PathBuf::from(value()?)),
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {arg}")),
+                _ if project.is_none() => project = Some(arg),
+                _ => return Err(format!("Unexpected argument {arg}")),
+            }
+        }
+        options.project = project.ok_or("The project is missing")?;
+        if options.width == 0 || options.height == 0 {
+            return Err("The image can't be empty".to_string());
+        }
+        if options.save_interval == Some(0) {
+            return Err("--save-interval must be at least 1".to_string());
+        }
+        if options.bucket.is_some() != options.bucket_out.is_some() {
+            return Err("--bucket and --bucket-out go together".to_string());
+        }
+        if let Some(bucket) = options.bucket {
+            if bucket.width == 0
+                || bucket.height == 0
+                || bucket.x + bucket.width > options.width
+                || bucket.y + bucket.height > options.height
+            {
+                return Err(format!("The bucket {bucket:?} isn't inside the image"));
+            }
+        }
+        Ok(options)
+    }
+
+    /// Where the render is written, the bucket EXR when there's one
+    fn output(&self) -> &Path {
+        self.bucket_out.as_deref().unwrap_or(&self.output)
+    }
+}
+
+fn parse_bucket(value: &str) -> Result<Tile, String> {
+    let numbers = value
+        .split(',')
+        .map(|number| parse_number("--bucket", number.trim()))
+        .collect::<Result<Vec<usize>, _>>()?;
+    let [x, y, width, height] = numbers[..] else {
+        return Err(format!("--bucket expects x,y,width,height, got {value}"));
+    };
+    Ok(Tile {
+        x,
+        y,
+        width,
+        height,
+    })
+}
+
+fn parse_number<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{arg} expects a positive integer, got {value}"))
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = if args.first().is_some_and(|arg| arg == "--merge") {
+        match &args[1..] {
+            [output, buckets @ ..] if !buckets.is_empty() => {
+                let buckets: Vec<_> = buckets.iter().map(PathBuf::from).collect();
+                merge_bucket_exrs(&buckets, Path::new(output))
+            }
+            _ => {
+                eprintln!("--merge needs an output and at least one bucket\n\n{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        match Options::parse(args.into_iter()) {
+            Ok(options) => run(&options),
+            Err(message) => {
+                eprintln!("{message}\n\n{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(options: &Options) -> Result<(), LoadError> {
+    let mut scene = Scene::default();
+    let mut camera = CustomCamera::new(45.0, 0.1, 100.0);
+    let mut renderer = Renderer::new(options.width, options.height);
+    load_project(&options.project, &mut scene, &mut camera, &mut renderer)?;
+    camera.resize(options.width as u32, options.height as u32);
+    load_assets(&mut scene)?;
+
+    // The project could have been saved with the accumulation disabled
+    renderer.accumulate = true;
+    if let Some(seed) = options.seed {
+        renderer.frame_seed_offset = seed;
+    }
+    renderer.set_bucket(options.bucket);
+
+    let start = Instant::now();
+    for sample in 1..=options.samples {
+        renderer.render_to(&camera, &scene);
+        if renderer.is_converged() {
+            println!("Converged after {sample} samples");
+            break;
+        }
+        if options
+            .save_interval
+            .is_some_and(|interval| sample % interval == 0 && sample < options.samples)
+        {
+            save(&renderer, options)?;
+            println!(
+                "Saved {} after {sample} samples, {:.1}s",
+                options.output().display(),
+                start.elapsed().as_secs_f32()
+            );
+        }
+    }
+    save(&renderer, options)?;
+    println!(
+        "Saved {} in {:.1}s",
+        options.output().display(),
+        start.elapsed().as_secs_f32()
+    );
+    Ok(())
+}
+
+/// Loads the textures and the environment map right away, the app loads them with systems
+fn load_assets(scene: &mut Scene) -> Result<(), LoadError> {
+    for texture in &mut scene.textures {
+        texture.data = Some(Arc::new(texture.load()?));
+    }
+    let sky = &mut scene.sky;
+    if sky.mode == SkyMode::Equirect && !sky.environment_path.is_empty() {
+        sky.environment = Some(Arc::new(EnvironmentMap::load(&sky.environment_path)?));
+    }
+    Ok(())
+}
+
+/// Writes the image next to the output and renames it, so a program watching the output never
+/// reads a partially written file
+fn save(renderer: &Renderer, options: &Options) -> Result<(), LoadError> {
+    let output = options.output();
+    let mut temporary = output.to_path_buf().into_os_string();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    if options.bucket.is_some() {
+        write_bucket_exr(renderer, &temporary)?;
+    } else {
+        write_png(renderer, false, &temporary)?;
+    }
+    fs::rename(&temporary, output).map_err(|err| LoadError::Save {
+        path: output.display().to_string(),
+        message: err.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String> {
+        Options::parse(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn parse_options() {
+        let options = parse("scene.ron --samples 128 --save-interval 16 --output out.png").unwrap();
+        assert_eq!(options.project, "scene.ron");
+        assert_eq!(options.samples, 128);
+        assert_eq!(options.save_interval, Some(16));
+        assert_eq!(options.output, PathBuf::from("out.png"));
+        assert_eq!((options.width, options.height), (512, 512));
+
+        assert!(parse("--samples 8").is_err());
+        assert!(parse("scene.ron --samples").is_err());
+        assert!(parse("scene.ron --save-interval 0").is_err());
+        assert!(parse("scene.ron --width -1").is_err());
+        assert!(parse("scene.ron other.ron").is_err());
+
+        let options = parse("scene.ron --width 64 --bucket 16,0,48,32 --bucket-out a.exr").unwrap();
+        let bucket = Tile {
+            x: 16,
+            y: 0,
+            width: 48,
+            height: 32,
+        };
+        assert_eq!(options.bucket, Some(bucket));
+        assert_eq!(options.output(), Path::new("a.exr"));
+        assert!(parse("scene.ron --bucket 16,0,48,32").is_err());
+        assert!(parse("scene.ron --width 32 --bucket 16,0,48,32 --bucket-out a.exr").is_err());
+        assert!(parse("scene.ron --bucket 0,0,8 --bucket-out a.exr").is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use bevy::{
+    math::Vec3A,
+    prelude::*,
+    render::{mesh::Indices, primitives::Aabb},
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::HashSet,
+};
+use futures_lite::future;
+
+use crate::{renderer::Renderer, scene::Scene};
+
+/// Maximum number of triangles stored in a leaf node
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct BvhNode {
+    pub aabb: Aabb,
+    /// For interior nodes, this is the index of the left child. The right child is always `first + 1`.
+    /// For leaf nodes, this is the index of the first triangle.
+    pub first: u32,
+    /// The number of triangles in a leaf node, 0 for interior nodes
+    pub count: u32,
+}
+
+impl BvhNode {
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+/// Bounding volume hierarchy over the triangles of a mesh
+///
+/// The tree is split at the median centroid along the longest axis, this is not the best split,
+/// but it's fast to build and always produces a balanced tree.
+///
+/// Reference:
+/// * https://jacco.ompf2.com/2022/04/13/how-to-build-a-bvh-part-1-basics/
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    /// The root node is always the first node
+    pub nodes: Vec<BvhNode>,
+    /// Vertex indices of each triangle, ordered so that every leaf references a contiguous range
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl Bvh {
+    pub fn build(positions: &[[f32; 3]], indices: &[u32]) -> Self {
+        let _span = info_span!("build bvh").entered();
+
+        let triangles: Vec<[u32; 3]> = indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect();
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(2 * triangles.len() / MAX_LEAF_TRIANGLES + 1),
+            triangles,
+        };
+        if bvh.triangles.is_empty() {
+            return bvh;
+        }
+
+        bvh.nodes.push(BvhNode {
+            aabb: Aabb::default(),
+            first: 0,
+            count: bvh.triangles.len() as u32,
+        });
+        bvh.update_bounds(0, positions);
+        bvh.subdivide(0, positions);
+        bvh
+    }
+
+    fn update_bounds(&mut self, node_index: usize, positions: &[[f32; 3]]) {
+        let node = &self.nodes[node_index];
+        let mut min = Vec3A::splat(f32::INFINITY);
+        let mut max = Vec3A::splat(f32::NEG_INFINITY);
+        for triangle in self.node_triangles(node) {
+            for i in triangle {
+                let position = Vec3A::from(positions[*i as usize]);
+                min = min.min(position);
+                max = max.max(position);
+            }
+        }
+        self.nodes[node_index].aabb = Aabb::from_min_max(min.into(), max.into());
+    }
+
+    fn subdivide(&mut self, node_index: usize, positions: &[[f32; 3]]) {
+        let node = &self.nodes[node_index];
+        let count = node.count as usize;
+        if count <= MAX_LEAF_TRIANGLES {
+            return;
+        }
+        let first = node.first as usize;
+
+        let centroid = |triangle: &[u32; 3]| {
+            triangle
+                .iter()
+                .map(|i| Vec3A::from(positions[*i as usize]))
+                .sum::<Vec3A>()
+                / 3.0
+        };
+
+        // Split along the longest axis of the bounds of the centroids
+        let mut min = Vec3A::splat(f32::INFINITY);
+        let mut max = Vec3A::splat(f32::NEG_INFINITY);
+        for triangle in self.node_triangles(node) {
+            let centroid = centroid(triangle);
+            min = min.min(centroid);
+            max = max.max(centroid);
+        }
+        let extent = max - min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+        if extent[axis] <= 0.0 {
+            // All the centroids are at the same position, there's no way to split them
+            return;
+        }
+
+        let mid = count / 2;
+        self.triangles[first..first + count]
+            .select_nth_unstable_by(mid, |a, b| centroid(a)[axis].total_cmp(&centroid(b)[axis]));
+
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode {
+            aabb: Aabb::default(),
+            first: first as u32,
+            count: mid as u32,
+        });
+        self.nodes.push(BvhNode {
+            aabb: Aabb::default(),
+            first: (first + mid) as u32,
+            count: (count - mid) as u32,
+        });
+        self.nodes[node_index].first = left as u32;
+        self.nodes[node_index].count = 0;
+
+        for child in [left, left + 1] {
+            self.update_bounds(child, positions);
+            self.subdivide(child, positions);
+        }
+    }
+
+    /// The triangles referenced by a leaf node
+    pub fn node_triangles(&self, node: &BvhNode) -> &[[u32; 3]] {
+        let first = node.first as usize;
+        &self.triangles[first..first + node.count as usize]
+    }
+}
+
+/// BVHs currently being built, with the [`crate::scene::TriangleMesh::revision`] of the vertices they are built from
+#[derive(Default, Resource)]
+pub struct BvhBuildTasks(Vec<(u64, Task<Bvh>)>);
+
+impl BvhBuildTasks {
+    pub fn is_building(&self, revision: u64) -> bool {
+        self.0.iter().any(|(r, _)| *r == revision)
+    }
+}
+
+/// Builds the BVH of every mesh that doesn't have one yet, and recomputes the stale AABBs and
+/// inverse transforms.
+///
+/// Building the BVH of a large mesh can take a while, so it's done in the background to keep the app
+/// responsive. Meshes are skipped by the renderer until their BVH is ready.
+/// The tasks of meshes that were edited or removed from the scene in the meantime are cancelled.
+pub fn build_mesh_bvhs(
+    mut scene: ResMut<Scene>,
+    mut tasks: ResMut<BvhBuildTasks>,
+    mut renderer: ResMut<Renderer>,
+    // Revisions of the meshes that can't have a BVH, so the warning isn't repeated every frame
+    mut skipped: Local<HashSet<u64>>,
+) {
+    for mesh in &mut scene.meshes {
+        // These are cheap compared to the BVH so they are done right away
+        mesh.update_world_to_local();
+        if mesh.aabb_dirty {
+            mesh.recompute_aabb();
+            renderer.reset_frame_index();
+        }
+    }
+
+    // Dropping a task cancels it
+    tasks
+        .0
+        .retain(|(revision, _)| scene.meshes.iter().any(|mesh| mesh.revision == *revision));
+
+    let task_pool = AsyncComputeTaskPool::get();
+    for mesh in &scene.meshes {
+        if mesh.bvh.is_some() || tasks.is_building(mesh.revision) {
+            continue;
+        }
+        let Some(mesh_data) = &mesh.mesh else {
+            continue;
+        };
+
+        if skipped.contains(&mesh.revision) {
+            continue;
+        }
+        // Meshes are checked when they are copied from their asset, so this only skips meshes
+        // that were built or edited by hand. They are never rendered without a BVH.
+        let Some(positions) = mesh_data
+            .attribute(Mesh::ATTRIBUTE_POSITION)
+            .and_then(|x| x.as_float3())
+        else {
+            warn!("Skipping a mesh without float3 vertex positions");
+            skipped.insert(mesh.revision);
+            continue;
+        };
+        // The renderer reads the normals of the meshes that have a BVH
+        if mesh_data
+            .attribute(Mesh::ATTRIBUTE_NORMAL)
+            .and_then(|x| x.as_float3())
+            .is_none()
+        {
+            warn!("Skipping a mesh without float3 vertex normals");
+            skipped.insert(mesh.revision);
+            continue;
+        }
+        let Some(Indices::U32(indices)) = mesh_data.indices() else {
+            warn!("Skipping a mesh without u32 indices");
+            skipped.insert(mesh.revision);
+            continue;
+        };
+
+        let positions = positions.to_vec();
+        let indices = indices.clone();
+        let task = task_pool.spawn(async move { Bvh::build(&positions, &indices) });
+        tasks.0.push((mesh.revision, task));
+    }
+
+    tasks.0.retain_mut(|(revision, task)| {
+        let Some(bvh) = future::block_on(future::poll_once(task)) else {
+            return true;
+        };
+        // Copies of a mesh share its revision until one of them is edited
+        for mesh in scene
+            .meshes
+            .iter_mut()
+            .filter(|mesh| mesh.revision == *revision)
+        {
+            mesh.bvh = Some(bvh.clone());
+        }
+        renderer.reset_frame_index();
+        false
+    });
+}
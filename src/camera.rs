@@ -1,12 +1,90 @@
+use std::ops::RangeInclusive;
+
 use bevy::{
     input::mouse::MouseMotion,
     math::{Vec3A, Vec4Swizzles},
     prelude::*,
+    render::primitives::Aabb,
     window::{CursorGrabMode, PrimaryWindow},
 };
 use rayon::prelude::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+
+use crate::{random, renderer::Renderer};
+
+/// The part of the camera that is saved in a project
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraSettings {
+    pub position: Vec3,
+    pub forward_direction: Vec3,
+    /// The field of view showing the same area as the orthographic projection at the focus distance
+    /// when `orthographic_height` is set
+    pub vertical_fov: f32,
+    /// Uses an orthographic projection of this height instead of the perspective `vertical_fov`
+    #[serde(default)]
+    pub orthographic_height: Option<f32>,
+    #[serde(default = "default_world_up")]
+    pub world_up: Vec3,
+    #[serde(default)]
+    pub aperture: f32,
+    #[serde(default = "default_focus_distance")]
+    pub focus_distance: f32,
+}
+
+fn default_world_up() -> Vec3 {
+    Vec3::Y
+}
+
+fn default_focus_distance() -> f32 {
+    6.0
+}
+
+/// A saved view, to compare renders from the same viewpoint after changing the scene
+#[derive(Debug, Clone)]
+pub struct CameraBookmark {
+    pub name: String,
+    pub position: Vec3,
+    pub forward_direction: Vec3,
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct CameraBookmarks(pub Vec<CameraBookmark>);
 
-use crate::renderer::Renderer;
+/// How the scene is projected on the image
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CameraProjection {
+    /// The rays start at the camera position, the field of view is in degrees
+    Perspective { vertical_fov: f32 },
+    /// The rays are parallel and start on the camera plane, so objects don't shrink with the
+    /// distance. This is useful for technical drawings and diagrams.
+    Orthographic { height: f32 },
+}
+
+impl Default for CameraProjection {
+    fn default() -> Self {
+        Self::Perspective { vertical_fov: 45.0 }
+    }
+}
+
+impl CameraProjection {
+    /// The field of view of a perspective projection showing the same height at `distance`
+    pub fn vertical_fov_at(self, distance: f32) -> f32 {
+        match self {
+            Self::Perspective { vertical_fov } => vertical_fov,
+            Self::Orthographic { height } => (2.0 * (height / 2.0 / distance).atan()).to_degrees(),
+        }
+    }
+
+    /// The height of an orthographic projection showing the same height at `distance`
+    pub fn height_at(self, distance: f32) -> f32 {
+        match self {
+            Self::Perspective { vertical_fov } => {
+                2.0 * distance * (vertical_fov.to_radians() / 2.0).tan()
+            }
+            Self::Orthographic { height } => height,
+        }
+    }
+}
 
 #[derive(Debug, Default, Clone, Resource)]
 pub struct CustomCamera {
@@ -18,11 +96,20 @@ pub struct CustomCamera {
     pub position: Vec3,
     pub forward_direction: Vec3,
 
+    /// Diameter of the lens, 0.0 is a pinhole camera where everything is in focus
+    pub aperture: f32,
+    /// Distance along the forward direction of the plane that is in focus
+    pub focus_distance: f32,
+
     pub ray_directions: Vec<Vec3A>,
+    /// `1.0 / ray_directions`, used by the slab test of every AABB intersection
+    pub inv_ray_directions: Vec<Vec3A>,
 
-    vertical_fov: f32,
+    projection_mode: CameraProjection,
     near_clip: f32,
     far_clip: f32,
+    /// Used to keep the camera level, this is also where the sky is above the horizon
+    world_up: Vec3,
 
     viewport_width: u32,
     viewport_height: u32,
@@ -30,14 +117,20 @@ pub struct CustomCamera {
 
 impl CustomCamera {
     pub fn new(vertical_fov: f32, near_clip: f32, far_clip: f32) -> Self {
-        Self {
-            vertical_fov,
+        let mut camera = Self {
+            projection_mode: CameraProjection::Perspective { vertical_fov },
             near_clip,
             far_clip,
+            world_up: default_world_up(),
+            focus_distance: default_focus_distance(),
             forward_direction: Vec3::NEG_Z,
             position: Vec3::new(0.0, 0.0, 6.0),
             ..default()
-        }
+        };
+        // The gizmos and the partial resets project through the view before the camera first moves
+        camera.recalculate_projection();
+        camera.recalculate_view();
+        camera
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
@@ -52,55 +145,361 @@ impl CustomCamera {
         self.recalculate_ray_directions();
     }
 
-    fn recalculate_projection(&mut self) {
-        self.projection = Mat4::perspective_rh(
-            self.vertical_fov.to_radians(),
-            self.viewport_width as f32 / self.viewport_height as f32,
+    pub fn settings(&self) -> CameraSettings {
+        CameraSettings {
+            position: self.position,
+            forward_direction: self.forward_direction,
+            vertical_fov: self.projection_mode.vertical_fov_at(self.focus_distance),
+            orthographic_height: match self.projection_mode {
+                CameraProjection::Perspective { .. } => None,
+                CameraProjection::Orthographic { height } => Some(height),
+            },
+            world_up: self.world_up,
+            aperture: self.aperture,
+            focus_distance: self.focus_distance,
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: &CameraSettings) {
+        self.position = settings.position;
+        self.forward_direction = settings.forward_direction;
+        self.projection_mode = match settings.orthographic_height {
+            Some(height) => CameraProjection::Orthographic { height },
+            None => CameraProjection::Perspective {
+                vertical_fov: settings.vertical_fov,
+            },
+        };
+        self.world_up = settings.world_up;
+        self.aperture = settings.aperture;
+        self.focus_distance = settings.focus_distance;
+
+        self.recalculate_projection();
+        self.recalculate_view();
+        self.recalculate_ray_directions();
+    }
+
+    pub fn bookmark(&self, name: String) -> CameraBookmark {
+        CameraBookmark {
+            name,
+            position: self.position,
+            forward_direction: self.forward_direction,
+        }
+    }
+
+    /// Moves the camera back to a saved view
+    pub fn restore_bookmark(&mut self, bookmark: &CameraBookmark) {
+        self.position = bookmark.position;
+        self.forward_direction = bookmark.forward_direction;
+        self.recalculate_view();
+        self.recalculate_ray_directions();
+    }
+
+    pub fn near_clip(&self) -> f32 {
+        self.near_clip
+    }
+
+    pub fn far_clip(&self) -> f32 {
+        self.far_clip
+    }
+
+    /// Only the hits of camera rays inside this range of distances are kept
+    pub fn clip_range(&self) -> RangeInclusive<f32> {
+        self.near_clip..=self.far_clip
+    }
+
+    pub fn world_up(&self) -> Vec3 {
+        self.world_up
+    }
+
+    pub fn projection_mode(&self) -> CameraProjection {
+        self.projection_mode
+    }
+
+    pub fn set_projection_mode(&mut self, projection_mode: CameraProjection) {
+        self.projection_mode = projection_mode;
+        self.recalculate_projection();
+        self.recalculate_ray_directions();
+    }
+
+    /// Sets the vertical field of view in degrees, this switches to a perspective projection
+    pub fn set_fov(&mut self, vertical_fov: f32) {
+        self.set_projection_mode(CameraProjection::Perspective { vertical_fov });
+    }
+
+    /// Content authored in CAD tools or Blender is often Z-up instead of Y-up
+    pub fn set_world_up(&mut self, world_up: Vec3) {
+        self.world_up = world_up.normalize();
+        self.recalculate_view();
+        self.recalculate_ray_directions();
+    }
+
+    pub fn set_clip(&mut self, near_clip: f32, far_clip: f32) {
+        self.near_clip = near_clip;
+        self.far_clip = far_clip;
+        self.recalculate_projection();
+        self.recalculate_ray_directions();
+    }
+
+    /// Moves the camera back along its forward direction until the whole box is in view.
+    /// An orthographic projection is resized to fit the box instead.
+    pub fn frame_bounds(&mut self, bounds: &Aabb) {
+        let radius = bounds.half_extents.length();
+        let aspect_ratio = self.viewport_width.max(1) as f32 / self.viewport_height.max(1) as f32;
+        let distance = match self.projection_mode {
+            CameraProjection::Perspective { vertical_fov } => {
+                let half_vertical_fov = vertical_fov.to_radians() / 2.0;
+                let half_horizontal_fov = (half_vertical_fov.tan() * aspect_ratio).atan();
+                // The sphere around the box fits in the narrowest side of the view
+                radius / half_vertical_fov.min(half_horizontal_fov).sin()
+            }
+            CameraProjection::Orthographic { .. } => {
+                self.projection_mode = CameraProjection::Orthographic {
+                    height: 2.0 * radius / aspect_ratio.min(1.0),
+                };
+                self.recalculate_projection();
+                // Far enough that the sphere around the box is in front of the near plane
+                radius + self.near_clip + 1.0
+            }
+        };
+
+        self.position = Vec3::from(bounds.center) - self.forward_direction * distance;
+        self.recalculate_view();
+        self.recalculate_ray_directions();
+    }
+
+    /// Formats the camera as Rust code that recreates it, useful to hardcode a view in `main.rs`
+    pub fn rust_snippet(&self) -> String {
+        let vec3 = |v: Vec3| format!("Vec3::new({:?}, {:?}, {:?})", v.x, v.y, v.z);
+        let mut snippet = format!(
+            "let mut camera = CustomCamera::new({:?}, {:?}, {:?});\n\
+             camera.position = {};\n\
+             camera.forward_direction = {};\n",
+            self.projection_mode.vertical_fov_at(self.focus_distance),
             self.near_clip,
             self.far_clip,
+            vec3(self.position),
+            vec3(self.forward_direction),
         );
+        if self.world_up != default_world_up() {
+            snippet += &format!("camera.set_world_up({});\n", vec3(self.world_up));
+        }
+        if let CameraProjection::Orthographic { height } = self.projection_mode {
+            snippet += &format!(
+                "camera.set_projection_mode(CameraProjection::Orthographic {{ height: {height:?} }});\n"
+            );
+        }
+        if self.aperture > 0.0 {
+            snippet += &format!(
+                "camera.aperture = {:?};\ncamera.focus_distance = {:?};\n",
+                self.aperture, self.focus_distance
+            );
+        }
+        snippet
+    }
+
+    /// Projects a point from world space to normalized device coordinates.
+    /// Returns `None` if the point is behind the camera.
+    pub fn world_to_ndc(&self, point: Vec3) -> Option<Vec3> {
+        let clip = self.projection * self.view * point.extend(1.0);
+        if clip.w <= 0.0 {
+            return None;
+        }
+        Some(clip.xyz() / clip.w)
+    }
+
+    /// Returns the rectangle in normalized device coordinates covered by a sphere.
+    /// Returns `None` if a part of the sphere is behind the camera.
+    pub fn project_bounding_sphere(&self, center: Vec3, radius: f32) -> Option<(Vec2, Vec2)> {
+        let mut min = Vec2::splat(f32::INFINITY);
+        let mut max = Vec2::splat(f32::NEG_INFINITY);
+        // The corners of the box around the sphere are enough for a conservative rectangle
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { -1.0 } else { 1.0 },
+            );
+            let ndc = self.world_to_ndc(center + corner * radius)?.truncate();
+            min = min.min(ndc);
+            max = max.max(ndc);
+        }
+        Some((min, max))
+    }
+
+    fn recalculate_projection(&mut self) {
+        // A zero height would give an infinite aspect ratio and a NaN projection
+        let aspect_ratio = self.viewport_width.max(1) as f32 / self.viewport_height.max(1) as f32;
+        self.projection = match self.projection_mode {
+            CameraProjection::Perspective { vertical_fov } => Mat4::perspective_rh(
+                vertical_fov.to_radians(),
+                aspect_ratio,
+                self.near_clip,
+                self.far_clip,
+            ),
+            CameraProjection::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * aspect_ratio;
+                Mat4::orthographic_rh(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.near_clip,
+                    self.far_clip,
+                )
+            }
+        };
         self.inverse_projection = self.projection.inverse();
     }
 
     fn recalculate_view(&mut self) {
-        self.view = Mat4::look_at_rh(
-            self.position,
-            self.position + self.forward_direction,
-            Vec3::Y,
-        );
+        // The view is undefined when looking straight along the up axis, any other up works there
+        let up = if self.forward_direction.cross(self.world_up).length_squared() < 1e-6 {
+            self.forward_direction.any_orthonormal_vector()
+        } else {
+            self.world_up
+        };
+        self.view = Mat4::look_at_rh(self.position, self.position + self.forward_direction, up);
         self.inverse_view = self.view.inverse();
     }
 
     fn recalculate_ray_directions(&mut self) {
         let _span = info_span!("recalculate ray directions").entered();
-        self.ray_directions.resize(
-            (self.viewport_width * self.viewport_height) as usize,
-            Vec3A::ZERO,
-        );
+        let len = (self.viewport_width * self.viewport_height) as usize;
+        // Shrinking keeps the capacity, so only growing past the largest size so far allocates
+        self.ray_directions.resize(len, Vec3A::ZERO);
+        self.inv_ray_directions.resize(len, Vec3A::ZERO);
 
         // This is called every time the camera moves so it's important to make it fast
+        let projection_mode = self.projection_mode;
         self.ray_directions
             .par_iter_mut()
+            .zip(&mut self.inv_ray_directions)
             .enumerate()
-            .for_each(|(i, ray_dir)| {
-                let x = i % self.viewport_width as usize + 1;
-                let y = i / self.viewport_width as usize + 1;
-                let coord = Vec2::new(
-                    x as f32 / self.viewport_width as f32,
-                    y as f32 / self.viewport_height as f32,
+            .for_each(|(i, (ray_dir, inv_ray_dir))| {
+                // Through the center of the pixel, like the jittered rays without jitter
+                let coord = pixel_to_ndc(
+                    i,
+                    Vec2::splat(0.5),
+                    self.viewport_width,
+                    self.viewport_height,
+                );
+                *ray_dir = ndc_to_ray_direction(
+                    projection_mode,
+                    self.inverse_projection,
+                    self.inverse_view,
+                    coord,
                 );
-                let mut coord = coord * 2.0 - 1.0; // -1 .. 1
-                coord.y = -coord.y;
+                *inv_ray_dir = 1.0 / *ray_dir;
+            });
+    }
 
-                let target = self.inverse_projection * coord.extend(1.0).extend(1.0);
-                // world space
-                *ray_dir = (self.inverse_view * (target.xyz() / target.w).normalize().extend(0.0))
+    /// Returns the origin and direction of a ray going through a random point of the pixel instead
+    /// of the precomputed one. `jitter` is the offset from the center of the pixel, in [-0.5, 0.5]
+    /// inside the pixel, the filters wider than a pixel go further.
+    pub fn jittered_ray(&self, pixel_index: usize, jitter: Vec2) -> (Vec3A, Vec3A) {
+        let coord = pixel_to_ndc(
+            pixel_index,
+            jitter + 0.5,
+            self.viewport_width,
+            self.viewport_height,
+        );
+        (
+            self.ndc_to_ray_origin(coord),
+            self.ndc_to_ray_direction(coord),
+        )
+    }
+
+    /// Returns the origin of the ray going through the center of a pixel, the precomputed
+    /// directions only share it with a perspective projection
+    pub fn ray_origin(&self, pixel_index: usize) -> Vec3A {
+        match self.projection_mode {
+            CameraProjection::Perspective { .. } => self.position.into(),
+            CameraProjection::Orthographic { .. } => self.ndc_to_ray_origin(pixel_to_ndc(
+                pixel_index,
+                Vec2::splat(0.5),
+                self.viewport_width,
+                self.viewport_height,
+            )),
+        }
+    }
+
+    /// Moves a camera ray to a point of the lens, keeping it aimed at the same point of the focus
+    /// plane. Returns the new origin and direction.
+    ///
+    /// `lens_sample` is a point of the unit square mapped to the lens.
+    ///
+    /// Reference:
+    /// * https://raytracing.github.io/books/RayTracingInOneWeekend.html#defocusblur
+    pub fn thin_lens_ray(
+        &self,
+        origin: Vec3A,
+        direction: Vec3A,
+        lens_sample: Vec2,
+    ) -> (Vec3A, Vec3A) {
+        let focus_point =
+            origin + direction * self.focus_distance / direction.dot(self.forward_direction.into());
+
+        let lens_point = random::in_unit_disk(lens_sample) * self.aperture / 2.0;
+        let right = Vec3A::from(self.inverse_view.x_axis.xyz());
+        let up = Vec3A::from(self.inverse_view.y_axis.xyz());
+        let origin = origin + right * lens_point.x + up * lens_point.y;
+        (origin, (focus_point - origin).normalize())
+    }
+
+    /// Returns the world space direction of the ray going through a point in normalized device coordinates
+    pub fn ndc_to_ray_direction(&self, ndc: Vec2) -> Vec3A {
+        ndc_to_ray_direction(
+            self.projection_mode,
+            self.inverse_projection,
+            self.inverse_view,
+            ndc,
+        )
+    }
+
+    /// Returns the world space origin of the ray going through a point in normalized device
+    /// coordinates
+    pub fn ndc_to_ray_origin(&self, ndc: Vec2) -> Vec3A {
+        match self.projection_mode {
+            CameraProjection::Perspective { .. } => self.position.into(),
+            CameraProjection::Orthographic { .. } => {
+                // The point of the camera plane, the depth is dropped to start at the camera
+                let view = self.inverse_projection * ndc.extend(0.0).extend(1.0);
+                (self.inverse_view * view.xy().extend(0.0).extend(1.0))
                     .xyz()
-                    .into();
-            });
+                    .into()
+            }
+        }
     }
 }
 
+/// `offset` is the position inside the pixel, from its top left corner
+fn pixel_to_ndc(pixel_index: usize, offset: Vec2, width: u32, height: u32) -> Vec2 {
+    let x = (pixel_index % width as usize) as f32 + offset.x;
+    let y = (pixel_index / width as usize) as f32 + offset.y;
+    let coord = Vec2::new(x / width as f32, y / height as f32);
+    let mut coord = coord * 2.0 - 1.0; // -1 .. 1
+    coord.y = -coord.y;
+    coord
+}
+
+fn ndc_to_ray_direction(
+    projection_mode: CameraProjection,
+    inverse_projection: Mat4,
+    inverse_view: Mat4,
+    ndc: Vec2,
+) -> Vec3A {
+    if let CameraProjection::Orthographic { .. } = projection_mode {
+        // Every ray goes straight forward
+        return (-inverse_view.z_axis.xyz()).into();
+    }
+    let target = inverse_projection * ndc.extend(1.0).extend(1.0);
+    // world space
+    (inverse_view * (target.xyz() / target.w).normalize().extend(0.0))
+        .xyz()
+        .into()
+}
+
 pub fn update_camera(
     mut camera: ResMut<CustomCamera>,
     mut mouse_motion_events: EventReader<MouseMotion>,
@@ -117,6 +516,7 @@ pub fn update_camera(
 
     let mut window = primary_window.single_mut();
     if !mouse_button_input.pressed(MouseButton::Right) {
+        renderer.is_moving = false;
         window.cursor.visible = true;
         window.cursor.grab_mode = CursorGrabMode::None;
         return;
@@ -126,9 +526,12 @@ pub fn update_camera(
 
     let mut moved = false;
 
-    let up_direction = Vec3::Y;
+    let up_direction = camera.world_up;
     let forward_direction = camera.forward_direction;
-    let right_direction = camera.forward_direction.cross(up_direction);
+    let right_direction = forward_direction
+        .cross(up_direction)
+        .try_normalize()
+        .unwrap_or_else(|| forward_direction.any_orthonormal_vector());
 
     let speed = 5.0;
     let rotation_speed = 1.0;
@@ -162,9 +565,14 @@ pub fn update_camera(
         if delta.x != 0.0 || delta.y != 0.0 {
             let pitch_delta = delta.y * rotation_speed * time.delta_seconds();
             let yaw_delta = delta.x * rotation_speed * time.delta_seconds();
-            let q = Quat::from_axis_angle(right_direction, -pitch_delta)
-                * Quat::from_axis_angle(up_direction, -yaw_delta);
-            camera.forward_direction = q.normalize() * forward_direction;
+            let yaw = Quat::from_axis_angle(up_direction, -yaw_delta);
+            let q = Quat::from_axis_angle(right_direction, -pitch_delta) * yaw;
+            let mut new_forward = q.normalize() * forward_direction;
+            // Stop pitching just before looking along the up axis, the view would flip past it
+            if new_forward.dot(up_direction).abs() > 0.999 {
+                new_forward = yaw * forward_direction;
+            }
+            camera.forward_direction = new_forward;
 
             moved = true;
         }
@@ -175,4 +583,53 @@ pub fn update_camera(
         camera.recalculate_ray_directions();
         renderer.reset_frame_index();
     }
+    renderer.is_moving = moved;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_camera() -> CustomCamera {
+        let mut camera = CustomCamera::new(45.0, 0.1, 100.0);
+        camera.resize(64, 64);
+        camera
+    }
+
+    #[test]
+    fn new_camera_projects_through_its_position() {
+        let camera = test_camera();
+        let ndc = camera.world_to_ndc(Vec3::ZERO).unwrap();
+        assert!(ndc.truncate().abs_diff_eq(Vec2::ZERO, 1e-6), "{ndc}");
+        // A point behind the camera position, but in front of the identity view
+        assert_eq!(camera.world_to_ndc(Vec3::new(0.0, 0.0, 7.0)), None);
+    }
+
+    #[test]
+    fn orthographic_rays_start_at_the_camera() {
+        let mut camera = test_camera();
+        camera.set_projection_mode(CameraProjection::Orthographic { height: 4.0 });
+        let origin = camera.ndc_to_ray_origin(Vec2::ZERO);
+        assert!(origin.abs_diff_eq(camera.position.into(), 1e-5), "{origin}");
+        let corner = camera.ndc_to_ray_origin(Vec2::ONE);
+        assert!((corner.z - camera.position.z).abs() < 1e-5, "{corner}");
+    }
+
+    #[test]
+    fn bounding_sphere_covers_its_center() {
+        let camera = test_camera();
+        let (min, max) = camera.project_bounding_sphere(Vec3::ZERO, 1.0).unwrap();
+        assert!(min.cmplt(Vec2::ZERO).all() && max.cmpgt(Vec2::ZERO).all());
+        assert!(min.cmpgt(Vec2::NEG_ONE).all() && max.cmplt(Vec2::ONE).all());
+    }
+
+    #[test]
+    fn zero_height_viewport_stays_finite() {
+        let mut camera = test_camera();
+        camera.resize(64, 0);
+        assert!(camera.projection.is_finite());
+        assert!(camera.inverse_projection.is_finite());
+        assert!(camera.ray_directions.iter().all(|dir| dir.is_finite()));
+        assert!(camera.inv_ray_directions.iter().all(|dir| dir.is_finite()));
+    }
 }
@@ -0,0 +1,127 @@
+use bevy::prelude::*;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+
+/// Each iteration doubles the distance between the taps, so more than this blurs whole objects
+pub const MAX_DENOISER_ITERATIONS: usize = 5;
+/// Weights of the 5 taps of the B3 spline kernel, from the center to the edge
+const KERNEL: [f32; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+/// Higher values keep the edges between surfaces with different normals sharper
+const NORMAL_POWER: f32 = 64.0;
+/// Relative depth difference at which the weight of a neighbour drops to about a third
+const DEPTH_TOLERANCE: f32 = 0.02;
+/// Lowest albedo the colors are divided by, darker channels would amplify the noise too much
+const MIN_DEMODULATION_ALBEDO: f32 = 0.01;
+
+/// Edge-aware à-trous wavelet filter smoothing the noise of the HDR image.
+///
+/// Every iteration blurs the image with a 5x5 kernel whose taps are twice as far apart as in the
+/// previous one. Neighbours facing another direction or at another depth, from
+/// [`crate::renderer::Renderer::normal_data`] and [`crate::renderer::Renderer::depth_data`], get
+/// a lower weight so the edges between objects stay sharp.
+///
+/// The colors are divided by the albedo of [`crate::renderer::Renderer::albedo_data`] before
+/// filtering and multiplied by it again afterward, so only the lighting is blurred and the
+/// textures stay sharp.
+///
+/// Reference:
+/// * Dammertz et al., Edge-Avoiding À-Trous Wavelet Transform for fast Global Illumination Filtering: https://jo.dreggn.org/home/2010_atrous.pdf
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Denoiser {
+    pub enabled: bool,
+    pub iterations: usize,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            iterations: 3,
+        }
+    }
+}
+
+impl Denoiser {
+    /// Filters the HDR colors of the image, including the alpha
+    pub fn apply(
+        &self,
+        hdr: &mut [Vec4],
+        albedos: &[Vec3],
+        normals: &[Vec3],
+        depths: &[f32],
+        width: usize,
+        height: usize,
+    ) {
+        for (color, albedo) in hdr.iter_mut().zip(albedos) {
+            *color /= demodulation_albedo(*albedo).extend(1.0);
+        }
+        for iteration in 0..self.iterations.min(MAX_DENOISER_ITERATIONS) {
+            let step = 1 << iteration;
+            let filtered: Vec<_> = (0..width * height)
+                .into_par_iter()
+                .map(|pixel_index| {
+                    let (x, y) = (pixel_index % width, pixel_index / width);
+                    let mut sum = Vec4::ZERO;
+                    let mut weight_sum = 0.0;
+                    for dy in -2..=2_isize {
+                        let Some(sample_y) = y.checked_add_signed(dy * step) else {
+                            continue;
+                        };
+                        if sample_y >= height {
+                            continue;
+                        }
+                        for dx in -2..=2_isize {
+                            let Some(sample_x) = x.checked_add_signed(dx * step) else {
+                                continue;
+                            };
+                            if sample_x >= width {
+                                continue;
+                            }
+                            let sample_index = sample_y * width + sample_x;
+                            let weight = KERNEL[dx.unsigned_abs()]
+                                * KERNEL[dy.unsigned_abs()]
+                                * edge_weight(
+                                    (normals[pixel_index], depths[pixel_index]),
+                                    (normals[sample_index], depths[sample_index]),
+                                );
+                            sum += hdr[sample_index] * weight;
+                            weight_sum += weight;
+                        }
+                    }
+                    // The edge weight of the center is 1, so its weight is KERNEL[0]² and the
+                    // sum can't be 0
+                    sum / weight_sum
+                })
+                .collect();
+            hdr.copy_from_slice(&filtered);
+        }
+        for (color, albedo) in hdr.iter_mut().zip(albedos) {
+            *color *= demodulation_albedo(*albedo).extend(1.0);
+        }
+    }
+}
+
+/// The albedo the color of a pixel is divided by before filtering.
+/// The background and black materials have no albedo, their color is filtered as is.
+fn demodulation_albedo(albedo: Vec3) -> Vec3 {
+    if albedo == Vec3::ZERO {
+        Vec3::ONE
+    } else {
+        albedo.max(Vec3::splat(MIN_DEMODULATION_ALBEDO))
+    }
+}
+
+/// How much a neighbour with the given normal and depth counts for a pixel, from 0 to 1
+fn edge_weight((normal, depth): (Vec3, f32), (sample_normal, sample_depth): (Vec3, f32)) -> f32 {
+    // The background has no normal and an infinite depth, it's only blended with itself
+    if depth.is_finite() != sample_depth.is_finite() {
+        return 0.0;
+    }
+    if !depth.is_finite() {
+        return 1.0;
+    }
+    let normal_weight = normal.dot(sample_normal).max(0.0).powf(NORMAL_POWER);
+    let depth_weight = (-(depth - sample_depth).abs() / (DEPTH_TOLERANCE * depth.max(1e-3))).exp();
+    normal_weight * depth_weight
+}
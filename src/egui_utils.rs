@@ -126,6 +126,37 @@ pub fn drag_usize(ui: &mut egui::Ui, value: &mut usize, speed: f32, max: usize)
     changed
 }
 
+/// A single line text edit for a file path, kept in the persisted memory of egui so it survives
+/// restarts. The file is only read when the caller's button is clicked, not while it's typed.
+pub fn persisted_path_edit(ui: &mut egui::Ui, id: &str, default: &str) -> String {
+    let id = egui::Id::new(id);
+    let mut path = ui.data_mut(|data| {
+        data.get_persisted_mut_or_insert_with(id, || default.to_string())
+            .clone()
+    });
+    if ui.text_edit_singleline(&mut path).changed() {
+        ui.data_mut(|data| data.insert_persisted(id, path.clone()));
+    }
+    path
+}
+
+/// Drags an index into a list of `len` elements.
+/// The drag is disabled when there's nothing else to choose from.
+pub fn drag_index(ui: &mut egui::Ui, value: &mut usize, len: usize) -> bool {
+    let range = index_range(len);
+    let mut changed = false;
+    ui.add_enabled_ui(range.is_some(), |ui| {
+        let max = range.map_or(0, |range| *range.end());
+        changed = drag_usize(ui, value, 1.0, max);
+    });
+    changed
+}
+
+/// The indices that can be picked in a list of `len` elements, `None` when there's at most one
+fn index_range(len: usize) -> Option<RangeInclusive<usize>> {
+    (len > 1).then(|| 0..=len - 1)
+}
+
 pub fn drag_vec3_color(ui: &mut egui::Ui, value: &mut Vec3) -> bool {
     let mut changed = false;
     let speed = 0.0025;
@@ -180,3 +211,17 @@ pub fn fmt_usize_separator(value: usize) -> String {
     }
     s
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_range_of_short_lists() {
+        // An empty list must not underflow
+        assert_eq!(index_range(0), None);
+        assert_eq!(index_range(1), None);
+        assert_eq!(index_range(2), Some(0..=1));
+        assert_eq!(index_range(5), Some(0..=4));
+    }
+}
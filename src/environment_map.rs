@@ -0,0 +1,120 @@
+use std::{f32::consts::PI, sync::Arc};
+
+use bevy::{
+    math::Vec3A,
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+};
+use futures_lite::future;
+
+use crate::{
+    image_utils::{bilinear, open_image, LinearImage},
+    load_error::{LoadError, LoadErrors},
+    renderer::Renderer,
+    scene::{Scene, SkyMode},
+};
+
+/// An HDR image of the whole environment in equirectangular projection, used as the sky
+#[derive(Debug)]
+pub struct EnvironmentMap {
+    /// The file the pixels were loaded from
+    pub path: String,
+    /// The rows go from the zenith to the nadir
+    image: LinearImage,
+}
+
+impl EnvironmentMap {
+    /// Loads any format supported by the `image` crate, only Radiance HDR and OpenEXR keep the
+    /// values above 1.0
+    pub fn load(path: &str) -> Result<Self, LoadError> {
+        let image = open_image(path)?.into_rgb32f();
+        Ok(Self {
+            path: path.to_string(),
+            image: LinearImage {
+                width: image.width() as usize,
+                height: image.height() as usize,
+                pixels: image.pixels().map(|pixel| Vec3::from(pixel.0)).collect(),
+            },
+        })
+    }
+
+    /// Returns the color seen in a direction, filtered between the 4 closest pixels.
+    /// The top of the image is toward `up` and its center toward -Z when Y is up.
+    ///
+    /// Reference:
+    /// * https://en.wikipedia.org/wiki/Equirectangular_projection
+    pub fn sample(&self, direction: Vec3A, up: Vec3A) -> Vec3 {
+        let direction = Quat::from_rotation_arc(up.into(), Vec3::Y) * Vec3::from(direction);
+        let u = 0.5 + direction.x.atan2(-direction.z) / (2.0 * PI);
+        let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+
+        let image = &self.image;
+        let position = Vec2::new(u * image.width as f32, v * image.height as f32);
+        // The image wraps around horizontally, but not past the poles
+        bilinear(position, |x, y| {
+            image.wrapped(x, y.clamp(0, image.height as isize - 1))
+        })
+    }
+}
+
+/// The environment map being loaded in the background, with the path it's loaded from
+type EnvironmentMapTask = (String, Task<Result<EnvironmentMap, LoadError>>);
+
+/// Loads the environment map of the sky when its path changes, for example after loading a project.
+/// A path that fails to load is only reported once, until the path changes again.
+///
+/// Decoding a large HDR image takes a while, so it's done in the background to keep the app
+/// responsive. The sky uses its previous map, or none, until the new one is ready.
+pub fn sync_environment_map(
+    mut scene: ResMut<Scene>,
+    mut renderer: ResMut<Renderer>,
+    mut load_errors: ResMut<LoadErrors>,
+    mut failed_path: Local<Option<String>>,
+    mut task: Local<Option<EnvironmentMapTask>>,
+) {
+    let sky = &scene.sky;
+    let loaded = sky
+        .environment
+        .as_ref()
+        .is_some_and(|map| map.path == sky.environment_path);
+    if sky.mode != SkyMode::Equirect
+        || loaded
+        || sky.environment_path.is_empty()
+        || failed_path.as_ref() == Some(&sky.environment_path)
+    {
+        // Dropping the task cancels it
+        *task = None;
+        return;
+    }
+
+    let loading = task
+        .as_ref()
+        .is_some_and(|(path, _)| *path == sky.environment_path);
+    if !loading {
+        let path = sky.environment_path.clone();
+        let load = AsyncComputeTaskPool::get().spawn({
+            let path = path.clone();
+            async move { EnvironmentMap::load(&path) }
+        });
+        *task = Some((path, load));
+    }
+
+    let Some((path, load)) = task.as_mut() else {
+        return;
+    };
+    let Some(result) = future::block_on(future::poll_once(load)) else {
+        return;
+    };
+    match result {
+        Ok(map) => {
+            scene.sky.environment = Some(Arc::new(map));
+            *failed_path = None;
+            renderer.reset_frame_index();
+        }
+        Err(err) => {
+            load_errors.push(err);
+            *failed_path = Some(path.clone());
+        }
+    }
+    *task = None;
+}
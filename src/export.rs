@@ -0,0 +1,306 @@
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use arboard::{Clipboard, ImageData};
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+use image::{ImageBuffer, ImageFormat, Luma, Rgba};
+
+use crate::{
+    load_error::{LoadError, LoadErrors},
+    renderer::{Renderer, Tile},
+};
+
+/// Copies the current render to the system clipboard.
+///
+/// This uses the same 8-bit sRGB data that is displayed in the viewport, at render resolution.
+pub fn copy_to_clipboard(renderer: &Renderer) -> Result<(), LoadError> {
+    let bytes: Vec<u8> = renderer.image_data.iter().flat_map(|p| *p).collect();
+    Clipboard::new()
+        .and_then(|mut clipboard| {
+            clipboard.set_image(ImageData {
+                width: renderer.width,
+                height: renderer.height,
+                bytes: Cow::Owned(bytes),
+            })
+        })
+        .map_err(|err| LoadError::Clipboard {
+            message: err.to_string(),
+        })
+}
+
+/// Returns a path in the working directory, named after the current time so it's unique
+fn timestamped_path(extension: &str) -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    PathBuf::from(format!("render_{timestamp}.{extension}"))
+}
+
+fn save_error(path: &Path, message: impl ToString) -> LoadError {
+    LoadError::Save {
+        path: path.display().to_string(),
+        message: message.to_string(),
+    }
+}
+
+fn write_file(path: &Path, data: &[u8]) -> Result<(), LoadError> {
+    fs::write(path, data).map_err(|err| save_error(path, err))
+}
+
+/// Returns the indices of the rows of an image in the order they are written.
+/// The rows of the render go from top to bottom, `flip_y` writes them from bottom to top instead,
+/// like the OpenGL convention.
+fn row_order(height: usize, flip_y: bool) -> impl Iterator<Item = usize> {
+    (0..height).map(move |y| if flip_y { height - 1 - y } else { y })
+}
+
+/// Saves the render as a binary PPM, using the same 8-bit sRGB data that is displayed in the viewport
+///
+/// Reference:
+/// * https://netpbm.sourceforge.net/doc/ppm.html
+pub fn save_ppm(renderer: &Renderer, flip_y: bool) -> Result<(), LoadError> {
+    let mut data = format!("P6\n{} {}\n255\n", renderer.width, renderer.height).into_bytes();
+    for y in row_order(renderer.height, flip_y) {
+        let row = &renderer.image_data[y * renderer.width..(y + 1) * renderer.width];
+        data.extend(row.iter().flat_map(|[r, g, b, _a]| [*r, *g, *b]));
+    }
+    write_file(&timestamped_path("ppm"), &data)
+}
+
+/// Saves the render as an 8-bit RGBA PNG, using the same sRGB data that is displayed in the viewport
+pub fn save_png(renderer: &Renderer, flip_y: bool) -> Result<(), LoadError> {
+    write_png(renderer, flip_y, &timestamped_path("png"))
+}
+
+/// Like [`save_png`] but to a given path, the file is overwritten if it exists.
+/// The format doesn't depend on the extension, so the path can be a temporary name.
+pub fn write_png(renderer: &Renderer, flip_y: bool, path: &Path) -> Result<(), LoadError> {
+    let data: Vec<u8> = row_order(renderer.height, flip_y)
+        .flat_map(|y| &renderer.image_data[y * renderer.width..(y + 1) * renderer.width])
+        .flatten()
+        .copied()
+        .collect();
+    ImageBuffer::<Rgba<u8>, _>::from_raw(renderer.width as u32, renderer.height as u32, data)
+        .ok_or_else(|| save_error(path, "the image doesn't match the size of the render"))?
+        .save_with_format(path, ImageFormat::Png)
+        .map_err(|err| save_error(path, err))
+}
+
+/// Saves the object ID pass as a 16-bit grayscale PNG, the ids above 65535 are clamped.
+/// The ids are the ones of [`crate::scene::Sphere::id`], 0 is the background.
+pub fn save_object_id_png(renderer: &Renderer, flip_y: bool) -> Result<(), LoadError> {
+    let data: Vec<u16> = row_order(renderer.height, flip_y)
+        .flat_map(|y| &renderer.id_data[y * renderer.width..(y + 1) * renderer.width])
+        .map(|id| (*id).min(u16::MAX as u32) as u16)
+        .collect();
+    // Not just .png so it doesn't overwrite a render saved in the same second
+    let path = timestamped_path("ids.png");
+    ImageBuffer::<Luma<u16>, _>::from_raw(renderer.width as u32, renderer.height as u32, data)
+        .ok_or_else(|| {
+            save_error(
+                &path,
+                "the object ID pass doesn't match the size of the render",
+            )
+        })?
+        .save(&path)
+        .map_err(|err| save_error(&path, err))
+}
+
+/// Saves the linear HDR render, before any clamping, as a little-endian PFM
+///
+/// Reference:
+/// * https://www.pauldebevec.com/Research/HDR/PFM/
+pub fn save_pfm(renderer: &Renderer) -> Result<(), LoadError> {
+    let mut data = format!("PF\n{} {}\n-1.0\n", renderer.width, renderer.height).into_bytes();
+    // PFM stores the rows from bottom to top, so they are always flipped
+    for y in row_order(renderer.height, true) {
+        for x in 0..renderer.width {
+            let color = renderer.averaged_pixel(y * renderer.width + x).truncate();
+            for channel in color.to_array() {
+                data.extend(channel.to_le_bytes());
+            }
+        }
+    }
+    write_file(&timestamped_path("pfm"), &data)
+}
+
+/// Saves the linear HDR render, before any clamping or tonemapping, as a 32-bit float RGB OpenEXR
+pub fn save_exr(renderer: &Renderer) -> Result<(), LoadError> {
+    let path = timestamped_path("exr");
+    exr::prelude::write_rgb_file(&path, renderer.width, renderer.height, |x, y| {
+        let color = renderer.averaged_pixel(y * renderer.width + x);
+        (color.x, color.y, color.z)
+    })
+    .map_err(|err| save_error(&path, err))
+}
+
+/// Saves the bucket of the render, or the whole image if there's none, as a 32-bit float RGBA
+/// OpenEXR.
+///
+/// Only the pixels of the bucket are stored. Their offset is the data window of the file, and the
+/// size of the whole image is its display window, like any cropped EXR.
+pub fn write_bucket_exr(renderer: &Renderer, path: &Path) -> Result<(), LoadError> {
+    use exr::prelude::*;
+
+    let bucket = renderer.bucket().unwrap_or(Tile {
+        x: 0,
+        y: 0,
+        width: renderer.width,
+        height: renderer.height,
+    });
+    let layer = Layer::new(
+        (bucket.width, bucket.height),
+        LayerAttributes::default().with_position(Vec2(bucket.x as i32, bucket.y as i32)),
+        Encoding::FAST_LOSSLESS,
+        SpecificChannels::rgba(|position: Vec2<usize>| {
+            let pixel_index = (bucket.y + position.y()) * renderer.width + bucket.x + position.x();
+            let color = renderer.averaged_pixel(pixel_index);
+            (color.x, color.y, color.z, color.w)
+        }),
+    );
+    let display_window = IntegerBounds::new((0, 0), (renderer.width, renderer.height));
+    Image::new(ImageAttributes::new(display_window), layer)
+        .write()
+        .to_file(path)
+        .map_err(|err| save_error(path, err))
+}
+
+/// Puts the buckets saved by [`write_bucket_exr`] back together in a single EXR.
+///
+/// The buckets must come from the same image. The pixels that aren't in any bucket are transparent
+/// black, and the last bucket wins where they overlap.
+pub fn merge_bucket_exrs(buckets: &[PathBuf], output: &Path) -> Result<(), LoadError> {
+    use exr::prelude::*;
+
+    let mut merged: Option<(IntegerBounds, Vec<Vec4>)> = None;
+    for path in buckets {
+        let image = read_first_rgba_layer_from_file(
+            path,
+            |size, _| (size.width(), vec![Vec4::ZERO; size.area()]),
+            |(width, pixels), position, (r, g, b, a): (f32, f32, f32, f32)| {
+                pixels[position.y() * *width + position.x()] = Vec4::new(r, g, b, a);
+            },
+        )
+        .map_err(|err| LoadError::Parse {
+            path: path.display().to_string(),
+            message: err.to_string(),
+        })?;
+
+        let display_window = image.attributes.display_window;
+        let (display_window, pixels) = merged
+            .get_or_insert_with(|| (display_window, vec![Vec4::ZERO; display_window.size.area()]));
+        let layer = &image.layer_data;
+        let bucket = IntegerBounds::new(layer.attributes.layer_position, layer.size);
+        if image.attributes.display_window != *display_window || !display_window.contains(bucket) {
+            return Err(LoadError::Unsupported {
+                path: path.display().to_string(),
+                message: "the bucket isn't part of the same image as the first one".to_string(),
+            });
+        }
+
+        let (_, bucket_pixels) = &layer.channel_data.pixels;
+        let offset = bucket.position - display_window.position;
+        for y in 0..bucket.size.height() {
+            let row = (offset.y() as usize + y) * display_window.size.width() + offset.x() as usize;
+            pixels[row..row + bucket.size.width()].copy_from_slice(
+                &bucket_pixels[y * bucket.size.width()..(y + 1) * bucket.size.width()],
+            );
+        }
+    }
+
+    let Some((display_window, pixels)) = merged else {
+        return Err(save_error(output, "there are no buckets to merge"));
+    };
+    let width = display_window.size.width();
+    Image::from_layer(Layer::new(
+        display_window.size,
+        LayerAttributes::default().with_position(display_window.position),
+        Encoding::FAST_LOSSLESS,
+        SpecificChannels::rgba(|position: Vec2<usize>| {
+            let color = pixels[position.y() * width + position.x()];
+            (color.x, color.y, color.z, color.w)
+        }),
+    ))
+    .write()
+    .to_file(output)
+    .map_err(|err| save_error(output, err))
+}
+
+pub fn copy_to_clipboard_shortcut(
+    mut egui_context: EguiContexts,
+    keyboard_input: Res<Input<KeyCode>>,
+    renderer: Res<Renderer>,
+    mut load_errors: ResMut<LoadErrors>,
+) {
+    // Don't steal the shortcut from text fields
+    if egui_context.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+
+    let ctrl = keyboard_input.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    if ctrl && keyboard_input.just_pressed(KeyCode::C) {
+        if let Err(err) = copy_to_clipboard(&renderer) {
+            load_errors.push(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn row_order_flips_y() {
+        assert_eq!(row_order(3, false).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(row_order(3, true).collect::<Vec<_>>(), [2, 1, 0]);
+        assert_eq!(row_order(0, true).count(), 0);
+    }
+
+    #[test]
+    fn merged_buckets_match_render() {
+        let mut renderer = Renderer::new(8, 6);
+        for pixel_index in 0..8 * 6 {
+            let color = Vec4::new(pixel_index as f32, 0.5, 2.0, 1.0);
+            renderer.accumulation_data.set_sum(pixel_index, color, 1);
+            renderer.sample_counts[pixel_index] = 1;
+        }
+
+        let directory = std::env::temp_dir().join(format!("buckets_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        // Buckets covering the image, with offsets in both directions
+        let buckets = [(0, 0, 3, 6), (3, 0, 5, 2), (3, 2, 5, 4)].map(|(x, y, width, height)| {
+            renderer.set_bucket(Some(Tile {
+                x,
+                y,
+                width,
+                height,
+            }));
+            let path = directory.join(format!("bucket_{x}_{y}.exr"));
+            write_bucket_exr(&renderer, &path).unwrap();
+            path
+        });
+        let merged = directory.join("merged.exr");
+        merge_bucket_exrs(&buckets, &merged).unwrap();
+
+        let image = exr::prelude::read_first_rgba_layer_from_file(
+            &merged,
+            |size, _| (size.width(), vec![Vec4::ZERO; size.area()]),
+            |(width, pixels), position, (r, g, b, a): (f32, f32, f32, f32)| {
+                pixels[position.y() * *width + position.x()] = Vec4::new(r, g, b, a);
+            },
+        )
+        .unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(image.layer_data.size, exr::math::Vec2(8, 6));
+        let (_, pixels) = &image.layer_data.channel_data.pixels;
+        for (pixel_index, pixel) in pixels.iter().enumerate() {
+            assert_eq!(*pixel, renderer.averaged_pixel(pixel_index));
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use bevy::{prelude::*, render::primitives::Aabb};
+use bevy_egui::egui::{self, Color32, Stroke};
+
+use crate::{
+    camera::CustomCamera,
+    renderer::{ray_hits_aabb, PathVertex},
+    scene::{ObjectRef, Scene},
+    selection::Selection,
+};
+
+/// Size in pixels of the axes drawn in the corner of the viewport
+const AXES_SIZE: f32 = 30.0;
+/// Length in pixels of the handles of the translate gizmo
+const HANDLE_LENGTH: f32 = 60.0;
+/// How close to a handle the pointer needs to be to grab it, in pixels
+const HANDLE_GRAB_DISTANCE: f32 = 8.0;
+
+const AXES: [(Vec3, Color32); 3] = [
+    (Vec3::X, Color32::RED),
+    (Vec3::Y, Color32::GREEN),
+    (Vec3::Z, Color32::LIGHT_BLUE),
+];
+
+#[derive(Debug, Resource)]
+pub struct ShowGizmos(pub bool);
+
+impl Default for ShowGizmos {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+/// A single path traced on demand, to understand where the color of a pixel comes from
+#[derive(Debug, Default, Resource)]
+pub struct PathDebug {
+    /// When enabled, clicking the viewport traces a path instead of selecting an object
+    pub enabled: bool,
+    /// Position of the traced pixel in the viewport, from 0 to 1
+    pub uv: Option<Vec2>,
+    pub path: Vec<PathVertex>,
+}
+
+/// Draws the debug path as a polyline going from the traced pixel to every bounce
+pub fn draw_path(painter: &egui::Painter, camera: &CustomCamera, path_debug: &PathDebug) {
+    let Some(uv) = path_debug.uv else {
+        return;
+    };
+    let rect = painter.clip_rect();
+    let mut previous = Some(rect.lerp(egui::vec2(uv.x, uv.y)));
+    for vertex in &path_debug.path {
+        let current = world_to_screen(camera, rect, vertex.position);
+        if let (Some(previous), Some(current)) = (previous, current) {
+            painter.line_segment([previous, current], Stroke::new(1.5, Color32::GOLD));
+        }
+        if let Some(current) = current {
+            let color = match vertex.material_id {
+                Some(_) => Color32::GOLD,
+                None => Color32::LIGHT_BLUE,
+            };
+            painter.circle_filled(current, 3.0, color);
+        }
+        previous = current;
+    }
+}
+
+/// Draws the bounding box of every object over the viewport image.
+/// The boxes hit by the ray going through the center of the viewport are drawn in green.
+pub fn draw_aabbs(painter: &egui::Painter, camera: &CustomCamera, scene: &Scene) {
+    let rect = painter.clip_rect();
+    let spheres = scene.spheres.iter().map(|sphere| {
+        let aabb = Aabb {
+            center: sphere.position.into(),
+            half_extents: Vec3::splat(sphere.radius).into(),
+        };
+        (aabb, Transform::IDENTITY)
+    });
+    let meshes = scene.meshes.iter().map(|mesh| (mesh.aabb, mesh.transform));
+
+    for (aabb, transform) in spheres.chain(meshes) {
+        // The mesh AABBs are in the space of the mesh, so the center ray is brought in that space
+        let to_local = transform.compute_matrix().inverse();
+        let hit = ray_hits_aabb(
+            to_local.transform_point3(camera.position),
+            to_local.transform_vector3(camera.forward_direction),
+            aabb,
+        );
+        let stroke = Stroke::new(1.0, if hit { Color32::GREEN } else { Color32::GRAY });
+
+        let corners: Vec<_> = (0..8)
+            .map(|i| {
+                let corner = Vec3::new(
+                    if i & 1 == 0 { -1.0 } else { 1.0 },
+                    if i & 2 == 0 { -1.0 } else { 1.0 },
+                    if i & 4 == 0 { -1.0 } else { 1.0 },
+                );
+                let local = Vec3::from(aabb.center) + Vec3::from(aabb.half_extents) * corner;
+                world_to_screen(camera, rect, transform.transform_point(local))
+            })
+            .collect();
+        // The edges join the corners that only differ along a single axis
+        for i in 0..8 {
+            for axis in [1, 2, 4] {
+                let j = i | axis;
+                if i == j {
+                    continue;
+                }
+                if let (Some(start), Some(end)) = (corners[i], corners[j]) {
+                    painter.line_segment([start, end], stroke);
+                }
+            }
+        }
+    }
+}
+
+/// Draws the position of the objects over the viewport image,
+/// with the world axes and the direction of the lights in the bottom left corner.
+pub fn draw_gizmos(
+    painter: &egui::Painter,
+    camera: &CustomCamera,
+    scene: &Scene,
+    selection: &Selection,
+) {
+    let rect = painter.clip_rect();
+    let to_screen = |point: Vec3| {
+        let pos = world_to_screen(camera, rect, point)?;
+        rect.contains(pos).then_some(pos)
+    };
+    let color = |object| {
+        if selection.object == Some(object) {
+            Color32::YELLOW
+        } else {
+            Color32::WHITE
+        }
+    };
+
+    for (i, sphere) in scene.spheres.iter().enumerate() {
+        if let Some(pos) = to_screen(sphere.position) {
+            painter.circle_stroke(pos, 4.0, Stroke::new(1.5, color(ObjectRef::Sphere(i))));
+        }
+    }
+    for (i, mesh) in scene.meshes.iter().enumerate() {
+        if let Some(pos) = to_screen(mesh.transform.translation) {
+            let rect = egui::Rect::from_center_size(pos, egui::vec2(8.0, 8.0));
+            painter.rect_stroke(rect, 0.0, Stroke::new(1.5, color(ObjectRef::Mesh(i))));
+        }
+    }
+
+    // The lights are directional so they don't have a position, only show where they come from
+    let origin = rect.left_bottom() + egui::vec2(AXES_SIZE + 10.0, -AXES_SIZE - 10.0);
+    let to_screen_direction = |direction: Vec3| {
+        let direction = camera.view.transform_vector3(direction.normalize_or_zero());
+        egui::vec2(direction.x, -direction.y) * AXES_SIZE
+    };
+    for (axis, color) in AXES {
+        painter.arrow(origin, to_screen_direction(axis), Stroke::new(2.0, color));
+    }
+    for light in &scene.lights {
+        let direction = to_screen_direction(light.direction);
+        painter.circle_filled(origin + direction, 3.0, Color32::GOLD);
+        painter.line_segment(
+            [origin, origin + direction],
+            Stroke::new(1.0, Color32::GOLD),
+        );
+    }
+}
+
+/// Draws a handle for each axis on the selected object.
+/// Dragging a handle with the left button moves the object along its axis.
+///
+/// Returns `true` if the object moved.
+pub fn translate_gizmo(
+    ui: &egui::Ui,
+    response: &egui::Response,
+    camera: &CustomCamera,
+    scene: &mut Scene,
+    selection: &mut Selection,
+) -> bool {
+    let Some(position) = selection
+        .object
+        .and_then(|object| scene.object_position_mut(object))
+    else {
+        return false;
+    };
+    let rect = response.rect;
+    let Some(origin) = world_to_screen(camera, rect, *position) else {
+        return false;
+    };
+
+    // The handles keep the same length on screen, so only the projected direction of each axis matters
+    let world_length = position.distance(camera.position) * 0.1;
+    let handles: Vec<_> = AXES
+        .iter()
+        .filter_map(|(axis, color)| {
+            let end = world_to_screen(camera, rect, *position + *axis * world_length)?;
+            let screen_axis = end - origin;
+            // The axis points toward the camera, it can't be dragged
+            if screen_axis.length() < 1.0 {
+                return None;
+            }
+            let pixels_per_unit = screen_axis.length() / world_length;
+            Some((*axis, *color, screen_axis.normalized(), pixels_per_unit))
+        })
+        .collect();
+
+    if response.drag_started_by(egui::PointerButton::Primary) {
+        let press_origin = ui.input(|i| i.pointer.press_origin());
+        selection.dragged_axis = press_origin.and_then(|press_origin| {
+            handles
+                .iter()
+                .find(|(_, _, direction, _)| {
+                    let end = origin + *direction * HANDLE_LENGTH;
+                    distance_to_segment(press_origin, origin, end) < HANDLE_GRAB_DISTANCE
+                })
+                .map(|(axis, ..)| *axis)
+        });
+    } else if !response.dragged_by(egui::PointerButton::Primary) {
+        selection.dragged_axis = None;
+    }
+
+    let painter = ui.painter_at(rect);
+    for (axis, color, direction, _) in &handles {
+        let width = if selection.dragged_axis == Some(*axis) {
+            4.0
+        } else {
+            2.5
+        };
+        painter.arrow(
+            origin,
+            *direction * HANDLE_LENGTH,
+            Stroke::new(width, *color),
+        );
+    }
+
+    let Some((axis, _, direction, pixels_per_unit)) = handles
+        .iter()
+        .find(|(axis, ..)| selection.dragged_axis == Some(*axis))
+    else {
+        return false;
+    };
+    let distance = response.drag_delta().dot(*direction) / pixels_per_unit;
+    *position += *axis * distance;
+    distance != 0.0
+}
+
+/// Projects a world space point to a position in the viewport image
+fn world_to_screen(camera: &CustomCamera, rect: egui::Rect, point: Vec3) -> Option<egui::Pos2> {
+    let ndc = camera.world_to_ndc(point)?;
+    Some(rect.lerp(egui::vec2((ndc.x + 1.0) / 2.0, (1.0 - ndc.y) / 2.0)))
+}
+
+fn distance_to_segment(point: egui::Pos2, start: egui::Pos2, end: egui::Pos2) -> f32 {
+    let segment = end - start;
+    let t = ((point - start).dot(segment) / segment.length_sq()).clamp(0.0, 1.0);
+    point.distance(start + segment * t)
+}
@@ -0,0 +1,209 @@
+use std::{fs, path::Path};
+
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+};
+use gltf::{buffer::Source, camera::Projection, khr_lights_punctual::Kind, mesh::Mode, Gltf};
+
+use crate::{
+    camera::{CameraProjection, CameraSettings, CustomCamera},
+    load_error::LoadError,
+    scene::{Light, Material, PointLight, Scene, TriangleMesh},
+};
+
+/// Replaces the objects, materials and lights of the scene by the ones of a glTF or glb file, and
+/// moves the camera to the first camera of the file.
+///
+/// The node hierarchy is flattened, every primitive becomes a [`TriangleMesh`] with the world
+/// transform of its node. The parts that can't be represented, like skins, animations or spot
+/// lights, are skipped with a warning.
+pub fn import_gltf(
+    path: &str,
+    scene: &mut Scene,
+    camera: &mut CustomCamera,
+    mesh_assets: &mut Assets<Mesh>,
+) -> Result<(), LoadError> {
+    let gltf = Gltf::open(path).map_err(|err| LoadError::Parse {
+        path: path.to_string(),
+        message: err.to_string(),
+    })?;
+    let buffers = load_buffers(path, &gltf)?;
+    let document = &gltf.document;
+
+    if document.skins().next().is_some() {
+        warn!("{path}: skins aren't supported, the meshes are imported in their bind pose");
+    }
+    if document.animations().next().is_some() {
+        warn!("{path}: animations aren't supported");
+    }
+
+    let mut materials: Vec<_> = document.materials().map(convert_material).collect();
+    // Used by the primitives that don't have a material
+    let default_material = materials.len();
+    materials.push(Material::default());
+
+    let mut meshes = Vec::new();
+    let mut lights = Vec::new();
+    let mut point_lights = Vec::new();
+    let mut camera_settings = None;
+
+    let Some(gltf_scene) = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    else {
+        return Err(LoadError::Unsupported {
+            path: path.to_string(),
+            message: "the file doesn't contain any scene".to_string(),
+        });
+    };
+    let mut nodes: Vec<_> = gltf_scene
+        .nodes()
+        .map(|node| (node, Mat4::IDENTITY))
+        .collect();
+    while let Some((node, parent_transform)) = nodes.pop() {
+        let transform = parent_transform * Mat4::from_cols_array_2d(&node.transform().matrix());
+        nodes.extend(node.children().map(|child| (child, transform)));
+        let (_, rotation, translation) = transform.to_scale_rotation_translation();
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                if primitive.mode() != Mode::Triangles {
+                    warn!("{path}: skipped a primitive of {:?}", primitive.mode());
+                    continue;
+                }
+                let reader =
+                    primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+                let (Some(positions), Some(normals)) =
+                    (reader.read_positions(), reader.read_normals())
+                else {
+                    warn!("{path}: skipped a primitive without positions or normals");
+                    continue;
+                };
+                let positions: Vec<_> = positions.collect();
+                let indices = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect(),
+                    None => (0..positions.len() as u32).collect(),
+                };
+
+                let mut bevy_mesh = Mesh::new(PrimitiveTopology::TriangleList);
+                bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+                bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals.collect::<Vec<_>>());
+                if let Some(uvs) = reader.read_tex_coords(0) {
+                    bevy_mesh
+                        .insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs.into_f32().collect::<Vec<_>>());
+                }
+                bevy_mesh.set_indices(Some(Indices::U32(indices)));
+
+                meshes.push(TriangleMesh::new(
+                    mesh_assets.add(bevy_mesh),
+                    Transform::from_matrix(transform),
+                    primitive.material().index().unwrap_or(default_material),
+                ));
+            }
+        }
+
+        if let Some(light) = node.light() {
+            match light.kind() {
+                Kind::Directional => lights.push(Light {
+                    // The light shines along -Z, the scene stores where it comes from
+                    direction: rotation * Vec3::Z,
+                    intensity: light.intensity(),
+                }),
+                Kind::Point => point_lights.push(PointLight {
+                    position: translation,
+                    color: Vec3::from(light.color()),
+                    intensity: light.intensity(),
+                }),
+                Kind::Spot { .. } => warn!("{path}: spot lights aren't supported"),
+            }
+        }
+
+        if let Some(gltf_camera) = node.camera().filter(|_| camera_settings.is_none()) {
+            match gltf_camera.projection() {
+                Projection::Perspective(perspective) => {
+                    camera_settings = Some((
+                        CameraSettings {
+                            position: translation,
+                            forward_direction: rotation * Vec3::NEG_Z,
+                            vertical_fov: perspective.yfov().to_degrees(),
+                            orthographic_height: None,
+                            // glTF is always Y-up
+                            world_up: Vec3::Y,
+                            // glTF cameras are pinholes
+                            aperture: 0.0,
+                            focus_distance: camera.focus_distance,
+                        },
+                        perspective.znear(),
+                        perspective.zfar(),
+                    ));
+                }
+                Projection::Orthographic(orthographic) => {
+                    let height = 2.0 * orthographic.ymag();
+                    camera_settings = Some((
+                        CameraSettings {
+                            position: translation,
+                            forward_direction: rotation * Vec3::NEG_Z,
+                            vertical_fov: CameraProjection::Orthographic { height }
+                                .vertical_fov_at(camera.focus_distance),
+                            orthographic_height: Some(height),
+                            world_up: Vec3::Y,
+                            aperture: 0.0,
+                            focus_distance: camera.focus_distance,
+                        },
+                        orthographic.znear(),
+                        Some(orthographic.zfar()),
+                    ));
+                }
+            }
+        }
+    }
+
+    scene.materials = materials;
+    scene.spheres.clear();
+    scene.meshes = meshes;
+    scene.lights = lights;
+    scene.point_lights = point_lights;
+    if let Some((settings, near_clip, far_clip)) = camera_settings {
+        camera.apply_settings(&settings);
+        camera.set_clip(near_clip, far_clip.unwrap_or(camera.far_clip()));
+    }
+    Ok(())
+}
+
+/// Reads the binary chunk of a glb and the buffers stored next to a glTF
+fn load_buffers(path: &str, gltf: &Gltf) -> Result<Vec<Vec<u8>>, LoadError> {
+    let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+    gltf.buffers()
+        .map(|buffer| match buffer.source() {
+            Source::Bin => gltf.blob.clone().ok_or_else(|| LoadError::Parse {
+                path: path.to_string(),
+                message: "the binary chunk is missing".to_string(),
+            }),
+            Source::Uri(uri) if uri.starts_with("data:") => Err(LoadError::Unsupported {
+                path: path.to_string(),
+                message: "buffers embedded as data URIs aren't supported".to_string(),
+            }),
+            Source::Uri(uri) => {
+                let buffer_path = directory.join(uri);
+                fs::read(&buffer_path).map_err(|error| LoadError::Io {
+                    path: buffer_path.display().to_string(),
+                    error,
+                })
+            }
+        })
+        .collect()
+}
+
+fn convert_material(material: gltf::Material) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let emissive = Vec3::from(material.emissive_factor());
+    Material {
+        albedo: Vec4::from(pbr.base_color_factor()).truncate(),
+        roughness: pbr.roughness_factor(),
+        metallic: pbr.metallic_factor(),
+        emissive_color: emissive,
+        emissive_power: if emissive == Vec3::ZERO { 0.0 } else { 1.0 },
+        ..default()
+    }
+}
@@ -0,0 +1,79 @@
+use std::{
+    fmt,
+    ops::{Add, Mul},
+};
+
+use bevy::prelude::*;
+use image::DynamicImage;
+
+use crate::load_error::LoadError;
+
+/// Opens any format supported by the `image` crate, images without pixels are rejected since
+/// they can't be sampled
+pub fn open_image(path: &str) -> Result<DynamicImage, LoadError> {
+    let image = image::open(path).map_err(|err| match err {
+        image::ImageError::IoError(error) => LoadError::Io {
+            path: path.to_string(),
+            error,
+        },
+        err => LoadError::Parse {
+            path: path.to_string(),
+            message: err.to_string(),
+        },
+    })?;
+    if image.width() == 0 || image.height() == 0 {
+        return Err(LoadError::Unsupported {
+            path: path.to_string(),
+            message: "the image is empty".to_string(),
+        });
+    }
+    Ok(image)
+}
+
+/// Linear colors of an image, row by row from the top
+pub struct LinearImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+impl fmt::Debug for LinearImage {
+    // The pixels would flood the output
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LinearImage")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LinearImage {
+    /// The pixel at a column and a row, the image repeats in both directions
+    pub fn wrapped(&self, x: isize, y: isize) -> Vec3 {
+        let x = x.rem_euclid(self.width as isize) as usize;
+        let y = y.rem_euclid(self.height as isize) as usize;
+        self.pixels[y * self.width + x]
+    }
+}
+
+/// Interpolates the 4 pixels closest to `position`, in pixels from the top left corner of the
+/// image.
+///
+/// `pixel` returns the value of a pixel from its column and row. They can be outside of the image,
+/// the caller decides if it repeats or is clamped at the edges.
+pub fn bilinear<T>(position: Vec2, pixel: impl Fn(isize, isize) -> T) -> T
+where
+    T: Add<Output = T> + Mul<f32, Output = T>,
+{
+    // Pixel centers are at half coordinates
+    let position = position - 0.5;
+    let corner = position.floor();
+    let t = position - corner;
+    let (x0, y0) = (corner.x as isize, corner.y as isize);
+    let lerp = |a: T, b: T, t: f32| a * (1.0 - t) + b * t;
+    lerp(
+        lerp(pixel(x0, y0), pixel(x0 + 1, y0), t.x),
+        lerp(pixel(x0, y0 + 1), pixel(x0 + 1, y0 + 1), t.x),
+        t.y,
+    )
+}
@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+
+/// Number of ghosts generated by each bright pixel
+const GHOST_COUNT: usize = 4;
+/// Spacing between the ghosts along the vector going through the center of the image
+const GHOST_DISPERSAL: f32 = 0.35;
+
+/// Screen space lens flare ghosts generated from the bright parts of the HDR image.
+///
+/// The bright parts are mirrored through the center of the image and repeated along the vector
+/// going toward the center, like the reflections between the elements of a camera lens.
+///
+/// Reference:
+/// * John Chapman, Pseudo Lens Flare: https://john-chapman-graphics.blogspot.com/2013/02/pseudo-lens-flare.html
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LensFlare {
+    pub enabled: bool,
+    pub intensity: f32,
+    /// Only the part of a color above this generates ghosts
+    pub threshold: f32,
+}
+
+impl Default for LensFlare {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            intensity: 0.1,
+            threshold: 1.0,
+        }
+    }
+}
+
+impl LensFlare {
+    /// Adds the ghosts to the HDR colors of the image, the alpha isn't affected
+    pub fn apply(&self, hdr: &mut [Vec4], width: usize, height: usize) {
+        let bright: Vec<_> = hdr
+            .iter()
+            .map(|color| (color.truncate() - Vec3::splat(self.threshold)).max(Vec3::ZERO))
+            .collect();
+        if bright.iter().all(|color| *color == Vec3::ZERO) {
+            return;
+        }
+
+        let size = Vec2::new(width as f32, height as f32);
+        let center = Vec2::splat(0.5);
+        let max_distance = center.length();
+        let ghosts: Vec<_> = (0..width * height)
+            .into_par_iter()
+            .map(|pixel_index| {
+                let pixel = Vec2::new((pixel_index % width) as f32, (pixel_index / width) as f32);
+                let uv = Vec2::ONE - (pixel + 0.5) / size;
+                let ghost_vector = (center - uv) * GHOST_DISPERSAL;
+
+                let mut color = Vec3::ZERO;
+                for i in 0..GHOST_COUNT {
+                    let sample_uv = uv + ghost_vector * i as f32;
+                    if sample_uv.cmplt(Vec2::ZERO).any() || sample_uv.cmpge(Vec2::ONE).any() {
+                        continue;
+                    }
+                    // Only the ghosts of the bright spots close to the center are visible
+                    let weight = (1.0 - (center - sample_uv).length() / max_distance).powi(10);
+                    let sample = (sample_uv * size).as_uvec2();
+                    color += bright[sample.y as usize * width + sample.x as usize] * weight;
+                }
+                color * self.intensity
+            })
+            .collect();
+
+        for (color, ghost) in hdr.iter_mut().zip(ghosts) {
+            *color += ghost.extend(0.0);
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! A CPU path tracer. The interactive app is in `main.rs`, the renderer itself can be driven
+//! without it through [`renderer::Renderer::render_to`], for example from a test or another binary.
+
+pub mod accumulation;
+pub mod bvh;
+pub mod camera;
+pub mod denoiser;
+pub mod egui_utils;
+pub mod environment_map;
+pub mod export;
+pub mod gizmos;
+pub mod gltf_import;
+pub mod image_utils;
+pub mod lens_flare;
+pub mod load_error;
+pub mod math_utils;
+pub mod obj_import;
+pub mod project;
+pub mod random;
+pub mod renderer;
+pub mod scene;
+pub mod selection;
+pub mod spherical_harmonics;
+pub mod texture;
+pub mod ui;
+
+use std::time::{Duration, Instant};
+
+use bevy::{
+    prelude::*,
+    render::render_resource::{
+        Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+    },
+};
+use bevy_egui::{egui::TextureId, EguiContexts};
+use camera::CustomCamera;
+use image_utils::bilinear;
+use math_utils::{linear_to_srgb, srgb_to_linear};
+use renderer::Renderer;
+use scene::Scene;
+
+/// Time spent rendering tiles on each app frame, a render frame that takes longer is spread over
+/// several app frames so the UI stays responsive
+const RENDER_TIME_BUDGET: Duration = Duration::from_millis(30);
+
+#[derive(Resource)]
+pub struct ViewportImage(pub Handle<Image>);
+#[derive(Resource)]
+pub struct ViewportEguiTexture(pub TextureId);
+#[derive(Resource)]
+pub struct ViewportSize(pub Vec2);
+
+impl ViewportSize {
+    /// The viewport has no area, for example when its dock tab is collapsed
+    pub fn is_empty(&self) -> bool {
+        self.0.x < 1.0 || self.0.y < 1.0
+    }
+}
+#[derive(Debug, Default, Resource)]
+pub struct Frametimes {
+    render: f32,
+    image_copy: f32,
+}
+
+#[derive(Resource)]
+pub struct RenderScale {
+    /// Size of the render relative to the viewport
+    pub scale: f32,
+    /// Upscales a scaled down render to the viewport size with a bilinear filter on the CPU,
+    /// instead of relying on the sampler of the texture to stretch it
+    pub bilinear_upscale: bool,
+}
+
+pub fn setup_renderer(
+    mut commands: Commands,
+    mut egui_ctx: EguiContexts,
+    mut images: ResMut<Assets<Image>>,
+    mut camera: ResMut<CustomCamera>,
+) {
+    let size = Extent3d {
+        width: 512,
+        height: 512,
+        ..default()
+    };
+
+    // This is the texture that will be rendered to.
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: None,
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        ..default()
+    };
+
+    // fill image.data with zeroes
+    image.resize(size);
+
+    let image_handle = images.add(image);
+    commands.insert_resource(ViewportImage(image_handle.clone()));
+    commands.insert_resource(ViewportEguiTexture(egui_ctx.add_image(image_handle)));
+    commands.insert_resource(ViewportSize(Vec2::new(
+        size.width as f32,
+        size.height as f32,
+    )));
+
+    camera.resize(size.width, size.height);
+    commands.insert_resource(Renderer::new(size.width as usize, size.height as usize));
+}
+
+pub fn resize_image(
+    viewport_image: Res<ViewportImage>,
+    viewport_size: Res<ViewportSize>,
+    mut images: ResMut<Assets<Image>>,
+    mut renderer: ResMut<Renderer>,
+    mut camera: ResMut<CustomCamera>,
+    render_scale: Res<RenderScale>,
+) {
+    // The viewport tab is collapsed, keep the current image until it's visible again
+    if viewport_size.is_empty() {
+        return;
+    }
+
+    let size = Extent3d {
+        width: ((viewport_size.0.x * render_scale.scale) as u32).max(1),
+        height: ((viewport_size.0.y * render_scale.scale) as u32).max(1),
+        ..default()
+    };
+    let image_size = if render_scale.bilinear_upscale && render_scale.scale < 1.0 {
+        Extent3d {
+            width: (viewport_size.0.x as u32).max(1),
+            height: (viewport_size.0.y as u32).max(1),
+            ..default()
+        }
+    } else {
+        size
+    };
+
+    let image = images.get_mut(&viewport_image.0).unwrap();
+    if image.texture_descriptor.size != image_size {
+        // This also clears the image with 0
+        image.resize(image_size);
+    }
+
+    // The viewport size can change by a fraction of a pixel without changing the actual image size.
+    // Resizing resets the accumulation so only do it when it's really needed.
+    if size.width as usize == renderer.width && size.height as usize == renderer.height {
+        return;
+    }
+
+    camera.resize(size.width, size.height);
+    renderer.resize(size.width as usize, size.height as usize);
+}
+
+pub fn render(
+    viewport_image: Res<ViewportImage>,
+    mut images: ResMut<Assets<Image>>,
+    mut renderer: ResMut<Renderer>,
+    mut frametimes: ResMut<Frametimes>,
+    camera: Res<CustomCamera>,
+    scene: Res<Scene>,
+    viewport_size: Res<ViewportSize>,
+) {
+    if viewport_size.is_empty() {
+        return;
+    }
+
+    // TODO use diagnostic system
+    let start = Instant::now();
+    let (width, height) = (renderer.width, renderer.height);
+    {
+        let _span = info_span!("render").entered();
+        renderer.render_until(&camera, &scene, start + RENDER_TIME_BUDGET);
+    }
+    let image_data = &renderer.image_data;
+    frametimes.render = start.elapsed().as_secs_f32();
+
+    let start = Instant::now();
+    let image = images.get_mut(&viewport_image.0).unwrap();
+    {
+        let _span = info_span!("image copy").entered();
+        let size = image.texture_descriptor.size;
+        image.data = if (size.width as usize, size.height as usize) == (width, height) {
+            image_data.iter().flat_map(|p| *p).collect()
+        } else {
+            bilinear_upscale(
+                image_data,
+                (width, height),
+                (size.width as usize, size.height as usize),
+            )
+        };
+    }
+    frametimes.image_copy = start.elapsed().as_secs_f32();
+}
+
+/// Steps of the table encoding the interpolated colors back to sRGB, enough for the dark values
+/// to be within one 8-bit step of the exact encoding
+const SRGB_ENCODE_STEPS: usize = 4096;
+
+/// Resamples the pixels of an image to a bigger size, interpolating the 4 closest source pixels.
+///
+/// The pixels are sRGB like the viewport texture, so they are interpolated in linear space.
+/// Interpolating the encoded values would darken the edges between bright and dark pixels.
+fn bilinear_upscale(
+    pixels: &[[u8; 4]],
+    (src_width, src_height): (usize, usize),
+    (dst_width, dst_height): (usize, usize),
+) -> Vec<u8> {
+    // This runs on every pixel of the viewport, so the transfer functions are tabulated
+    let decode: [f32; 256] = std::array::from_fn(|i| srgb_to_linear(i as f32 / 255.0));
+    let encode: Vec<u8> = (0..=SRGB_ENCODE_STEPS)
+        .map(|i| (linear_to_srgb(i as f32 / SRGB_ENCODE_STEPS as f32) * 255.0).round() as u8)
+        .collect();
+    let encode = |value: f32| encode[(value * SRGB_ENCODE_STEPS as f32).round() as usize];
+
+    let scale = Vec2::new(
+        src_width as f32 / dst_width as f32,
+        src_height as f32 / dst_height as f32,
+    );
+    // The edges are clamped instead of wrapping around
+    let pixel = |x: isize, y: isize| {
+        let x = x.clamp(0, src_width as isize - 1) as usize;
+        let y = y.clamp(0, src_height as isize - 1) as usize;
+        let [r, g, b, a] = pixels[y * src_width + x];
+        // Alpha isn't encoded
+        Vec4::new(
+            decode[r as usize],
+            decode[g as usize],
+            decode[b as usize],
+            a as f32 / 255.0,
+        )
+    };
+    let mut data = Vec::with_capacity(dst_width * dst_height * 4);
+    for y in 0..dst_height {
+        for x in 0..dst_width {
+            // The centers of the pixels line up in both images
+            let src = (Vec2::new(x as f32, y as f32) + 0.5) * scale;
+            let color = bilinear(src, pixel).clamp(Vec4::ZERO, Vec4::ONE);
+            data.extend([
+                encode(color.x),
+                encode(color.y),
+                encode(color.z),
+                (color.w * 255.0).round() as u8,
+            ]);
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bilinear_upscale_keeps_flat_colors() {
+        // A flat image interpolates to itself, so this checks the sRGB tables round trip
+        for value in 0..=255 {
+            let pixels = [[value, value, value, value]; 4];
+            let upscaled = bilinear_upscale(&pixels, (2, 2), (5, 5));
+            assert!(upscaled.iter().all(|channel| *channel == value), "{value}");
+        }
+    }
+
+    #[test]
+    fn viewport_without_area_is_empty() {
+        assert!(ViewportSize(Vec2::new(64.0, 0.0)).is_empty());
+        assert!(ViewportSize(Vec2::new(0.0, 64.0)).is_empty());
+        assert!(!ViewportSize(Vec2::new(64.0, 1.0)).is_empty());
+    }
+}
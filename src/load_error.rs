@@ -0,0 +1,102 @@
+use std::{fmt, fs, io, time::Instant};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use ron::ser::PrettyConfig;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// How long an error stays on screen, in seconds
+const TOAST_DURATION: f32 = 8.0;
+/// Older errors are dropped once there are more than this
+const MAX_RECENT_ERRORS: usize = 5;
+
+/// Why a file couldn't be loaded or saved
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file couldn't be read
+    Io { path: String, error: io::Error },
+    /// The file was read but its content is malformed
+    Parse { path: String, message: String },
+    /// The file is valid but uses something the renderer doesn't handle
+    Unsupported { path: String, message: String },
+    /// The asset server failed to load the file, the reason is only in the log
+    Asset { path: String },
+    /// The file couldn't be written
+    Save { path: String, message: String },
+    /// The render couldn't be copied to the clipboard
+    Clipboard { message: String },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "Failed to read {path}: {error}"),
+            Self::Parse { path, message } => write!(f, "Failed to parse {path}: {message}"),
+            Self::Unsupported { path, message } => write!(f, "Can't use {path}: {message}"),
+            Self::Asset { path } => write!(f, "Failed to load {path}, see the log for details"),
+            Self::Save { path, message } => write!(f, "Failed to save {path}: {message}"),
+            Self::Clipboard { message } => write!(f, "Failed to copy to clipboard: {message}"),
+        }
+    }
+}
+
+/// Reads and parses a RON file
+pub fn read_ron<T: DeserializeOwned>(path: &str) -> Result<T, LoadError> {
+    let ron = fs::read_to_string(path).map_err(|error| LoadError::Io {
+        path: path.to_string(),
+        error,
+    })?;
+    ron::from_str(&ron).map_err(|err| LoadError::Parse {
+        path: path.to_string(),
+        message: err.to_string(),
+    })
+}
+
+/// Serializes a value and writes it to a RON file
+pub fn write_ron<T: Serialize>(path: &str, value: &T) -> Result<(), LoadError> {
+    let save_error = |message: String| LoadError::Save {
+        path: path.to_string(),
+        message,
+    };
+    let ron = ron::ser::to_string_pretty(value, PrettyConfig::default())
+        .map_err(|err| save_error(err.to_string()))?;
+    fs::write(path, ron).map_err(|err| save_error(err.to_string()))
+}
+
+/// The last load and save errors, shown for a few seconds in the corner of the window.
+///
+/// Loading a file the user picked shouldn't crash the app, so loaders report here instead of
+/// panicking. Saving and exporting report here too, so every error shows up in the same place.
+#[derive(Debug, Default, Resource)]
+pub struct LoadErrors(Vec<(LoadError, Instant)>);
+
+impl LoadErrors {
+    pub fn push(&mut self, error: LoadError) {
+        warn!("{error}");
+        self.0.push((error, Instant::now()));
+        if self.0.len() > MAX_RECENT_ERRORS {
+            self.0.remove(0);
+        }
+    }
+}
+
+pub fn show_load_errors(mut egui_context: EguiContexts, mut load_errors: ResMut<LoadErrors>) {
+    load_errors
+        .0
+        .retain(|(_, time)| time.elapsed().as_secs_f32() < TOAST_DURATION);
+    if load_errors.0.is_empty() {
+        return;
+    }
+
+    egui::Area::new("load_errors")
+        .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-8.0, -8.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                for (error, _) in &load_errors.0 {
+                    ui.colored_label(egui::Color32::RED, error.to_string());
+                }
+            });
+        });
+    // Keep repainting so the toast disappears even if nothing else happens
+    egui_context.ctx_mut().request_repaint();
+}
@@ -1,43 +1,21 @@
-mod camera;
-mod egui_utils;
-mod math_utils;
-mod random;
-mod renderer;
-mod scene;
-mod ui;
-
-use std::time::Instant;
-
-use bevy::{
-    diagnostic::FrameTimeDiagnosticsPlugin,
-    math::vec3,
-    prelude::*,
-    render::render_resource::{
-        Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
-    },
-    window::PresentMode,
+use bevy::{diagnostic::FrameTimeDiagnosticsPlugin, math::vec3, prelude::*, window::PresentMode};
+use bevy_egui::EguiPlugin;
+use bevy_tracing::{
+    bvh::{build_mesh_bvhs, BvhBuildTasks},
+    camera::{update_camera, CameraBookmarks, CustomCamera},
+    environment_map::sync_environment_map,
+    export::copy_to_clipboard_shortcut,
+    gizmos::{PathDebug, ShowGizmos},
+    load_error::{show_load_errors, LoadErrors},
+    project::{ProjectPath, Scenes},
+    render, resize_image,
+    scene::{sync_mesh_assets, Fog, Material, Scene, Sky, Sphere},
+    selection::{nudge_selection, Selection},
+    setup_renderer,
+    texture::sync_textures,
+    ui::{draw_dock_area, save_dock_layout, setup_ui},
+    Frametimes, RenderScale,
 };
-use bevy_egui::{egui::TextureId, EguiContexts, EguiPlugin};
-use camera::{update_camera, CustomCamera};
-
-use renderer::Renderer;
-use scene::{Light, Material, Scene, Sky, Sphere};
-use ui::{draw_dock_area, setup_ui};
-
-#[derive(Resource)]
-struct ViewportImage(Handle<Image>);
-#[derive(Resource)]
-pub struct ViewportEguiTexture(pub TextureId);
-#[derive(Resource)]
-pub struct ViewportSize(pub Vec2);
-#[derive(Debug, Default, Resource)]
-pub struct Frametimes {
-    render: f32,
-    image_copy: f32,
-}
-
-#[derive(Resource)]
-pub struct RenderScale(pub f32);
 
 fn main() {
     App::new()
@@ -54,7 +32,18 @@ fn main() {
         .add_plugin(EguiPlugin)
         .add_plugin(FrameTimeDiagnosticsPlugin)
         .init_resource::<Frametimes>()
-        .insert_resource(RenderScale(0.75))
+        .init_resource::<LoadErrors>()
+        .init_resource::<ProjectPath>()
+        .init_resource::<Scenes>()
+        .init_resource::<Selection>()
+        .init_resource::<ShowGizmos>()
+        .init_resource::<PathDebug>()
+        .init_resource::<CameraBookmarks>()
+        .init_resource::<BvhBuildTasks>()
+        .insert_resource(RenderScale {
+            scale: 0.75,
+            bilinear_upscale: false,
+        })
         .insert_resource(CustomCamera::new(45.0, 0.1, 100.0))
         // TODO use bevy scene feature
         .insert_resource(Scene {
@@ -62,12 +51,16 @@ fn main() {
                 zenith_color: vec3(0.6, 0.7, 0.9),
                 horizon_color: Vec3::ONE,
                 ground_color: vec3(0.7, 0.7, 0.7),
+                sun_direction: vec3(1.0, 1.0, 1.0),
+                sun_intensity: 20.0,
+                ..default()
             },
             // sky: Sky::BLACK,
-            lights: vec![Light {
-                direction: vec3(1.0, 1.0, 1.0),
-                intensity: 0.5,
-            }],
+            fog: Fog::default(),
+            // The sun of the sky already lights the scene
+            lights: Vec::new(),
+            point_lights: Vec::new(),
+            textures: Vec::new(),
             materials: vec![
                 Material {
                     albedo: vec3(1.0, 0.0, 1.0),
@@ -87,7 +80,6 @@ fn main() {
                 Material {
                     albedo: vec3(0.0, 1.0, 0.0),
                     roughness: 1.0,
-                    specular: 2.0,
                     ..default()
                 },
                 Material {
@@ -117,47 +109,58 @@ fn main() {
                     position: vec3(0.0, -201.0, 0.0),
                     radius: 200.0,
                     material_id: 5,
+                    ..default()
                 },
                 Sphere {
                     position: vec3(-1.25, -0.5, 0.0),
                     radius: 0.5,
                     material_id: 2,
+                    ..default()
                 },
                 Sphere {
                     position: vec3(0.0, -0.5, 0.0),
                     radius: 0.5,
                     material_id: 3,
+                    ..default()
                 },
                 Sphere {
                     position: vec3(1.25, -0.5, 0.0),
                     radius: 0.5,
                     material_id: 4,
+                    ..default()
                 },
                 Sphere {
                     position: vec3(-10.0, 10.0, 10.0),
                     radius: 10.0,
                     material_id: 6,
+                    ..default()
                 },
             ],
             meshes: vec![
-            //     {
-            //     let mesh: Mesh = Cube { size: 1.0 }.into();
-            //     let aabb = mesh.compute_aabb().unwrap();
-            //     TriangleMesh {
-            //         transform: Transform::from_xyz(0.0, 0.0, 0.0),
-            //         mesh,
-            //         material_id: 0,
-            //         aabb,
-            //     }
-            // }
+            // Meshes are loaded through the AssetServer, for example:
+            // TriangleMesh::new(
+            //     asset_server.load("models/cube.glb#Mesh0/Primitive0"),
+            //     Transform::from_xyz(0.0, 0.0, 0.0),
+            //     0,
+            // )
             ],
         })
         .add_startup_system(setup_renderer)
         .add_startup_system(setup_ui)
         .add_system(draw_dock_area)
         .add_system(resize_image.after(draw_dock_area))
-        .add_system(render.after(resize_image))
+        .add_system(show_load_errors.after(draw_dock_area))
+        .add_system(sync_mesh_assets)
+        .add_system(sync_environment_map)
+        .add_system(sync_textures)
+        .add_system(build_mesh_bvhs.after(sync_mesh_assets))
+        // The inverse transforms of the meshes are up to date when rendering
+        .add_system(render.after(resize_image).after(build_mesh_bvhs))
         .add_system(update_camera)
+        .add_system(copy_to_clipboard_shortcut)
+        .add_system(nudge_selection)
+        // The window is closed in PostUpdate, this needs to see the exit event of the same frame
+        .add_system(save_dock_layout.in_base_set(CoreSet::Last))
         // .add_system(show_profiler)
         .run();
 }
@@ -172,100 +175,3 @@ fn main() {
 //         show.0 = puffin_egui::profiler_window(ctx);
 //     }
 // }
-
-fn setup_renderer(
-    mut commands: Commands,
-    mut egui_ctx: EguiContexts,
-    mut images: ResMut<Assets<Image>>,
-) {
-    let size = Extent3d {
-        width: 512,
-        height: 512,
-        ..default()
-    };
-
-    // This is the texture that will be rendered to.
-    let mut image = Image {
-        texture_descriptor: TextureDescriptor {
-            label: None,
-            size,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::Rgba8UnormSrgb,
-            mip_level_count: 1,
-            sample_count: 1,
-            usage: TextureUsages::TEXTURE_BINDING
-                | TextureUsages::COPY_DST
-                | TextureUsages::RENDER_ATTACHMENT,
-            view_formats: &[],
-        },
-        ..default()
-    };
-
-    // fill image.data with zeroes
-    image.resize(size);
-
-    let image_handle = images.add(image);
-    commands.insert_resource(ViewportImage(image_handle.clone()));
-    commands.insert_resource(ViewportEguiTexture(egui_ctx.add_image(image_handle)));
-    commands.insert_resource(ViewportSize(Vec2::new(
-        size.width as f32,
-        size.height as f32,
-    )));
-
-    commands.insert_resource(Renderer::new(size.width as usize, size.height as usize));
-}
-
-#[allow(clippy::too_many_arguments)]
-fn resize_image(
-    viewport_image: Res<ViewportImage>,
-    viewport_size: Res<ViewportSize>,
-    mut images: ResMut<Assets<Image>>,
-    mut renderer: ResMut<Renderer>,
-    mut camera: ResMut<CustomCamera>,
-    render_scale: Res<RenderScale>,
-    mut prev_scale: Local<f32>,
-    mut prev_viewport_size: Local<Vec2>,
-) {
-    let image = images.get_mut(&viewport_image.0).unwrap();
-    if viewport_size.0 != *prev_viewport_size || render_scale.0 != *prev_scale {
-        let size = Extent3d {
-            width: (viewport_size.0.x * render_scale.0) as u32,
-            height: (viewport_size.0.y * render_scale.0) as u32,
-            ..default()
-        };
-
-        // This also clears the image with 0
-        image.resize(size);
-
-        camera.resize(size.width, size.height);
-        renderer.resize(size.width as usize, size.height as usize);
-    }
-
-    *prev_scale = render_scale.0;
-    *prev_viewport_size = viewport_size.0;
-}
-
-fn render(
-    viewport_image: Res<ViewportImage>,
-    mut images: ResMut<Assets<Image>>,
-    mut renderer: ResMut<Renderer>,
-    mut frametimes: ResMut<Frametimes>,
-    camera: Res<CustomCamera>,
-    scene: Res<Scene>,
-) {
-    // TODO use diagnostic system
-    let start = Instant::now();
-    {
-        let _span = info_span!("render").entered();
-        renderer.render(&camera, &scene);
-    }
-    frametimes.render = start.elapsed().as_secs_f32();
-
-    let start = Instant::now();
-    let image = images.get_mut(&viewport_image.0).unwrap();
-    {
-        let _span = info_span!("image copy").entered();
-        image.data = renderer.image_data.iter().flat_map(|p| *p).collect();
-    }
-    frametimes.image_copy = start.elapsed().as_secs_f32();
-}
@@ -1,11 +1,61 @@
-use bevy::math::Vec3A;
+use std::f32::consts::PI;
+
+use bevy::math::{Vec3, Vec3A};
+
+/// Schlick's approximation of the Fresnel reflectance of a dielectric
+///
+/// Reference:
+/// * https://en.wikipedia.org/wiki/Schlick%27s_approximation
+pub fn fresnel_schlick(cos_theta: f32, f0: f32) -> f32 {
+    f0 + (1.0 - f0) * (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+}
 
 // For the incident vector I and surface orientation N, returns the reflection direction
-#[allow(unused)]
 pub fn reflect(i: Vec3A, n: Vec3A) -> Vec3A {
     i - 2.0 * n.dot(i) * n
 }
 
+/// For the incident vector I, the surface normal N facing I and the ratio of indices of refraction
+/// eta, returns the refraction direction, or `None` on total internal reflection.
+///
+/// Reference:
+/// * https://registry.khronos.org/OpenGL-Refpages/gl4/html/refract.xhtml
+pub fn refract(i: Vec3A, n: Vec3A, eta: f32) -> Option<Vec3A> {
+    let cos_i = -n.dot(i);
+    let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+    if k < 0.0 {
+        return None;
+    }
+    Some(eta * i + (eta * cos_i - k.sqrt()) * n)
+}
+
+/// Returns a tangent and a bitangent perpendicular to the normal.
+/// The tangent is the projection of `tangent_hint` on the surface when possible.
+pub fn tangent_frame(normal: Vec3A, tangent_hint: Vec3A) -> (Vec3A, Vec3A) {
+    let tangent = tangent_hint - normal * normal.dot(tangent_hint);
+    let tangent = if tangent.length_squared() > 1e-6 {
+        tangent.normalize()
+    } else {
+        // The hint is parallel to the normal so any tangent works
+        normal.any_orthonormal_vector()
+    };
+    (tangent, normal.cross(tangent))
+}
+
+/// Weight of a sample drawn with `pdf` when another strategy could have drawn the same direction
+/// with `other_pdf`. The weights of both strategies add up to 1, so their samples can be summed.
+///
+/// Reference:
+/// * https://www.pbr-book.org/3ed-2018/Monte_Carlo_Integration/Importance_Sampling#MultipleImportanceSampling
+pub fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
 pub fn smoothstep(edge0: f32, edge1: f32, t: f32) -> f32 {
     if t < edge0 {
         return 0.0;
@@ -16,3 +66,67 @@ pub fn smoothstep(edge0: f32, edge1: f32, t: f32) -> f32 {
     let t = (t - edge0) / (edge1 - edge0);
     t * t * (3.0 - 2.0 * t)
 }
+
+/// Decodes a color channel encoded with the sRGB transfer function, the inverse of
+/// [`linear_to_srgb`]
+///
+/// Reference:
+/// * https://en.wikipedia.org/wiki/SRGB#From_sRGB_to_CIE_XYZ
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a linear color channel with the sRGB transfer function
+///
+/// Reference:
+/// * https://en.wikipedia.org/wiki/SRGB#From_CIE_XYZ_to_sRGB
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Wavelengths in nanometers used to evaluate the red, green and blue channels of spectral effects
+const RGB_WAVELENGTHS: [f32; 3] = [650.0, 510.0, 475.0];
+
+/// Reflectance of a thin film between the air and a substrate, for the red, green and blue channels.
+/// The light reflected on both sides of the film interferes, which gives the colors of soap bubbles.
+///
+/// Reference:
+/// * https://en.wikipedia.org/wiki/Thin-film_interference
+/// * Belcour and Barla, A Practical Extension to Microfacet Theory for the Modeling of Varying Iridescence
+pub fn thin_film_reflectance(
+    cos_theta: f32,
+    film_thickness: f32,
+    film_ior: f32,
+    substrate_ior: f32,
+) -> Vec3 {
+    let cos_1 = cos_theta.clamp(0.0, 1.0);
+    let sin_1_squared = 1.0 - cos_1 * cos_1;
+    let cos_t = |ior: f32| (1.0 - sin_1_squared / (ior * ior)).max(0.0).sqrt();
+    let cos_2 = cos_t(film_ior);
+    let cos_3 = cos_t(substrate_ior);
+
+    // Fresnel amplitude coefficients at the top and bottom of the film, for both polarizations
+    let (n1, n2, n3) = (1.0, film_ior, substrate_ior);
+    let r12_s = (n1 * cos_1 - n2 * cos_2) / (n1 * cos_1 + n2 * cos_2);
+    let r12_p = (n2 * cos_1 - n1 * cos_2) / (n2 * cos_1 + n1 * cos_2);
+    let r23_s = (n2 * cos_2 - n3 * cos_3) / (n2 * cos_2 + n3 * cos_3);
+    let r23_p = (n3 * cos_2 - n2 * cos_3) / (n3 * cos_2 + n2 * cos_3);
+
+    // Sum of all the reflections inside the film
+    let airy = |r12: f32, r23: f32, phase: f32| {
+        let interference = 2.0 * r12 * r23 * phase.cos();
+        (r12 * r12 + r23 * r23 + interference) / (1.0 + r12 * r12 * r23 * r23 + interference)
+    };
+    Vec3::from_array(RGB_WAVELENGTHS.map(|wavelength| {
+        let phase = 4.0 * PI * n2 * film_thickness * cos_2 / wavelength;
+        (airy(r12_s, r23_s, phase) + airy(r12_p, r23_p, phase)) / 2.0
+    }))
+}
@@ -0,0 +1,106 @@
+use std::{fs::File, io::BufReader};
+
+use bevy::{
+    math::Vec3A,
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+};
+
+use crate::{load_error::LoadError, scene::TriangleMesh};
+
+impl TriangleMesh {
+    /// Loads every object of a Wavefront OBJ file into a single mesh with an identity transform.
+    /// The materials of the file are ignored, the whole mesh uses `material_id`.
+    ///
+    /// Faces with more than 3 vertices are triangulated, and smooth normals are generated for the
+    /// objects that don't have any.
+    pub fn from_obj(
+        path: &str,
+        material_id: usize,
+        mesh_assets: &mut Assets<Mesh>,
+    ) -> Result<Self, LoadError> {
+        // tobj drops the io error when it opens the file itself
+        let file = File::open(path).map_err(|error| LoadError::Io {
+            path: path.to_string(),
+            error,
+        })?;
+        let (models, _materials) = tobj::load_obj_buf(
+            &mut BufReader::new(file),
+            &tobj::LoadOptions {
+                triangulate: true,
+                // The renderer needs a single index buffer shared by every attribute
+                single_index: true,
+                ..default()
+            },
+            // The .mtl files aren't needed since the materials are ignored
+            |_| Ok(Default::default()),
+        )
+        .map_err(|err| LoadError::Parse {
+            path: path.to_string(),
+            message: err.to_string(),
+        })?;
+
+        let mut positions: Vec<[f32; 3]> = Vec::new();
+        let mut normals: Vec<[f32; 3]> = Vec::new();
+        let mut uvs: Vec<[f32; 2]> = Vec::new();
+        let mut indices: Vec<u32> = Vec::new();
+        for model in &models {
+            let mesh = &model.mesh;
+            let first_vertex = positions.len();
+            let vertex_count = mesh.positions.len() / 3;
+            positions.extend(mesh.positions.chunks_exact(3).map(|p| [p[0], p[1], p[2]]));
+            let model_indices = mesh.indices.iter().map(|i| first_vertex as u32 + i);
+            indices.extend(model_indices);
+
+            if mesh.normals.len() == mesh.positions.len() {
+                normals.extend(mesh.normals.chunks_exact(3).map(|n| [n[0], n[1], n[2]]));
+            } else {
+                normals.extend(smooth_normals(&positions[first_vertex..], &mesh.indices));
+            }
+            if mesh.texcoords.len() / 2 == vertex_count {
+                // OBJ has v going up, the textures are sampled with v going down like in images
+                uvs.extend(
+                    mesh.texcoords
+                        .chunks_exact(2)
+                        .map(|uv| [uv[0], 1.0 - uv[1]]),
+                );
+            } else {
+                uvs.resize(positions.len(), [0.0, 0.0]);
+            }
+        }
+        if indices.is_empty() {
+            return Err(LoadError::Unsupported {
+                path: path.to_string(),
+                message: "the file doesn't contain any face".to_string(),
+            });
+        }
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        Ok(Self::new(
+            mesh_assets.add(mesh),
+            Transform::IDENTITY,
+            material_id,
+        ))
+    }
+}
+
+/// Averages the normals of the triangles around each vertex, weighted by their area
+fn smooth_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    let mut normals = vec![Vec3A::ZERO; positions.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [v0, v1, v2] = [0, 1, 2].map(|i| Vec3A::from(positions[triangle[i] as usize]));
+        // The length of the cross product is twice the area of the triangle
+        let normal = (v1 - v0).cross(v2 - v0);
+        for i in triangle {
+            normals[*i as usize] += normal;
+        }
+    }
+    normals
+        .into_iter()
+        .map(|normal| normal.normalize_or_zero().into())
+        .collect()
+}
@@ -0,0 +1,175 @@
+use std::borrow::Cow;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::{CameraSettings, CustomCamera},
+    load_error::{read_ron, write_ron, LoadError},
+    renderer::{Renderer, RendererSettings},
+    scene::{Material, Scene},
+};
+
+/// Everything needed to get back to the same render after restarting the app
+#[derive(Serialize, Deserialize)]
+struct Project<'a> {
+    scene: Cow<'a, Scene>,
+    camera: CameraSettings,
+    renderer: RendererSettings,
+}
+
+/// Path of the file used to save and load the project
+#[derive(Debug, Resource)]
+pub struct ProjectPath(pub String);
+
+impl Default for ProjectPath {
+    fn default() -> Self {
+        Self("project.ron".to_string())
+    }
+}
+
+pub fn save_project(
+    path: &str,
+    scene: &Scene,
+    camera: &CustomCamera,
+    renderer: &Renderer,
+) -> Result<(), LoadError> {
+    let project = Project {
+        scene: Cow::Borrowed(scene),
+        camera: camera.settings(),
+        renderer: renderer.settings(),
+    };
+    write_ron(path, &project)
+}
+
+/// Loads a project and resets the accumulation.
+///
+/// Meshes aren't saved in projects yet, so the current ones are kept, see
+/// [`Scene::replace_keep_meshes`].
+pub fn load_project(
+    path: &str,
+    scene: &mut Scene,
+    camera: &mut CustomCamera,
+    renderer: &mut Renderer,
+) -> Result<(), LoadError> {
+    let project: Project = read_ron(path)?;
+    scene.replace_keep_meshes(project.scene.into_owned(), path)?;
+
+    camera.apply_settings(&project.camera);
+    renderer.apply_settings(&project.renderer);
+    Ok(())
+}
+
+/// The state of a scene that isn't active
+#[derive(Debug, Clone)]
+struct SceneState {
+    scene: Scene,
+    camera: CameraSettings,
+    renderer: RendererSettings,
+}
+
+impl SceneState {
+    fn capture(scene: &Scene, camera: &CustomCamera, renderer: &Renderer) -> Self {
+        Self {
+            scene: scene.clone(),
+            camera: camera.settings(),
+            renderer: renderer.settings(),
+        }
+    }
+
+    fn restore(self, scene: &mut Scene, camera: &mut CustomCamera, renderer: &mut Renderer) {
+        *scene = self.scene;
+        camera.apply_settings(&self.camera);
+        renderer.apply_settings(&self.renderer);
+    }
+}
+
+#[derive(Debug)]
+pub struct NamedScene {
+    pub name: String,
+    /// `None` for the active scene, its state is in the [`Scene`], [`CustomCamera`] and [`Renderer`]
+    /// resources
+    state: Option<SceneState>,
+}
+
+/// Scenes kept in memory to switch between them, each with its own camera and render settings
+#[derive(Debug, Resource)]
+pub struct Scenes {
+    pub list: Vec<NamedScene>,
+    active: usize,
+}
+
+impl Default for Scenes {
+    fn default() -> Self {
+        Self {
+            list: vec![NamedScene {
+                name: "Scene 1".to_string(),
+                state: None,
+            }],
+            active: 0,
+        }
+    }
+}
+
+impl Scenes {
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    /// Saves the state of the active scene in the list and replaces it with the state of another one.
+    /// This resets the accumulation.
+    pub fn switch_to(
+        &mut self,
+        index: usize,
+        scene: &mut Scene,
+        camera: &mut CustomCamera,
+        renderer: &mut Renderer,
+    ) {
+        if index == self.active || index >= self.list.len() {
+            return;
+        }
+        self.list[self.active].state = Some(SceneState::capture(scene, camera, renderer));
+        if let Some(state) = self.list[index].state.take() {
+            state.restore(scene, camera, renderer);
+        }
+        self.active = index;
+    }
+
+    /// Adds an empty scene, with a default material, and switches to it.
+    /// It starts with the current camera and render settings.
+    pub fn add_new(
+        &mut self,
+        scene: &mut Scene,
+        camera: &mut CustomCamera,
+        renderer: &mut Renderer,
+    ) {
+        let new_scene = Scene {
+            materials: vec![Material::default()],
+            ..default()
+        };
+        self.push(SceneState {
+            scene: new_scene,
+            camera: camera.settings(),
+            renderer: renderer.settings(),
+        });
+        self.switch_to(self.list.len() - 1, scene, camera, renderer);
+    }
+
+    /// Adds a copy of the active scene and switches to it
+    pub fn duplicate_active(
+        &mut self,
+        scene: &mut Scene,
+        camera: &mut CustomCamera,
+        renderer: &mut Renderer,
+    ) {
+        self.push(SceneState::capture(scene, camera, renderer));
+        self.switch_to(self.list.len() - 1, scene, camera, renderer);
+    }
+
+    fn push(&mut self, state: SceneState) {
+        self.list.push(NamedScene {
+            name: format!("Scene {}", self.list.len() + 1),
+            state: Some(state),
+        });
+    }
+}
@@ -1,4 +1,4 @@
-use bevy::math::Vec3A;
+use bevy::math::{Vec2, Vec3A};
 use rand::{Rng, RngCore};
 
 #[allow(unused)]
@@ -47,19 +47,103 @@ impl RngCore for PcgHashRng {
     }
 }
 
-pub fn in_unit_sphere<R: Rng>(rng: &mut R) -> Vec3A {
+/// Samples a microfacet normal from an anisotropic GGX distribution, in tangent space.
+///
+/// The slopes of the isotropic distribution are stretched by `alpha_x` and `alpha_y` along the
+/// tangent and the bitangent.
+///
+/// Reference:
+/// * Eric Heitz, Understanding the Masking-Shadowing Function: https://jcgt.org/published/0003/02/03/
+pub fn ggx_microfacet_normal<R: Rng>(rng: &mut R, alpha_x: f32, alpha_y: f32) -> Vec3A {
+    let phi = std::f32::consts::TAU * rng.gen::<f32>();
+    let u: f32 = rng.gen_range(0.0..1.0);
+    let slope = (u / (1.0 - u)).sqrt();
     Vec3A::new(
-        rng.gen_range(-1.0..1.0),
-        rng.gen_range(-1.0..1.0),
-        rng.gen_range(-1.0..1.0),
+        -alpha_x * slope * phi.cos(),
+        -alpha_y * slope * phi.sin(),
+        1.0,
     )
     .normalize()
+}
+
+/// Van der Corput radical inverse of `index` in the given base, this is one dimension of a Halton
+/// sequence
+///
+/// Reference:
+/// * https://pbr-book.org/3ed-2018/Sampling_and_Reconstruction/The_Halton_Sampler
+pub fn radical_inverse(base: u32, mut index: u32) -> f32 {
+    let inv_base = 1.0 / base as f32;
+    let mut inv_base_n = 1.0;
+    let mut reversed = 0u64;
+    while index > 0 {
+        reversed = reversed * base as u64 + (index % base) as u64;
+        inv_base_n *= inv_base;
+        index /= base;
+    }
+    (reversed as f32 * inv_base_n).min(1.0 - f32::EPSILON)
+}
+
+/// The first two dimensions of the Sobol sequence
+///
+/// Reference:
+/// * Kollig and Keller, Efficient Multidimensional Sampling: https://www.uni-kl.de/AG-Heinrich/EMS.pdf
+pub fn sobol_2d(index: u32) -> Vec2 {
+    let mut v = 1 << 31;
+    let mut second = 0u32;
+    let mut i = index;
+    while i != 0 {
+        if i & 1 == 1 {
+            second ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    // The first dimension is the base 2 radical inverse
+    let first = index.reverse_bits();
+    Vec2::new(first as f32, second as f32) / 2f32.powi(32)
+}
+
+/// Shuffles `index` so every aligned power of 2 block of indices maps to another one, a different
+/// `seed` gives a different order. The blocks of the Sobol sequence are all well stratified, so
+/// walking it with the shuffled indices keeps the quality of its prefixes while decorrelating it
+/// from other walks of the same sequence.
+///
+/// Reference:
+/// * Burley, Practical Hash-based Owen Scrambling: https://jcgt.org/published/0009/04/01/
+pub fn nested_uniform_scramble(index: u32, seed: u32) -> u32 {
+    // Laine-Karras permutation of the reversed bits, each bit only depends on the lower ones
+    let mut x = index.reverse_bits().wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+/// Maps a point of the unit square to a point on the unit sphere, preserving the uniformity
+pub fn on_unit_sphere(point: Vec2) -> Vec3A {
+    let z = 1.0 - 2.0 * point.x;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = std::f32::consts::TAU * point.y;
+    Vec3A::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Maps a point of the unit square to a point in the unit disk, preserving the uniformity
+pub fn in_unit_disk(point: Vec2) -> Vec2 {
+    let r = point.x.sqrt();
+    let phi = std::f32::consts::TAU * point.y;
+    Vec2::new(r * phi.cos(), r * phi.sin())
+}
 
-    // let normal_distr = StandardNormal;
-    // Vec3A::new(
-    //     normal_distr.sample(rng),
-    //     normal_distr.sample(rng),
-    //     normal_distr.sample(rng),
-    // )
-    // .normalize()
+/// Samples a direction around `normal` with a density proportional to the cosine with the normal.
+///
+/// This is the distribution of a Lambertian surface, so the cosine term and the PDF cancel out and
+/// the throughput of a diffuse bounce is only multiplied by the albedo.
+///
+/// Reference:
+/// * https://raytracing.github.io/books/RayTracingInOneWeekend.html#diffusematerials/truelambertianreflection
+pub fn cosine_weighted_hemisphere(normal: Vec3A, point: Vec2) -> Vec3A {
+    let direction = normal + on_unit_sphere(point);
+    // The sphere point can be exactly opposite to the normal
+    direction.try_normalize().unwrap_or(normal)
 }
@@ -1,17 +1,30 @@
-use std::ops::RangeInclusive;
+use std::{
+    f32::consts::{PI, TAU},
+    ops::{Range, RangeInclusive},
+    time::Instant,
+};
 
 use bevy::{
     math::Vec3A,
     prelude::*,
-    render::{mesh::Indices, primitives::Aabb},
+    render::{mesh::VertexAttributeValues, primitives::Aabb},
 };
-use rayon::prelude::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use rand::Rng;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    accumulation::{AccumulationBuffer, AccumulationPrecision},
     camera::CustomCamera,
-    math_utils::smoothstep,
+    denoiser::Denoiser,
+    lens_flare::LensFlare,
+    math_utils::{
+        fresnel_schlick, linear_to_srgb, power_heuristic, reflect, refract, smoothstep,
+        tangent_frame, thin_film_reflectance,
+    },
     random::{self, PcgHashRng},
-    scene::{Scene, Sphere},
+    scene::{Fog, Material, ObjectRef, Scene, SkyMode, Sphere},
+    spherical_harmonics::ShIrradiance,
 };
 
 #[derive(Debug, Clone, Copy)]
@@ -21,37 +34,719 @@ struct Ray {
     inv_direction: Vec3A,
 }
 
+/// The BVH is balanced so this is enough for any mesh that fits in memory
+const BVH_STACK_SIZE: usize = 64;
+
 struct HitPayload {
-    #[allow(unused)]
     hit_distance: f32,
     world_position: Vec3,
     world_normal: Vec3,
+    /// Texture coordinates of the hit, (0, 0) on meshes without UVs
+    uv: Vec2,
+    /// Direction in which u increases along the surface, zero when the UVs don't define one
+    tangent: Vec3,
     material_id: usize,
+    object: ObjectRef,
+}
+
+impl HitPayload {
+    /// Returns the material of the hit surface with its textures applied.
+    /// The normal texture bends `world_normal`, so this is called before shading the hit.
+    fn apply_textures(&mut self, scene: &Scene) -> Material {
+        let mut material = scene.materials[self.material_id];
+        if let Some(texture) = material
+            .albedo_texture
+            .and_then(|index| scene.textures.get(index))
+        {
+            material.albedo *= texture.sample(self.uv);
+        }
+        if let Some(texture) = material
+            .normal_texture
+            .and_then(|index| scene.textures.get(index))
+        {
+            // tangent space normal, remapped from [-1, 1] to [0, 1] in the image
+            let normal = texture.sample(self.uv) * 2.0 - 1.0;
+            let (tangent, bitangent) = tangent_frame(self.world_normal.into(), self.tangent.into());
+            let bent_normal = tangent * normal.x
+                + bitangent * normal.y
+                + Vec3A::from(self.world_normal) * normal.z;
+            if let Some(bent_normal) = bent_normal.try_normalize() {
+                self.world_normal = bent_normal.into();
+            }
+        }
+        material
+    }
+}
+
+/// Width and height in pixels of a tile, except on the right and bottom edges of the image
+const TILE_SIZE: usize = 16;
+
+/// Tiles rendered by each thread before the image is updated and the deadline is checked
+const TILES_PER_THREAD: usize = 4;
+
+/// Surfaces a shadow ray can go through before the light is considered blocked
+const MAX_SHADOW_HITS: usize = 8;
+
+/// Specular bounces on materials smoother than this use the specular allowance instead of the
+/// bounce budget
+const NEAR_SPECULAR_ALPHA: f32 = 0.1;
+
+/// How many times a path can bounce
+#[derive(Debug, Clone, Copy)]
+struct BounceBudget {
+    /// Maximum number of diffuse and rough specular bounces of a path, unless the material that
+    /// was just hit overrides it with [`Material::max_bounce`]
+    diffuse: u8,
+    /// Used by the near mirror bounces before they start counting against `diffuse`
+    specular: u8,
+}
+
+/// Rectangle of pixels, from its top left corner.
+/// This is the block of pixels rendered by a single task, and the bucket of [`Renderer::set_bucket`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    /// Index in the image of every pixel of the tile, in row-major order
+    pub fn pixel_indices(self, image_width: usize) -> impl Iterator<Item = usize> {
+        (self.y..self.y + self.height)
+            .flat_map(move |y| (self.x..self.x + self.width).map(move |x| y * image_width + x))
+    }
+
+    /// The pixels of the tile that are also in `other`, `None` if they don't overlap
+    fn intersection(self, other: Tile) -> Option<Tile> {
+        let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        (x < right && y < bottom).then_some(Tile {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        })
+    }
+}
+
+/// Splits the image in tiles ordered in a spiral going out from the center.
+/// The center of the image is usually where the subject is, so it's rendered first.
+fn spiral_tiles(width: usize, height: usize) -> Vec<Tile> {
+    let mut tiles = vec![];
+    for y in (0..height).step_by(TILE_SIZE) {
+        for x in (0..width).step_by(TILE_SIZE) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: TILE_SIZE.min(width - x),
+                height: TILE_SIZE.min(height - y),
+            });
+        }
+    }
+
+    let center = Vec2::new(width as f32, height as f32) / 2.0;
+    let spiral_key = |tile: &Tile| {
+        let tile_center = Vec2::new(
+            tile.x as f32 + tile.width as f32 / 2.0,
+            tile.y as f32 + tile.height as f32 / 2.0,
+        );
+        let offset = (tile_center - center) / TILE_SIZE as f32;
+        // Each ring of tiles around the center is sorted by angle
+        let ring = offset.x.abs().max(offset.y.abs()).round() as i32;
+        (ring, offset.y.atan2(offset.x))
+    };
+    tiles.sort_by(|a, b| {
+        let (a_ring, a_angle) = spiral_key(a);
+        let (b_ring, b_angle) = spiral_key(b);
+        a_ring.cmp(&b_ring).then(a_angle.total_cmp(&b_angle))
+    });
+    tiles
+}
+
+/// The tiles of [`spiral_tiles`] cropped to the bucket, the ones outside of it are dropped
+fn bucket_tiles(width: usize, height: usize, bucket: Option<Tile>) -> Vec<Tile> {
+    let tiles = spiral_tiles(width, height);
+    match bucket {
+        Some(bucket) => tiles
+            .into_iter()
+            .filter_map(|tile| tile.intersection(bucket))
+            .collect(),
+        None => tiles,
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RenderMode {
+    #[default]
+    PathTraced,
+    /// Only traces camera rays and lights them with the sky irradiance.
+    /// This is biased, but it converges almost instantly.
+    FastPreview,
+}
+
+/// What the displayed image shows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RenderOutput {
+    #[default]
+    Beauty,
+    /// Each object with a distinct flat color, from [`Renderer::id_data`]
+    ObjectId,
+    /// The textured albedo of the first hit, from [`Renderer::albedo_data`]
+    Albedo,
+    /// The shading normal of the first hit remapped to [0, 1], from [`Renderer::normal_data`]
+    Normal,
+    /// The closest hits are white and the farthest are black, from [`Renderer::depth_data`]
+    Depth,
+}
+
+impl RenderOutput {
+    pub const ALL: [Self; 5] = [
+        Self::Beauty,
+        Self::ObjectId,
+        Self::Albedo,
+        Self::Normal,
+        Self::Depth,
+    ];
+}
+
+/// What the camera ray through the center of a pixel hits, see [`Renderer::id_data`]
+#[derive(Debug, Clone, Copy)]
+struct FirstHit {
+    object_id: u32,
+    albedo: Vec3,
+    normal: Vec3,
+    depth: f32,
+}
+
+impl FirstHit {
+    const BACKGROUND: Self = Self {
+        object_id: 0,
+        albedo: Vec3::ZERO,
+        normal: Vec3::ZERO,
+        depth: f32::INFINITY,
+    };
+
+    fn trace(camera: &CustomCamera, scene: &Scene, pixel_index: usize) -> Self {
+        let Some(mut payload) =
+            trace_ray(&camera_ray(camera, pixel_index), scene, camera.clip_range())
+        else {
+            return Self::BACKGROUND;
+        };
+        let material = payload.apply_textures(scene);
+        Self {
+            object_id: scene.object_id(payload.object),
+            albedo: material.albedo,
+            normal: payload.world_normal,
+            depth: payload.hit_distance,
+        }
+    }
+}
+
+/// A color that is easy to tell apart from the colors of the neighbouring ids
+fn object_id_color(id: u32) -> [u8; 4] {
+    if id == 0 {
+        return [0, 0, 0, 255];
+    }
+    let [r, g, b, _] = random::pcg_hash(id).to_le_bytes();
+    [r, g, b, 255]
+}
+
+/// Maps the HDR colors of the render to the range of the display, before they are encoded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Tonemapping {
+    /// Everything above 1.0 is clipped
+    #[default]
+    None,
+    /// Compresses the highlights, luminances above [`Renderer::white_point`] are mapped to white
+    ReinhardExtended,
+}
+
+impl Tonemapping {
+    pub const ALL: [Self; 2] = [Self::None, Self::ReinhardExtended];
+
+    /// Reference:
+    /// * Reinhard et al., Photographic Tone Reproduction for Digital Images: https://www.cs.utah.edu/docs/techreports/2002/pdf/UUCS-02-001.pdf
+    fn apply(self, color: Vec4, white_point: f32) -> Vec4 {
+        match self {
+            Self::None => color,
+            Self::ReinhardExtended => {
+                let luminance = luminance(color.truncate());
+                if luminance <= 0.0 {
+                    return color;
+                }
+                let mapped =
+                    luminance * (1.0 + luminance / (white_point * white_point)) / (1.0 + luminance);
+                (color.truncate() * mapped / luminance).extend(color.w)
+            }
+        }
+    }
+}
+
+/// Relative luminance of a linear Rec. 709 color
+fn luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+/// How the linear colors of the render are encoded in the displayed image.
+/// This only changes the display, the accumulation is always linear.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputColorSpace {
+    #[default]
+    Srgb,
+    /// Writes the linear values as is, this looks too dark but is useful for debugging
+    Linear,
+    /// A pure 2.4 gamma, like the displays of the Rec.709 and BT.1886 standards
+    Rec709,
+}
+
+impl OutputColorSpace {
+    pub const ALL: [Self; 3] = [Self::Srgb, Self::Linear, Self::Rec709];
+
+    /// Clamps and encodes a linear color, the alpha stays linear
+    fn encode(self, color: Vec4) -> [u8; 4] {
+        let color = color.clamp(Vec4::ZERO, Vec4::ONE);
+        let encoded = match self {
+            Self::Srgb => color.truncate().to_array().map(linear_to_srgb).into(),
+            Self::Linear => color.truncate(),
+            Self::Rec709 => color.truncate().powf(1.0 / 2.4),
+        };
+        encoded.extend(color.w).as_u8_array()
+    }
+}
+
+/// How the random numbers of the pixel jitter, the lens and the diffuse bounces are generated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Sampler {
+    #[default]
+    Random,
+    /// Low discrepancy sequences cover the hemisphere more evenly than random numbers,
+    /// so the same number of samples has less noise.
+    Halton,
+    Sobol,
+}
+
+impl Sampler {
+    pub const ALL: [Self; 3] = [Self::Random, Self::Halton, Self::Sobol];
+
+    /// Returns the point of the sequence at `index` for one dimension of the sample.
+    /// The point is shifted by `rotation` so neighbouring pixels and bounces don't use the same points,
+    /// this is known as a Cranley-Patterson rotation.
+    fn sample_2d<R: Rng>(
+        self,
+        index: u32,
+        dimension: SampleDimension,
+        rotation: Vec2,
+        rng: &mut R,
+    ) -> Vec2 {
+        let point = match self {
+            Self::Random => return Vec2::new(rng.gen(), rng.gen()),
+            Self::Halton => {
+                let [x_base, y_base] = dimension.halton_bases();
+                Vec2::new(
+                    random::radical_inverse(x_base, index),
+                    random::radical_inverse(y_base, index),
+                )
+            }
+            // Only the first two dimensions of the Sobol sequence are implemented, so every
+            // dimension walks the same points in its own order instead
+            Self::Sobol => {
+                random::sobol_2d(random::nested_uniform_scramble(index, dimension.seed()))
+            }
+        };
+        (point + rotation).fract()
+    }
+}
+
+/// The parts of a sample that each take a 2D point from the [`Sampler`].
+///
+/// With the same point for all of them, the position in the pixel would decide the position on
+/// the lens and the first bounce, which shows up as structured noise instead of grain.
+/// There's no motion blur so there's no time dimension yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleDimension {
+    /// Direction of the diffuse bounces
+    Bounce,
+    /// Position of the camera ray in its pixel, see [`PixelFilter`]
+    PixelJitter,
+    /// Position on the lens of the depth of field
+    Lens,
+}
+
+impl SampleDimension {
+    pub const ALL: [Self; 3] = [Self::Bounce, Self::PixelJitter, Self::Lens];
+
+    /// Every dimension uses its own primes, the bounces get the lowest ones since they have the
+    /// most impact on the noise
+    fn halton_bases(self) -> [u32; 2] {
+        match self {
+            Self::Bounce => [2, 3],
+            Self::PixelJitter => [5, 7],
+            Self::Lens => [11, 13],
+        }
+    }
+
+    fn seed(self) -> u32 {
+        random::pcg_hash(self as u32 + 1)
+    }
+}
+
+/// The first `count` points of one dimension of a sampler, without any rotation.
+///
+/// Used by the UI to show how the points are distributed.
+pub fn sample_points(sampler: Sampler, dimension: SampleDimension, count: u32) -> Vec<Vec2> {
+    let mut rng = PcgHashRng::new(dimension.seed());
+    (0..count)
+        .map(|index| sampler.sample_2d(index, dimension, Vec2::ZERO, &mut rng))
+        .collect()
+}
+
+/// How the samples of a pixel are spread around its center.
+///
+/// The samples are accumulated with the same weight, so instead of weighting them by their distance
+/// to the center, they are placed with the density of the filter. This is known as filter
+/// importance sampling, it converges to the same image without changing the accumulation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PixelFilter {
+    /// Uniform over the pixel
+    #[default]
+    Box,
+    /// Decreases linearly up to one pixel from the center, so it overlaps the neighbouring pixels.
+    /// The edges are smoother than with the box filter.
+    Tent,
+    /// Standard deviation of half a pixel, a bit softer than the tent filter
+    Gaussian,
+}
+
+impl PixelFilter {
+    pub const ALL: [Self; 3] = [Self::Box, Self::Tent, Self::Gaussian];
+
+    /// Maps a point of the unit square to an offset from the center of the pixel, in pixels
+    fn sample(self, point: Vec2) -> Vec2 {
+        match self {
+            Self::Box => point - 0.5,
+            Self::Tent => Vec2::new(sample_tent(point.x), sample_tent(point.y)),
+            Self::Gaussian => {
+                // Box-Muller transform, 1 - x avoids the logarithm of 0
+                let radius = 0.5 * (-2.0 * (1.0 - point.x).ln()).sqrt();
+                let angle = TAU * point.y;
+                Vec2::new(angle.cos(), angle.sin()) * radius
+            }
+        }
+    }
+}
+
+/// Inverse of the cumulative distribution of the tent function between -1 and 1
+fn sample_tent(x: f32) -> f32 {
+    if x < 0.5 {
+        (2.0 * x).sqrt() - 1.0
+    } else {
+        1.0 - (2.0 - 2.0 * x).sqrt()
+    }
+}
+
+/// Everything needed to generate the random numbers of one sample of a pixel
+#[derive(Debug, Clone, Copy)]
+struct SampleSeed {
+    /// Seeds the random numbers, should be different for every frame
+    frame_seed: u32,
+    sampler: Sampler,
+    /// Index of the sample in the sequence of the pixel
+    index: u32,
+    /// Mixed in the rotation of the sequence, see [`Renderer::frame_seed_offset`]
+    scramble: u32,
+    /// Spreads the samples around the center of their pixel, `None` when
+    /// [`Renderer::anti_aliasing`] is disabled
+    jitter: Option<PixelFilter>,
+}
+
+/// Sets the renderer settings that have the most impact on quality in one go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QualityPreset {
+    Draft,
+    Preview,
+    Final,
+}
+
+impl QualityPreset {
+    pub const ALL: [Self; 3] = [Self::Draft, Self::Preview, Self::Final];
+
+    /// Rays per pixel, bounces and max samples
+    fn values(self) -> (u8, u8, Option<usize>) {
+        match self {
+            Self::Draft => (1, 2, None),
+            Self::Preview => (1, 5, Some(256)),
+            Self::Final => (4, 8, Some(1024)),
+        }
+    }
+
+    /// Returns the preset matching the current settings, `None` if they were edited manually
+    pub fn from_renderer(renderer: &Renderer) -> Option<Self> {
+        Self::ALL.into_iter().find(|preset| {
+            preset.values()
+                == (
+                    renderer.rays_per_pixel,
+                    renderer.bounces,
+                    renderer.max_samples,
+                )
+        })
+    }
+
+    pub fn apply(self, renderer: &mut Renderer) {
+        (
+            renderer.rays_per_pixel,
+            renderer.bounces,
+            renderer.max_samples,
+        ) = self.values();
+        renderer.reset_frame_index();
+    }
+}
+
+/// State of the frame being rendered, a frame can be spread over several calls of
+/// [`Renderer::render_until`]
+#[derive(Debug, Default)]
+struct FrameProgress {
+    /// Index in the spiral of the next tile to render, 0 when no frame is in progress
+    next_tile: usize,
+    /// Sum of the change of every pixel rendered so far
+    noise_sum: f32,
+    min_sample_count: u32,
+    stats: FrameStats,
+}
+
+/// Statistics of the paths traced for the last frame, shown in the Stats tab
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameStats {
+    pub paths: u64,
+    /// Paths whose camera ray hit something
+    pub primary_hits: u64,
+    /// Surfaces hit by all the paths
+    pub path_vertices: u64,
+}
+
+impl FrameStats {
+    fn add_path(&mut self, path_length: u32) {
+        self.paths += 1;
+        self.primary_hits += (path_length > 0) as u64;
+        self.path_vertices += path_length as u64;
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.paths += other.paths;
+        self.primary_hits += other.primary_hits;
+        self.path_vertices += other.path_vertices;
+    }
+
+    pub fn primary_hit_rate(&self) -> f32 {
+        self.primary_hits as f32 / self.paths.max(1) as f32
+    }
+
+    /// Average number of surfaces hit by a path
+    pub fn average_path_length(&self) -> f32 {
+        self.path_vertices as f32 / self.paths.max(1) as f32
+    }
+}
+
+/// A point where a path traced by [`Renderer::debug_path`] hit something
+#[derive(Debug, Clone)]
+pub struct PathVertex {
+    pub position: Vec3,
+    /// `None` when the ray escaped to the sky, the position is then a point along the ray
+    pub material_id: Option<usize>,
+    /// How much of the light coming from the next vertices reaches the camera
+    pub throughput: Vec3,
+    /// Light gathered by the path so far
+    pub radiance: Vec3,
+}
+
+/// The part of the renderer that is saved in a project
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RendererSettings {
+    pub accumulate: bool,
+    pub bounces: u8,
+    #[serde(default)]
+    pub specular_bounces: u8,
+    pub rays_per_pixel: u8,
+    #[serde(default)]
+    pub transparent_background: bool,
+    #[serde(default)]
+    pub mode: RenderMode,
+    #[serde(default)]
+    pub max_samples: Option<usize>,
+    #[serde(default)]
+    pub lens_flare: LensFlare,
+    #[serde(default)]
+    pub denoiser: Denoiser,
+    #[serde(default)]
+    pub sampler: Sampler,
+    #[serde(default)]
+    pub output_color_space: OutputColorSpace,
+    #[serde(default)]
+    pub tonemapping: Tonemapping,
+    #[serde(default = "default_white_point")]
+    pub white_point: f32,
+    #[serde(default)]
+    pub output: RenderOutput,
+    #[serde(default)]
+    pub firefly_clamp: Option<f32>,
+    #[serde(default = "default_anti_aliasing")]
+    pub anti_aliasing: bool,
+    #[serde(default)]
+    pub frame_seed_offset: u32,
+    #[serde(default)]
+    pub caustic_boost: bool,
+    #[serde(default)]
+    pub pixel_filter: PixelFilter,
+}
+
+fn default_white_point() -> f32 {
+    4.0
+}
+
+fn default_anti_aliasing() -> bool {
+    true
 }
 
 #[derive(Debug, Resource)]
 pub struct Renderer {
     pub image_data: Vec<[u8; 4]>,
-    pub accumulation_data: Vec<Vec4>,
+    pub accumulation_data: AccumulationBuffer,
     pub width: usize,
     pub height: usize,
+    /// Index of the next frame, used to seed the samples
     pub samples: usize,
+    /// Number of frames accumulated in each pixel.
+    /// This is usually the same for every pixel, except after a partial reset.
+    pub sample_counts: Vec<u32>,
     pub accumulate: bool,
+    /// Bounces on diffuse and rough surfaces
     pub bounces: u8,
+    /// Extra bounces on near mirror surfaces, so mirror chains resolve without making every path longer
+    pub specular_bounces: u8,
     pub rays_per_pixel: u8,
+    /// Stops accumulating once this many frames have been accumulated, read through
+    /// [`Renderer::sample_limit`]. `Some(0)` can come from an edited project file, it means no
+    /// limit like `None` instead of a render that never starts.
+    pub max_samples: Option<usize>,
+    /// Maximum luminance of a single sample.
+    /// This removes the fireflies of rare bright paths at the cost of darkening the highlights.
+    pub firefly_clamp: Option<f32>,
+    /// Every sample of the path tracer goes through a random point of its pixel instead of the
+    /// center, so the edges are antialiased once a few samples are accumulated
+    pub anti_aliasing: bool,
+    /// Distribution of the jittered rays around the pixel centers
+    pub pixel_filter: PixelFilter,
+    /// Lets the light sampling of the sun and the emissive spheres go through transmissive
+    /// surfaces, like the directional and point lights do. The bounces rarely find the light
+    /// focused by glass, so this brightens the dark areas under it.
+    ///
+    /// This is biased: the light isn't bent by the glass, and it's added on top of the light the
+    /// bounces still find through it.
+    pub caustic_boost: bool,
+    /// Size in pixels of the blocks traced as a single pixel while the camera moves, 1 disables it.
+    /// This keeps the navigation smooth when the full resolution is too slow to trace.
+    pub preview_scale: u8,
+    /// Set by [`crate::camera::update_camera`] on the frames where the camera moved
+    pub is_moving: bool,
+    /// Moving an object only resets the pixels around it instead of the whole image.
+    /// This is faster to converge, but shadows and reflections of the object elsewhere become stale.
+    pub partial_reset: bool,
+    /// Camera rays that don't hit anything get an alpha of 0, useful to composite the render
+    pub transparent_background: bool,
+    pub mode: RenderMode,
+    /// Applied to the HDR accumulation before it's clamped to the displayed image
+    pub lens_flare: LensFlare,
+    /// Display only, applied before the lens flare. The PFM and EXR exports aren't denoised.
+    pub denoiser: Denoiser,
+    pub sampler: Sampler,
+    /// Call [`Renderer::resolve_image`] after changing it to update the image without a reset
+    pub output_color_space: OutputColorSpace,
+    /// Display only like `output_color_space`
+    pub tonemapping: Tonemapping,
+    /// Lowest luminance mapped to white by [`Tonemapping::ReinhardExtended`]
+    pub white_point: f32,
+    /// Display only like `output_color_space`
+    pub output: RenderOutput,
+    /// [`crate::scene::Sphere::id`] of the object seen through the center of each pixel, 0 for the background.
+    /// This is only traced on the first sample after a reset.
+    pub id_data: Vec<u32>,
+    /// Albedo of the material seen through the center of each pixel, black for the background.
+    /// Like `id_data`, this is only traced on the first sample after a reset.
+    pub albedo_data: Vec<Vec3>,
+    /// Shading normal seen through the center of each pixel, zero for the background
+    pub normal_data: Vec<Vec3>,
+    /// Distance from the camera of the hit seen through the center of each pixel, infinite for the
+    /// background
+    pub depth_data: Vec<f32>,
+    /// Added to the per-pixel seed of every sample, it's edited in the Settings tab.
+    /// The same settings render a different noise pattern with another offset, so giving each
+    /// frame of an animation its own offset keeps the noise from looking static.
+    pub frame_seed_offset: u32,
+    /// Average change of a pixel between the last two accumulated frames.
+    /// `None` until at least two frames have been accumulated.
+    pub noise_estimate: Option<f32>,
+    /// Tiles in the order they are scheduled, updated when resizing
+    tiles: Vec<Tile>,
+    /// Only these pixels are rendered when it's set, see [`Renderer::set_bucket`]
+    bucket: Option<Tile>,
+    /// The frame being rendered, reset with the accumulation
+    progress: FrameProgress,
+    /// Indices of the emissive spheres, sampled on every diffuse hit. This is collected when a frame
+    /// starts instead of searching the spheres on every hit.
+    emitters: Vec<usize>,
+    /// Used by [`RenderMode::FastPreview`], updated when the accumulation is reset
+    sky_irradiance: ShIrradiance,
+    /// Lowest value of `sample_counts` after the last frame
+    min_sample_count: u32,
+    /// Counted while rendering the last frame
+    pub stats: FrameStats,
 }
 
 impl Renderer {
     pub fn new(width: usize, height: usize) -> Self {
         Self {
             image_data: vec![[0, 0, 0, 0]; width * height],
-            accumulation_data: vec![Vec4::ZERO; width * height],
+            accumulation_data: AccumulationBuffer::new(AccumulationPrecision::Full, width * height),
             width,
             height,
             samples: 1,
+            sample_counts: vec![0; width * height],
             accumulate: true,
             bounces: 5,
+            specular_bounces: 0,
             rays_per_pixel: 1,
+            max_samples: None,
+            firefly_clamp: None,
+            caustic_boost: false,
+            pixel_filter: PixelFilter::Box,
+            anti_aliasing: default_anti_aliasing(),
+            preview_scale: 1,
+            is_moving: false,
+            partial_reset: false,
+            transparent_background: false,
+            mode: RenderMode::PathTraced,
+            lens_flare: LensFlare::default(),
+            denoiser: Denoiser::default(),
+            sampler: Sampler::Random,
+            output_color_space: OutputColorSpace::Srgb,
+            tonemapping: Tonemapping::None,
+            white_point: default_white_point(),
+            output: RenderOutput::Beauty,
+            id_data: vec![0; width * height],
+            albedo_data: vec![Vec3::ZERO; width * height],
+            normal_data: vec![Vec3::ZERO; width * height],
+            depth_data: vec![f32::INFINITY; width * height],
+            frame_seed_offset: 0,
+            noise_estimate: None,
+            tiles: spiral_tiles(width, height),
+            bucket: None,
+            progress: FrameProgress::default(),
+            emitters: Vec::new(),
+            sky_irradiance: ShIrradiance::default(),
+            min_sample_count: 0,
+            stats: FrameStats::default(),
         }
     }
 
@@ -60,149 +755,1400 @@ impl Renderer {
         self.height = height;
 
         self.image_data.resize(width * height, [0, 0, 0, 0]);
-        self.accumulation_data.resize(width * height, Vec4::ZERO);
+        self.accumulation_data.resize(width * height);
+        self.id_data.resize(width * height, 0);
+        self.albedo_data.resize(width * height, Vec3::ZERO);
+        self.normal_data.resize(width * height, Vec3::ZERO);
+        self.depth_data.resize(width * height, f32::INFINITY);
+        // The pixels moved around, so the old counts don't match them anymore
+        self.sample_counts.clear();
+        self.sample_counts.resize(width * height, 0);
+        self.tiles = bucket_tiles(width, height, self.bucket);
+
+        self.reset_frame_index();
+    }
+
+    /// Only renders the pixels of a rectangle of the image, `None` renders the whole image again.
+    /// The other pixels aren't updated. This resets the accumulation.
+    ///
+    /// The pixels keep the camera rays and the seeds they have in the whole image, so the buckets
+    /// of an image can be rendered separately, for example on several machines, and put back
+    /// together with [`crate::export::merge_bucket_exrs`].
+    pub fn set_bucket(&mut self, bucket: Option<Tile>) {
+        self.bucket = bucket;
+        self.tiles = bucket_tiles(self.width, self.height, bucket);
+        self.reset_frame_index();
+    }
+
+    pub fn bucket(&self) -> Option<Tile> {
+        self.bucket
+    }
+
+    /// Replaces the accumulation buffer by one storing the samples with a different precision, this
+    /// resets the accumulation
+    pub fn set_accumulation_precision(&mut self, precision: AccumulationPrecision) {
+        self.accumulation_data = AccumulationBuffer::new(precision, self.width * self.height);
+        self.reset_frame_index();
+    }
+
+    /// Renders a frame and returns the displayed image, one RGBA pixel per element, row by row.
+    ///
+    /// This doesn't need any Bevy system, so it can be used to drive the renderer from a test or
+    /// another binary. The camera must be resized to the size of the renderer first.
+    pub fn render_to(&mut self, camera: &CustomCamera, scene: &Scene) -> &[[u8; 4]] {
+        self.render(camera, scene);
+        &self.image_data
+    }
+
+    /// Renders the rest of the current frame, or a whole frame if none is in progress
+    pub fn render(&mut self, camera: &CustomCamera, scene: &Scene) {
+        self.render_tiles(camera, scene, None);
+    }
+
+    /// Renders the tiles of the current frame, starting from the center of the image, until the
+    /// deadline is passed. The next call continues the frame where this one stopped.
+    ///
+    /// The image is updated after every batch of tiles, so when a frame is slower than the
+    /// deadline its center shows up first and the rest of the image is filled over the next calls.
+    pub fn render_until(&mut self, camera: &CustomCamera, scene: &Scene, deadline: Instant) {
+        self.render_tiles(camera, scene, Some(deadline));
+    }
+
+    fn render_tiles(&mut self, camera: &CustomCamera, scene: &Scene, deadline: Option<Instant>) {
+        if self.progress.next_tile == 0 && !self.start_frame(camera, scene) {
+            return;
+        }
+
+        let frame_seed = (self.samples as u32).wrapping_add(self.frame_seed_offset);
+        // The ghosts of a pixel come from the other side of the image, the denoiser reads the
+        // neighbouring tiles and the depth is normalized over the whole image, so these are only
+        // displayed once every tile is rendered. Until then the pixels of the previous frame stay.
+        let needs_resolve =
+            self.lens_flare.enabled || self.denoiser.enabled || self.output != RenderOutput::Beauty;
+        let batch_size = rayon::current_num_threads() * TILES_PER_THREAD;
+        loop {
+            let first = self.progress.next_tile;
+            let batch = first..(first + batch_size).min(self.tiles.len());
+            self.render_batch(camera, scene, batch.clone(), frame_seed, needs_resolve);
+            self.progress.next_tile = batch.end;
+            if self.progress.next_tile == self.tiles.len() {
+                break;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return;
+            }
+        }
+
+        // Every tile is rendered, the frame is complete
+        let progress = std::mem::take(&mut self.progress);
+        self.stats = progress.stats;
+        self.min_sample_count = progress.min_sample_count;
+        self.noise_estimate =
+            (self.samples > 1).then(|| progress.noise_sum / self.image_data.len().max(1) as f32);
+
+        if needs_resolve {
+            self.resolve_image();
+        }
+
+        if self.accumulate {
+            self.samples += 1;
+        } else {
+            self.samples = 1;
+        }
+    }
+
+    /// Prepares the buffers for a new frame, returns `false` if there are no tiles to render
+    fn start_frame(&mut self, camera: &CustomCamera, scene: &Scene) -> bool {
+        if self.is_converged() {
+            self.stats = FrameStats::default();
+            return false;
+        }
+
+        self.emitters.clear();
+        self.emitters.extend(scene.emissive_spheres());
+
+        if self.samples == 1 {
+            self.accumulation_data.clear();
+            self.sample_counts.fill(0);
+
+            if self.mode == RenderMode::FastPreview {
+                self.sky_irradiance = ShIrradiance::from_radiance(|direction| {
+                    let direction = Vec3A::from(direction);
+                    let ray = Ray {
+                        origin: Vec3A::ZERO,
+                        direction,
+                        inv_direction: 1.0 / direction,
+                    };
+                    sky_color(scene, &ray, camera.world_up().into())
+                });
+            }
+        }
+
+        if self.is_moving && self.preview_scale > 1 {
+            self.render_preview(camera, scene);
+            return false;
+        }
+
+        self.progress = FrameProgress {
+            min_sample_count: u32::MAX,
+            ..default()
+        };
+        true
+    }
+
+    /// Renders a range of the tiles of the current frame and copies them to the image
+    fn render_batch(
+        &mut self,
+        camera: &CustomCamera,
+        scene: &Scene,
+        batch: Range<usize>,
+        frame_seed: u32,
+        needs_resolve: bool,
+    ) {
+        let rendered_tiles: Vec<_> = self.tiles[batch]
+            .par_iter()
+            // This block runs in parallel for every tile
+            .map(|tile| {
+                let mut stats = FrameStats::default();
+                let pixels: Vec<_> = tile
+                    .pixel_indices(self.width)
+                    .map(|pixel_index| {
+                        let sample_count = self.sample_counts[pixel_index];
+                        let accumulated_pixel =
+                            self.accumulation_data.sum(pixel_index, sample_count);
+                        let previous_color = accumulated_pixel / sample_count.max(1) as f32;
+
+                        // Only pixels that were partially reset still need samples
+                        if self.pixel_is_converged(sample_count) {
+                            let first_hit = self.first_hit(pixel_index);
+                            return (accumulated_pixel, sample_count, None, 0.0, first_hit);
+                        }
+
+                        // The object under the pixel doesn't change until the next reset
+                        let first_hit = if sample_count == 0 {
+                            FirstHit::trace(camera, scene, pixel_index)
+                        } else {
+                            self.first_hit(pixel_index)
+                        };
+
+                        let mut color = Vec4::ZERO;
+                        let mut valid_samples = 0;
+                        for ray in 0..self.rays_per_pixel as u32 {
+                            let seed = SampleSeed {
+                                frame_seed,
+                                sampler: self.sampler,
+                                index: sample_count * self.rays_per_pixel as u32 + ray,
+                                scramble: self.frame_seed_offset,
+                                jitter: self.anti_aliasing.then_some(self.pixel_filter),
+                            };
+                            let (sample, path_length) =
+                                self.trace_sample(camera, scene, pixel_index, seed);
+                            stats.add_path(path_length);
+                            // A single inf or NaN would stay in the accumulation until the next reset
+                            if sample.is_finite() {
+                                color += match self.firefly_clamp {
+                                    Some(max_luminance) => clamp_luminance(sample, max_luminance),
+                                    None => sample,
+                                };
+                                valid_samples += 1;
+                            }
+                        }
+                        let color = if valid_samples > 0 {
+                            color / valid_samples as f32
+                        } else {
+                            // Keeps the average unchanged instead of darkening the pixel
+                            previous_color
+                        };
+
+                        // accumulate the color over multiple frames
+                        let accumulated_pixel = accumulated_pixel + color;
+                        let sample_count = sample_count + 1;
+                        let accumulated_color = accumulated_pixel / sample_count as f32;
+
+                        let pixel = (!needs_resolve).then(|| {
+                            self.output_color_space
+                                .encode(self.tonemapping.apply(accumulated_color, self.white_point))
+                        });
+
+                        // how much this frame changed the pixel
+                        let delta = (accumulated_color - previous_color).truncate().abs();
+                        (
+                            accumulated_pixel,
+                            sample_count,
+                            pixel,
+                            (delta.x + delta.y + delta.z) / 3.0,
+                            first_hit,
+                        )
+                    })
+                    .collect();
+                (*tile, pixels, stats)
+            })
+            .collect();
+
+        for (tile, pixels, stats) in rendered_tiles {
+            self.progress.stats.merge(&stats);
+            for (pixel_index, (accumulated_pixel, sample_count, pixel, delta, first_hit)) in
+                tile.pixel_indices(self.width).zip(pixels)
+            {
+                self.id_data[pixel_index] = first_hit.object_id;
+                self.albedo_data[pixel_index] = first_hit.albedo;
+                self.normal_data[pixel_index] = first_hit.normal;
+                self.depth_data[pixel_index] = first_hit.depth;
+                self.accumulation_data
+                    .set_sum(pixel_index, accumulated_pixel, sample_count);
+                self.sample_counts[pixel_index] = sample_count;
+                if let Some(pixel) = pixel {
+                    self.image_data[pixel_index] = pixel;
+                }
+                self.progress.noise_sum += delta;
+                self.progress.min_sample_count = self.progress.min_sample_count.min(sample_count);
+            }
+        }
+    }
+
+    /// Traces one sample of a pixel with the current [`RenderMode`].
+    ///
+    /// Returns the color and the number of surfaces hit.
+    fn trace_sample(
+        &self,
+        camera: &CustomCamera,
+        scene: &Scene,
+        pixel_index: usize,
+        seed: SampleSeed,
+    ) -> (Vec4, u32) {
+        match self.mode {
+            RenderMode::PathTraced => per_pixel(
+                scene,
+                &self.emitters,
+                camera,
+                pixel_index,
+                self.bounce_budget(),
+                seed,
+                self.transparent_background,
+                self.caustic_boost,
+                None,
+            ),
+            RenderMode::FastPreview => fast_preview_pixel(
+                scene,
+                camera,
+                pixel_index,
+                &self.sky_irradiance,
+                self.transparent_background,
+            ),
+        }
+    }
+
+    /// Traces a single sample per block of `preview_scale` by `preview_scale` pixels and fills the
+    /// whole block with it.
+    ///
+    /// Nothing is accumulated, the first frame after the camera stops starts over at full
+    /// resolution.
+    fn render_preview(&mut self, camera: &CustomCamera, scene: &Scene) {
+        let scale = self.preview_scale as usize;
+        let (width, height) = (self.width, self.height);
+        let block_corners: Vec<usize> = (0..height)
+            .step_by(scale)
+            .flat_map(|y| (0..width).step_by(scale).map(move |x| y * width + x))
+            .collect();
+        let seed = SampleSeed {
+            frame_seed: (self.samples as u32).wrapping_add(self.frame_seed_offset),
+            sampler: Sampler::Random,
+            index: 0,
+            scramble: self.frame_seed_offset,
+            jitter: None,
+        };
+
+        let pixels: Vec<_> = block_corners
+            .par_iter()
+            .map(|pixel_index| {
+                let (color, _) = self.trace_sample(camera, scene, *pixel_index, seed);
+                let color = if color.is_finite() { color } else { Vec4::ZERO };
+                self.output_color_space
+                    .encode(self.tonemapping.apply(color, self.white_point))
+            })
+            .collect();
+
+        for (pixel_index, pixel) in block_corners.into_iter().zip(pixels) {
+            let (x, y) = (pixel_index % width, pixel_index / width);
+            for row in y..(y + scale).min(height) {
+                let row_start = row * width;
+                self.image_data[row_start + x..row_start + (x + scale).min(width)].fill(pixel);
+            }
+        }
+        self.stats = FrameStats::default();
+        self.noise_estimate = None;
+    }
+
+    pub fn settings(&self) -> RendererSettings {
+        RendererSettings {
+            accumulate: self.accumulate,
+            bounces: self.bounces,
+            specular_bounces: self.specular_bounces,
+            rays_per_pixel: self.rays_per_pixel,
+            transparent_background: self.transparent_background,
+            mode: self.mode,
+            max_samples: self.max_samples,
+            lens_flare: self.lens_flare,
+            denoiser: self.denoiser,
+            sampler: self.sampler,
+            output_color_space: self.output_color_space,
+            tonemapping: self.tonemapping,
+            white_point: self.white_point,
+            output: self.output,
+            firefly_clamp: self.firefly_clamp,
+            anti_aliasing: self.anti_aliasing,
+            frame_seed_offset: self.frame_seed_offset,
+            caustic_boost: self.caustic_boost,
+            pixel_filter: self.pixel_filter,
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: &RendererSettings) {
+        self.accumulate = settings.accumulate;
+        self.bounces = settings.bounces;
+        self.specular_bounces = settings.specular_bounces;
+        self.rays_per_pixel = settings.rays_per_pixel;
+        self.transparent_background = settings.transparent_background;
+        self.mode = settings.mode;
+        self.max_samples = settings.max_samples;
+        self.lens_flare = settings.lens_flare;
+        self.denoiser = settings.denoiser;
+        self.sampler = settings.sampler;
+        self.output_color_space = settings.output_color_space;
+        self.tonemapping = settings.tonemapping;
+        self.white_point = settings.white_point;
+        self.output = settings.output;
+        self.firefly_clamp = settings.firefly_clamp;
+        self.anti_aliasing = settings.anti_aliasing;
+        self.frame_seed_offset = settings.frame_seed_offset;
+        self.caustic_boost = settings.caustic_boost;
+        self.pixel_filter = settings.pixel_filter;
+        self.reset_frame_index();
+    }
+
+    /// Recomputes the whole displayed image from the accumulation, applying the post effects, the
+    /// tonemapping and the output color space
+    pub fn resolve_image(&mut self) {
+        match self.output {
+            RenderOutput::Beauty => {}
+            RenderOutput::ObjectId => {
+                for (pixel, id) in self.image_data.iter_mut().zip(&self.id_data) {
+                    *pixel = object_id_color(*id);
+                }
+                return;
+            }
+            RenderOutput::Albedo => {
+                for (pixel, albedo) in self.image_data.iter_mut().zip(&self.albedo_data) {
+                    *pixel = self.output_color_space.encode(albedo.extend(1.0));
+                }
+                return;
+            }
+            RenderOutput::Normal => {
+                for (pixel, normal) in self.image_data.iter_mut().zip(&self.normal_data) {
+                    *pixel = if *normal == Vec3::ZERO {
+                        [0, 0, 0, 255]
+                    } else {
+                        (*normal * 0.5 + 0.5).extend(1.0).as_u8_array()
+                    };
+                }
+                return;
+            }
+            RenderOutput::Depth => {
+                let max_depth = self
+                    .depth_data
+                    .iter()
+                    .copied()
+                    .filter(|depth| depth.is_finite())
+                    .fold(0.0, f32::max);
+                for (pixel, depth) in self.image_data.iter_mut().zip(&self.depth_data) {
+                    let value = if depth.is_finite() && max_depth > 0.0 {
+                        1.0 - depth / max_depth
+                    } else {
+                        0.0
+                    };
+                    *pixel = Vec3::splat(value).extend(1.0).as_u8_array();
+                }
+                return;
+            }
+        }
+
+        let mut hdr: Vec<_> = (0..self.image_data.len())
+            .map(|pixel_index| self.averaged_pixel(pixel_index))
+            .collect();
+        if self.denoiser.enabled {
+            self.denoiser.apply(
+                &mut hdr,
+                &self.albedo_data,
+                &self.normal_data,
+                &self.depth_data,
+                self.width,
+                self.height,
+            );
+        }
+        if self.lens_flare.enabled {
+            self.lens_flare.apply(&mut hdr, self.width, self.height);
+        }
+        for (pixel, color) in self.image_data.iter_mut().zip(hdr) {
+            *pixel = self
+                .output_color_space
+                .encode(self.tonemapping.apply(color, self.white_point));
+        }
+    }
+
+    /// Returns the average color of the pixels showing an object and the largest difference
+    /// between a channel of one of them and `expected`, `None` if no object is visible.
+    ///
+    /// The background shows the sky directly, so it would match `expected` even if the materials
+    /// lost or created energy.
+    pub fn compare_to_color(&self, expected: Vec3) -> Option<(Vec3, f32)> {
+        let mut sum = Vec3::ZERO;
+        let mut count = 0;
+        let mut max_error: f32 = 0.0;
+        for pixel_index in 0..self.sample_counts.len() {
+            if self.id_data[pixel_index] == 0 {
+                continue;
+            }
+            let color = self.averaged_pixel(pixel_index).truncate();
+            sum += color;
+            count += 1;
+            max_error = max_error.max((color - expected).abs().max_element());
+        }
+        (count > 0).then(|| (sum / count as f32, max_error))
+    }
+
+    /// Memory used by the buffers that have one element per pixel, in bytes
+    pub fn buffers_size(&self) -> usize {
+        self.accumulation_data.size()
+            + self.sample_counts.len() * std::mem::size_of::<u32>()
+            + self.image_data.len() * std::mem::size_of::<[u8; 4]>()
+            + self.id_data.len() * std::mem::size_of::<u32>()
+            + (self.albedo_data.len() + self.normal_data.len()) * std::mem::size_of::<Vec3>()
+            + self.depth_data.len() * std::mem::size_of::<f32>()
+    }
+
+    fn first_hit(&self, pixel_index: usize) -> FirstHit {
+        FirstHit {
+            object_id: self.id_data[pixel_index],
+            albedo: self.albedo_data[pixel_index],
+            normal: self.normal_data[pixel_index],
+            depth: self.depth_data[pixel_index],
+        }
+    }
+
+    /// Whether every pixel accumulated `max_samples` frames
+    pub fn is_converged(&self) -> bool {
+        self.samples > 1 && self.pixel_is_converged(self.min_sample_count)
+    }
+
+    fn pixel_is_converged(&self, sample_count: u32) -> bool {
+        self.accumulate
+            && self
+                .sample_limit()
+                .is_some_and(|max_samples| sample_count as usize >= max_samples)
+    }
+
+    /// The number of frames accumulated before stopping, `None` if there's no limit
+    pub fn sample_limit(&self) -> Option<usize> {
+        self.max_samples.filter(|max_samples| *max_samples > 0)
+    }
+
+    /// The average of all the frames accumulated in a pixel, before clamping
+    pub fn averaged_pixel(&self, pixel_index: usize) -> Vec4 {
+        self.accumulation_data
+            .average(pixel_index, self.sample_counts[pixel_index])
+    }
+
+    /// Traces one path for a single pixel, with the seed of the next frame, and records every vertex
+    pub fn debug_path(
+        &self,
+        scene: &Scene,
+        camera: &CustomCamera,
+        pixel_index: usize,
+    ) -> Vec<PathVertex> {
+        let mut path = vec![];
+        if pixel_index < camera.ray_directions.len() {
+            let seed = SampleSeed {
+                frame_seed: (self.samples as u32).wrapping_add(self.frame_seed_offset),
+                sampler: self.sampler,
+                index: self.sample_counts[pixel_index] * self.rays_per_pixel as u32,
+                scramble: self.frame_seed_offset,
+                jitter: self.anti_aliasing.then_some(self.pixel_filter),
+            };
+            // The path can be debugged before the first frame of the scene is rendered
+            let emitters: Vec<usize> = scene.emissive_spheres().collect();
+            per_pixel(
+                scene,
+                &emitters,
+                camera,
+                pixel_index,
+                self.bounce_budget(),
+                seed,
+                self.transparent_background,
+                self.caustic_boost,
+                Some(&mut path),
+            );
+        }
+        path
+    }
+
+    fn bounce_budget(&self) -> BounceBudget {
+        BounceBudget {
+            diffuse: self.bounces,
+            specular: self.specular_bounces,
+        }
+    }
+
+    /// Resets the frame index.
+    /// This will force the renderer to reset the accumulation date and start accumulating again.
+    /// The per-pixel sample counts are cleared with the accumulation at the start of the next frame.
+    pub fn reset_frame_index(&mut self) {
+        self.samples = 1;
+        self.min_sample_count = 0;
+        // The next frame starts over from the center
+        self.progress.next_tile = 0;
+    }
+
+    /// Resets the accumulation of the pixels inside a rectangle in normalized device coordinates,
+    /// the rest of the image stays converged.
+    pub fn reset_region(&mut self, ndc_min: Vec2, ndc_max: Vec2) {
+        let size = Vec2::new(self.width as f32, self.height as f32);
+        // NDC goes up but the rows of the image go down
+        let to_pixel = |ndc: Vec2| Vec2::new(ndc.x + 1.0, 1.0 - ndc.y) / 2.0 * size;
+        let min = to_pixel(Vec2::new(ndc_min.x, ndc_max.y))
+            .floor()
+            .clamp(Vec2::ZERO, size);
+        let max = to_pixel(Vec2::new(ndc_max.x, ndc_min.y))
+            .ceil()
+            .clamp(Vec2::ZERO, size);
+
+        for y in min.y as usize..max.y as usize {
+            for x in min.x as usize..max.x as usize {
+                let pixel_index = y * self.width + x;
+                self.accumulation_data.set_sum(pixel_index, Vec4::ZERO, 0);
+                self.sample_counts[pixel_index] = 0;
+            }
+        }
+        self.min_sample_count = 0;
+        // The tiles already rendered in the current frame were counted before the reset
+        self.progress.min_sample_count = 0;
+    }
+
+    /// Resets the accumulation after an object moved, using its bounding sphere before and after
+    /// the move. Everything is reset unless `partial_reset` is enabled.
+    pub fn reset_moved_object(
+        &mut self,
+        camera: &CustomCamera,
+        before: Option<(Vec3, f32)>,
+        after: Option<(Vec3, f32)>,
+    ) {
+        if !self.partial_reset {
+            self.reset_frame_index();
+            return;
+        }
+        for bounds in [before, after] {
+            let Some((ndc_min, ndc_max)) =
+                bounds.and_then(|(center, radius)| camera.project_bounding_sphere(center, radius))
+            else {
+                // The object is behind the camera or crosses it, it could cover anything
+                self.reset_frame_index();
+                return;
+            };
+            self.reset_region(ndc_min, ndc_max);
+        }
+    }
+}
+
+/// Computes the color of the sky in the direction of the ray.
+/// `up` is the world up axis of the camera, it points toward the zenith.
+///
+/// Reference:
+/// * Sebastian Lague: https://youtu.be/Qz0KTGYJtUk?t=1207
+fn sky_color(scene: &Scene, ray: &Ray, up: Vec3A) -> Vec3 {
+    sky_background(scene, ray.direction, up) + sun_radiance(scene, ray.direction, up)
+}
+
+/// The color of the sky without the sun of [`SkyMode::Gradient`], see [`sky_color`]
+fn sky_background(scene: &Scene, direction: Vec3A, up: Vec3A) -> Vec3 {
+    let height = direction.dot(up);
+    let color = match scene.sky.mode {
+        SkyMode::Gradient => sky_gradient(scene, height),
+        SkyMode::Solid(color) => color,
+        SkyMode::Equirect => scene
+            .sky
+            .environment
+            .as_ref()
+            .map_or(Vec3::ZERO, |map| map.sample(direction, up)),
+    };
+    Vec3::lerp(
+        color,
+        scene.fog.color,
+        1.0 - sky_fog_transmittance(scene, height),
+    )
+}
+
+/// Fraction of the light of the sky that goes through the fog, `height` is the cosine between the
+/// ray and the up axis
+fn sky_fog_transmittance(scene: &Scene, height: f32) -> f32 {
+    // Rays close to the horizon travel further through the fog
+    let sky_distance = Fog::SKY_DISTANCE / height.abs().max(0.01);
+    scene.fog.transmittance(sky_distance)
+}
+
+/// Warm white, the sun is a bit yellow once its light went through the atmosphere
+const SUN_COLOR: Vec3 = Vec3::new(1.0, 0.9, 0.75);
+
+/// `height` is the cosine between the ray and the up axis
+fn sky_gradient(scene: &Scene, height: f32) -> Vec3 {
+    let sky_gradient_t = smoothstep(0.0, 0.4, height).powf(0.35);
+    let sky_gradient = Vec3::lerp(
+        scene.sky.horizon_color,
+        scene.sky.zenith_color,
+        sky_gradient_t,
+    );
+
+    let ground_to_sky_t = smoothstep(-0.01, 0.0, height);
+    Vec3::lerp(scene.sky.ground_color, sky_gradient, ground_to_sky_t)
+}
+
+/// The direction toward the sun, `None` when the sky has no sun
+fn sun_direction(scene: &Scene) -> Option<Vec3A> {
+    if scene.sky.mode != SkyMode::Gradient || scene.sky.sun_intensity <= 0.0 {
+        return None;
+    }
+    // The direction isn't normalized when it's edited
+    Vec3A::from(scene.sky.sun_direction).try_normalize()
+}
+
+/// Light coming from the sun in a direction, it's a cosine lobe around [`Sky::sun_direction`]
+/// drawn on top of the sky.
+///
+/// [`Sky::sun_direction`]: crate::scene::Sky::sun_direction
+fn sun_radiance(scene: &Scene, direction: Vec3A, up: Vec3A) -> Vec3 {
+    let Some(sun_direction) = sun_direction(scene) else {
+        return Vec3::ZERO;
+    };
+    let height = direction.dot(up);
+    let sun = direction
+        .dot(sun_direction)
+        .max(0.0)
+        .powf(scene.sky.sun_focus.max(0.0))
+        * scene.sky.sun_intensity
+        * SUN_COLOR;
+    // The ground hides the part of the sun below the horizon
+    sun * smoothstep(-0.01, 0.0, height) * sky_fog_transmittance(scene, height)
+}
+
+/// Probability density per solid angle of [`sample_sun`] choosing `direction`.
+/// The directions are distributed like the cosine lobe of the sun.
+fn sun_pdf(scene: &Scene, direction: Vec3A) -> f32 {
+    let Some(sun_direction) = sun_direction(scene) else {
+        return 0.0;
+    };
+    let focus = scene.sky.sun_focus.max(0.0);
+    (focus + 1.0) / TAU * direction.dot(sun_direction).max(0.0).powf(focus)
+}
+
+/// Returns the closest object under a point of the viewport in normalized device coordinates
+pub fn pick(scene: &Scene, camera: &CustomCamera, ndc: Vec2) -> Option<ObjectRef> {
+    trace_ray(&ndc_ray(camera, ndc), scene, camera.clip_range()).map(|payload| payload.object)
+}
+
+/// What the camera ray under a point of the viewport hits, shown by the hover probe
+#[derive(Debug, Clone, Copy)]
+pub struct ProbeHit {
+    pub position: Vec3,
+    pub material_id: usize,
+    pub distance: f32,
+}
+
+/// Like [`pick`], but returns where the hit is instead of the object
+pub fn probe(scene: &Scene, camera: &CustomCamera, ndc: Vec2) -> Option<ProbeHit> {
+    trace_ray(&ndc_ray(camera, ndc), scene, camera.clip_range()).map(|payload| ProbeHit {
+        position: payload.world_position,
+        material_id: payload.material_id,
+        distance: payload.hit_distance,
+    })
+}
+
+/// Whether a ray hits an AABB, with the same test the renderer uses before checking the triangles
+pub fn ray_hits_aabb(origin: Vec3, direction: Vec3, aabb: Aabb) -> bool {
+    let ray = Ray {
+        origin: origin.into(),
+        direction: direction.into(),
+        inv_direction: 1.0 / Vec3A::from(direction),
+    };
+    aabb_intersect(&ray, aabb, f32::INFINITY)
+}
+
+fn ndc_ray(camera: &CustomCamera, ndc: Vec2) -> Ray {
+    let direction = camera.ndc_to_ray_direction(ndc);
+    Ray {
+        origin: camera.ndc_to_ray_origin(ndc),
+        direction,
+        inv_direction: 1.0 / direction,
+    }
+}
+
+fn camera_ray(camera: &CustomCamera, pixel_index: usize) -> Ray {
+    Ray {
+        origin: camera.ray_origin(pixel_index),
+        direction: camera.ray_directions[pixel_index],
+        inv_direction: camera.inv_ray_directions[pixel_index],
+    }
+}
+
+/// Shades the first hit with the sky irradiance instead of tracing more bounces.
+/// This ignores shadows and the light bounced by other objects.
+///
+/// Returns the color and the number of surfaces hit, like [`per_pixel`].
+fn fast_preview_pixel(
+    scene: &Scene,
+    camera: &CustomCamera,
+    pixel_index: usize,
+    sky_irradiance: &ShIrradiance,
+    transparent_background: bool,
+) -> (Vec4, u32) {
+    let ray = camera_ray(camera, pixel_index);
+    let Some(mut payload) = trace_ray(&ray, scene, camera.clip_range()) else {
+        if transparent_background {
+            return (Vec4::ZERO, 0);
+        }
+        return (
+            sky_color(scene, &ray, camera.world_up().into()).extend(1.0),
+            0,
+        );
+    };
+
+    let material = payload.apply_textures(scene);
+    let diffuse = material.albedo * sky_irradiance.irradiance(payload.world_normal) / PI;
+    let emission = material.get_emission()
+        + scene
+            .emissive_override(payload.object)
+            .unwrap_or(Vec3::ZERO);
+    let color = diffuse + emission;
+
+    let fog_transmittance = scene.fog.transmittance(payload.hit_distance);
+    let color = color
+        .lerp(scene.fog.color, 1.0 - fog_transmittance)
+        .extend(1.0);
+    (color, 1)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn per_pixel(
+    scene: &Scene,
+    emitters: &[usize],
+    camera: &CustomCamera,
+    pixel_index: usize,
+    mut budget: BounceBudget,
+    sample_seed: SampleSeed,
+    transparent_background: bool,
+    caustic_boost: bool,
+    mut path: Option<&mut Vec<PathVertex>>,
+) -> (Vec4, u32) {
+    let mut ray = camera_ray(camera, pixel_index);
+
+    // Every part is hashed so the pixels, the frames and the rays of a pixel get unrelated seeds,
+    // while the same frame of the same scene still renders the exact same image
+    let sample_hash =
+        random::pcg_hash(sample_seed.frame_seed ^ random::pcg_hash(sample_seed.index));
+    // The frame seed of a frame is also the one of an earlier frame with a higher seed offset, so
+    // the offset is mixed in again for the jitter and the lens to change with it
+    let mut camera_rng = PcgHashRng::new(random::pcg_hash(
+        pixel_index as u32 ^ sample_hash ^ random::pcg_hash(sample_seed.scramble),
+    ));
+    let mut camera_sample = |dimension: SampleDimension| {
+        // Every pixel walks the sequence of each dimension with a different rotation
+        let mut rotation_rng = PcgHashRng::new(
+            random::pcg_hash(pixel_index as u32)
+                ^ random::pcg_hash(dimension.seed() ^ sample_seed.scramble),
+        );
+        let rotation = Vec2::new(rotation_rng.gen(), rotation_rng.gen());
+        sample_seed
+            .sampler
+            .sample_2d(sample_seed.index, dimension, rotation, &mut camera_rng)
+    };
+    if let Some(filter) = sample_seed.jitter {
+        let jitter = filter.sample(camera_sample(SampleDimension::PixelJitter));
+        (ray.origin, ray.direction) = camera.jittered_ray(pixel_index, jitter);
+    }
+    if camera.aperture > 0.0 {
+        let lens_sample = camera_sample(SampleDimension::Lens);
+        (ray.origin, ray.direction) = camera.thin_lens_ray(ray.origin, ray.direction, lens_sample);
+    }
+    ray.inv_direction = 1.0 / ray.direction;
+    let up = Vec3A::from(camera.world_up());
+    // How much of the light found further along the path reaches the camera
+    let mut throughput = Vec3::ONE;
+    let mut light = Vec3::ZERO;
+    // Number of surfaces hit, reported in the stats
+    let mut path_length = 0;
+
+    let mut seed = random::pcg_hash(pixel_index as u32 ^ sample_hash);
+
+    let mut is_camera_ray = true;
+    // Whether the last bounce sampled the emissive spheres directly
+    let mut emitters_sampled = false;
+    // Probability density of the direction of the last bounce when it was diffuse, the sun was then
+    // also sampled directly and the two are weighted, see [`sample_sun`]
+    let mut sun_sampled_pdf = None;
+    // Bounces counted against the diffuse budget so far
+    let mut diffuse_bounces = 0;
+    loop {
+        seed = seed.wrapping_add(1);
+        let mut rng = PcgHashRng::new(seed);
+
+        // Only camera rays are clipped, bounces can hit anything
+        let hit_distance_range = if is_camera_ray {
+            camera.clip_range()
+        } else {
+            0.001..=f32::INFINITY
+        };
+        if let Some(mut payload) = trace_ray(&ray, scene, hit_distance_range) {
+            path_length += 1;
+            let material = payload.apply_textures(scene);
+
+            // fog in front of the hit scatters its own color toward the camera
+            let fog_transmittance = scene.fog.transmittance(payload.hit_distance);
+            light += scene.fog.color * (1.0 - fog_transmittance) * throughput;
+            throughput *= fog_transmittance;
+
+            // The light sampling of the previous bounce already counted the emissive spheres
+            let emission_sampled = emitters_sampled
+                && matches!(payload.object, ObjectRef::Sphere(i) if scene.is_emissive_sphere(i));
+            if !emission_sampled {
+                light += throughput * material.get_emission();
+                if let Some(emission) = scene.emissive_override(payload.object) {
+                    light += throughput * emission;
+                }
+            }
+
+            // The material replaces the bounce count of the renderer, it can raise or lower it
+            let max_diffuse_bounces = material.max_bounce.unwrap_or(budget.diffuse);
+            let mut bounce_again = diffuse_bounces < max_diffuse_bounces || budget.specular > 0;
+            if bounce_again {
+                let diffuse_sample = if sample_seed.sampler == Sampler::Random {
+                    Vec2::new(rng.gen(), rng.gen())
+                } else {
+                    // Every pixel and bounce walks the same sequence with a different rotation
+                    let mut rotation_rng = PcgHashRng::new(
+                        random::pcg_hash(pixel_index as u32)
+                            ^ random::pcg_hash(path_length ^ sample_seed.scramble),
+                    );
+                    let rotation = Vec2::new(rotation_rng.gen(), rotation_rng.gen());
+                    sample_seed.sampler.sample_2d(
+                        sample_seed.index,
+                        SampleDimension::Bounce,
+                        rotation,
+                        &mut rng,
+                    )
+                };
+                let bounce = scatter(&material, &ray, &payload, &mut rng, diffuse_sample);
+                throughput *= bounce.attenuation;
+
+                // Mirror chains need many bounces to resolve but each one is cheap to converge,
+                // so they don't count against the budget of the diffuse bounces
+                if bounce.specular && material.alpha() < NEAR_SPECULAR_ALPHA && budget.specular > 0
+                {
+                    budget.specular -= 1;
+                } else if diffuse_bounces < max_diffuse_bounces {
+                    diffuse_bounces += 1;
+                } else {
+                    bounce_again = false;
+                }
+
+                // The lights are too small to be found by the bounces, so they are sampled directly.
+                // The attenuation of the diffuse lobe is the albedo, like the lights need.
+                if !bounce.specular {
+                    light += throughput * direct_light(scene, &payload);
+                    light += throughput
+                        * sample_emissive_spheres(
+                            scene,
+                            emitters,
+                            &payload,
+                            caustic_boost,
+                            &mut rng,
+                        );
+                    light += throughput
+                        * sample_sun(scene, &payload, up, bounce_again, caustic_boost, &mut rng);
+                }
+                emitters_sampled = !bounce.specular;
+                sun_sampled_pdf = (!bounce.specular)
+                    .then(|| bounce.direction.dot(payload.world_normal.into()) / PI);
+
+                // Refracted rays start on the other side of the surface
+                let side = bounce.direction.dot(payload.world_normal.into()).signum();
+                ray = Ray {
+                    origin: (payload.world_position + payload.world_normal * 0.0001 * side).into(),
+                    direction: bounce.direction,
+                    inv_direction: 1.0 / bounce.direction,
+                };
+            }
+
+            if let Some(path) = &mut path {
+                path.push(PathVertex {
+                    position: payload.world_position,
+                    material_id: Some(payload.material_id),
+                    throughput,
+                    radiance: light,
+                });
+            }
+            if !bounce_again {
+                break;
+            }
+            is_camera_ray = false;
+        } else {
+            if is_camera_ray && transparent_background {
+                return (Vec4::ZERO, 0);
+            }
+            let sun_weight = sun_sampled_pdf.map_or(1.0, |pdf| {
+                power_heuristic(pdf, sun_pdf(scene, ray.direction))
+            });
+            let sky = sky_background(scene, ray.direction, up)
+                + sun_radiance(scene, ray.direction, up) * sun_weight;
+            light += sky * throughput;
+            if let Some(path) = &mut path {
+                path.push(PathVertex {
+                    position: (ray.origin + ray.direction).into(),
+                    material_id: None,
+                    throughput,
+                    radiance: light,
+                });
+            }
+            break;
+        }
+    }
+    (light.extend(1.0), path_length)
+}
 
-        self.reset_frame_index();
+/// Scales the color down to `max_luminance` while keeping its hue
+fn clamp_luminance(color: Vec4, max_luminance: f32) -> Vec4 {
+    let luminance = luminance(color.truncate());
+    if luminance <= max_luminance {
+        return color;
     }
+    (color.truncate() * max_luminance / luminance).extend(color.w)
+}
 
-    pub fn render(&mut self, camera: &CustomCamera, scene: &Scene) {
-        if self.samples == 1 {
-            self.accumulation_data.fill(Vec4::ZERO);
-        }
-
-        self.image_data
-            .par_iter_mut()
-            .zip(&mut self.accumulation_data)
-            .enumerate()
-            // This block runs in parallel for every pixel
-            .for_each(|(pixel_index, (pixel, accumulated_pixel))| {
-                let mut color = Vec4::ZERO;
-                for _ in 0..self.rays_per_pixel {
-                    color += per_pixel(scene, camera, pixel_index, self.bounces, self.samples);
-                }
-                color /= self.rays_per_pixel as f32;
+/// A ray bouncing off a surface, sampled by [`scatter`]
+struct Bounce {
+    /// Multiplies the light found by the rest of the path
+    attenuation: Vec3,
+    direction: Vec3A,
+    /// Whether the specular lobe was chosen instead of the diffuse one
+    specular: bool,
+}
 
-                // accumulate the color over multiple frames
-                *accumulated_pixel += color;
+/// Randomly chooses between the specular and the diffuse lobes of a material, and samples the
+/// direction of the bounce in that lobe.
+///
+/// Metals only reflect, tinted by their albedo. Dielectrics reflect some of the light on their
+/// surface without tinting it, weighted by Fresnel, and the rest is diffused with their albedo.
+/// `diffuse_sample` is the point of the unit square mapped to the diffuse direction.
+fn scatter(
+    material: &Material,
+    ray: &Ray,
+    payload: &HitPayload,
+    rng: &mut PcgHashRng,
+    diffuse_sample: Vec2,
+) -> Bounce {
+    let normal = Vec3A::from(payload.world_normal);
+    let mut attenuation = Vec3::ONE;
+    let reflected_by_film = material.film_thickness > 0.0
+        && sample_thin_film(material, ray.direction, normal, &mut attenuation, rng);
+    let specular = if reflected_by_film {
+        true
+    } else if rng.gen::<f32>() < material.metallic {
+        attenuation *= material.albedo;
+        true
+    } else if rng.gen::<f32>() < material.transmission {
+        return scatter_transmission(material, ray.direction, normal, rng);
+    } else {
+        let cos_theta = normal.dot(-ray.direction);
+        let reflected = rng.gen::<f32>() < fresnel_schlick(cos_theta, material.f0());
+        if !reflected {
+            attenuation *= material.albedo;
+        }
+        reflected
+    };
 
-                let mut accumulated_color = *accumulated_pixel;
-                accumulated_color /= self.samples as f32;
+    let direction = if specular {
+        specular_direction(material, ray.direction, normal, rng)
+    } else {
+        random::cosine_weighted_hemisphere(normal, diffuse_sample)
+    };
+    Bounce {
+        attenuation,
+        direction,
+        specular,
+    }
+}
 
-                let color = accumulated_color.clamp(Vec4::ZERO, Vec4::ONE);
-                *pixel = color.as_u8_array();
-            });
+/// Randomly reflects or refracts the ray according to the Fresnel reflectance of the surface.
+///
+/// The normal of a closed object always points outside, so it's flipped when the ray leaves the
+/// object, along with the ratio of indices of refraction.
+fn scatter_transmission(
+    material: &Material,
+    direction: Vec3A,
+    normal: Vec3A,
+    rng: &mut PcgHashRng,
+) -> Bounce {
+    let (normal, eta) = if direction.dot(normal) < 0.0 {
+        (normal, 1.0 / material.ior)
+    } else {
+        (-normal, material.ior)
+    };
+    let f0 = ((1.0 - material.ior) / (1.0 + material.ior)).powi(2);
+    let reflectance = fresnel_schlick(normal.dot(-direction), f0);
 
-        if self.accumulate {
-            self.samples += 1;
-        } else {
-            self.samples = 1;
-        }
+    match refract(direction, normal, eta) {
+        Some(refracted) if rng.gen::<f32>() >= reflectance => Bounce {
+            attenuation: material.albedo,
+            direction: refracted,
+            specular: true,
+        },
+        // Total internal reflection ends up here too
+        _ => Bounce {
+            attenuation: Vec3::ONE,
+            direction: reflect(direction, normal),
+            specular: true,
+        },
     }
+}
 
-    /// Resets the frame index.
-    /// This will force the renderer to reset the accumulation date and start accumulating again.
-    pub fn reset_frame_index(&mut self) {
-        self.samples = 1;
+/// Randomly chooses between the light reflected by the thin film coating and the light going
+/// through it to the base material, and weights the contribution accordingly.
+///
+/// Returns `true` if the light is reflected by the film.
+fn sample_thin_film<R: Rng>(
+    material: &Material,
+    direction: Vec3A,
+    normal: Vec3A,
+    contribution: &mut Vec3,
+    rng: &mut R,
+) -> bool {
+    let reflectance = thin_film_reflectance(
+        normal.dot(-direction),
+        material.film_thickness,
+        material.film_ior,
+        Material::FILM_SUBSTRATE_IOR,
+    );
+    let probability = ((reflectance.x + reflectance.y + reflectance.z) / 3.0).clamp(0.01, 0.99);
+    if rng.gen::<f32>() < probability {
+        *contribution *= reflectance / probability;
+        true
+    } else {
+        *contribution *= (Vec3::ONE - reflectance) / (1.0 - probability);
+        false
     }
 }
 
-/// Computes a color gradient simulating a sky
+/// Samples a reflection direction around the mirror direction using a GGX microfacet distribution.
 ///
 /// Reference:
-/// * Sebastian Lague: https://youtu.be/Qz0KTGYJtUk?t=1207
-fn sky_color(scene: &Scene, ray: &Ray) -> Vec3 {
-    let sky_gradient_t = smoothstep(0.0, 0.4, ray.direction.y).powf(0.35);
-    let sky_gradient = Vec3::lerp(
-        scene.sky.horizon_color,
-        scene.sky.zenith_color,
-        sky_gradient_t,
-    );
+/// * Disney anisotropic parameterization: https://media.disneyanimation.com/uploads/production/publication_asset/48/asset/s2012_pbs_disney_brdf_notes_v3.pdf
+fn specular_direction<R: Rng>(
+    material: &Material,
+    direction: Vec3A,
+    normal: Vec3A,
+    rng: &mut R,
+) -> Vec3A {
+    let alpha = material.alpha().max(0.001);
+    let aspect = (1.0 - 0.9 * material.anisotropy).sqrt();
+    let microfacet_normal = random::ggx_microfacet_normal(rng, alpha / aspect, alpha * aspect);
 
-    // FIXME This is supposed to draw a circle for the sun, but it doesn't work correctly
-    // let sun = ray
-    //     .direction
-    //     .dot(scene.sky.sun_direction.into())
-    //     .max(0.0)
-    //     .powf(scene.sky.sun_focus)
-    //     * scene.sky.sun_intensity;
+    let (tangent, bitangent) = tangent_frame(normal, material.tangent.into());
+    let microfacet_normal = tangent * microfacet_normal.x
+        + bitangent * microfacet_normal.y
+        + normal * microfacet_normal.z;
 
-    let ground_to_sky_t = smoothstep(-0.01, 0.0, ray.direction.y);
-    // let sun_mask = (ground_to_sky_t >= 1.0) as i32 as f32;
-    Vec3::lerp(scene.sky.ground_color, sky_gradient, ground_to_sky_t) // + sun * sun_mask
+    let reflected = reflect(direction, microfacet_normal);
+    if reflected.dot(normal) > 0.0 {
+        reflected
+    } else {
+        // The microfacet reflected the ray below the surface
+        reflect(direction, normal)
+    }
 }
 
-fn per_pixel(
-    scene: &Scene,
-    camera: &CustomCamera,
-    pixel_index: usize,
-    bounces: u8,
-    samples: usize,
-) -> Vec4 {
-    let mut ray = Ray {
-        origin: Vec3A::from(camera.position),
-        direction: camera.ray_directions[pixel_index],
-        inv_direction: 1.0 / camera.ray_directions[pixel_index],
-    };
-    let mut contribution = Vec3::ONE;
-    let mut light = Vec3::ZERO;
-
-    let mut seed = pixel_index as u32;
-    seed *= samples as u32;
+/// Light received by a surface from the lights that aren't occluded, weighted by the cosine with
+/// the normal
+fn direct_light(scene: &Scene, payload: &HitPayload) -> Vec3 {
+    let normal = Vec3A::from(payload.world_normal);
+    // Offset along the normal so the shadow ray doesn't hit the surface it starts from
+    let origin = Vec3A::from(payload.world_position) + normal * 0.0001;
 
-    for _ in 0..=bounces {
-        seed += 1;
-        let mut rng = PcgHashRng::new(seed);
+    let mut light = Vec3::ZERO;
+    for directional in &scene.lights {
+        let Some(light_dir) = Vec3A::from(directional.direction).try_normalize() else {
+            continue;
+        };
+        let n_dot_l = normal.dot(light_dir);
+        if n_dot_l <= 0.0 {
+            continue;
+        }
 
-        if let Some(payload) = trace_ray(&ray, scene) {
-            let material = scene.materials[payload.material_id];
+        let shadow_ray = Ray {
+            origin,
+            direction: light_dir,
+            inv_direction: 1.0 / light_dir,
+        };
+        // The BRDF of the diffuse lobe is albedo / PI, the albedo is already in the throughput
+        light += shadow_transmittance(scene, &shadow_ray, 0.001..=f32::INFINITY, true)
+            * directional.intensity
+            * n_dot_l
+            / PI;
+    }
 
-            // let light_intensity = compute_light_intensity(
-            //     scene,
-            //     (ray.origin + payload.hit_distance * ray.direction).into(),
-            //     payload.world_normal,
-            // );
-            // light += light_intensity;
+    for point in &scene.point_lights {
+        let to_light = Vec3A::from(point.position) - origin;
+        let distance = to_light.length();
+        let light_dir = to_light / distance;
+        let n_dot_l = normal.dot(light_dir);
+        if n_dot_l <= 0.0 || distance <= 0.0 {
+            continue;
+        }
 
-            contribution *= material.albedo;
-            light += material.get_emission();
+        let shadow_ray = Ray {
+            origin,
+            direction: light_dir,
+            inv_direction: 1.0 / light_dir,
+        };
+        // Objects behind the light don't cast a shadow
+        light += shadow_transmittance(scene, &shadow_ray, 0.001..=distance, true)
+            * point.color
+            * point.intensity
+            * n_dot_l
+            / (PI * distance * distance);
+    }
+    light
+}
 
-            ray.origin = (payload.world_position + payload.world_normal * 0.0001).into();
-            ray.direction =
-                (Vec3A::from(payload.world_normal) + random::in_unit_sphere(&mut rng)).normalize();
-        } else {
-            light += sky_color(scene, &ray) * contribution;
-            break;
+/// Fraction of the light that goes along the ray without being blocked, between the ends of
+/// `hit_distance_range`.
+///
+/// The lights of [`direct_light`] can't be found by the bounces, so this is the only way for their
+/// light to reach a surface. They skip the inside-out spheres with `skip_inside_out`, those are
+/// domes or rooms around the scene and would block every light outside them. Transmissive surfaces
+/// let their transmitted part through, tinted by their albedo like the refracted rays. The ray
+/// isn't bent, so glass casts a colored shadow instead of a caustic.
+fn shadow_transmittance(
+    scene: &Scene,
+    ray: &Ray,
+    hit_distance_range: RangeInclusive<f32>,
+    skip_inside_out: bool,
+) -> Vec3 {
+    let mut transmittance = Vec3::ONE;
+    let mut start = *hit_distance_range.start();
+    for _ in 0..MAX_SHADOW_HITS {
+        let Some(hit) = trace_ray(ray, scene, start..=*hit_distance_range.end()) else {
+            return transmittance;
+        };
+        let inside_out = match hit.object {
+            ObjectRef::Sphere(i) => scene.spheres[i].inside_out,
+            ObjectRef::Mesh(_) => false,
+        };
+        if !(inside_out && skip_inside_out) {
+            let material = &scene.materials[hit.material_id];
+            // Same probabilities as the lobes chosen by scatter, metals never transmit
+            transmittance *= material.albedo * material.transmission * (1.0 - material.metallic);
+            if transmittance == Vec3::ZERO {
+                return Vec3::ZERO;
+            }
         }
+        start = hit.hit_distance + 0.001;
     }
-    (light).extend(1.0)
+    Vec3::ZERO
 }
 
-#[allow(unused)]
-fn compute_light_intensity(scene: &Scene, position: Vec3, normal: Vec3) -> f32 {
-    let mut light_intensity = 0.0;
-    for light in &scene.lights {
-        let light_dir = light.direction.normalize();
+/// Light received from an emissive sphere chosen at random, weighted by the cosine with the normal
+/// and divided by the probability of sampling it.
+///
+/// Small emitters are rarely found by the diffuse bounces, so sampling them directly removes most
+/// of the noise of the surfaces they light. The sphere is chosen in proportion to
+/// [`emitter_weight`], so with many emitters the bright and close ones are sampled more often than
+/// the ones that barely light the surface.
+/// `emitters` are the indices of the emissive spheres, see [`Renderer::emitters`].
+/// `caustic_boost` lets the light through transmissive surfaces, see [`Renderer::caustic_boost`].
+fn sample_emissive_spheres(
+    scene: &Scene,
+    emitters: &[usize],
+    payload: &HitPayload,
+    caustic_boost: bool,
+    rng: &mut PcgHashRng,
+) -> Vec3 {
+    let normal = Vec3A::from(payload.world_normal);
+    // Offset along the normal so the shadow ray doesn't hit the surface it starts from
+    let origin = Vec3A::from(payload.world_position) + normal * 0.0001;
 
-        let light_dir_a = Vec3A::from(light_dir);
-        let shadow_ray = Ray {
-            origin: position.into(),
-            direction: light_dir_a,
-            inv_direction: 1.0 / light_dir_a,
+    // Weighted reservoir sampling: a single candidate is kept while streaming over the emitters,
+    // and each one replaces it with the probability of its share of the weights seen so far
+    let mut chosen = None;
+    let mut total_weight = 0.0;
+    for &sphere_index in emitters {
+        let Some(sphere) = scene.spheres.get(sphere_index) else {
+            continue;
         };
-        if let (Some(_), _) = find_closest_sphere(&shadow_ray, scene, 0.001..=f32::INFINITY) {
-            break;
+        let weight = emitter_weight(scene, sphere, origin);
+        if weight <= 0.0 {
+            continue;
         }
-        if let (Some(_), _, _) = find_closest_triangle(&shadow_ray, scene, 0.001..=f32::INFINITY) {
-            break;
+        total_weight += weight;
+        if rng.gen::<f32>() * total_weight < weight {
+            chosen = Some((sphere_index, sphere, weight));
         }
+    }
+    let Some((sphere_index, sphere, weight)) = chosen else {
+        return Vec3::ZERO;
+    };
+    let selection_pdf = weight / total_weight;
 
-        let n_dot_l = normal.dot(light_dir);
-        // diffuse
-        if n_dot_l > 0.0 {
-            light_intensity += light.intensity * n_dot_l / (normal.length() * light_dir.length());
+    let Some((direction, pdf)) = sample_sphere_light(sphere, origin, rng) else {
+        return Vec3::ZERO;
+    };
+    let n_dot_l = normal.dot(direction);
+    if n_dot_l <= 0.0 {
+        return Vec3::ZERO;
+    }
+
+    let shadow_ray = Ray {
+        origin,
+        direction,
+        inv_direction: 1.0 / direction,
+    };
+    let visibility = if caustic_boost {
+        match sphere_intersect(&shadow_ray, sphere, 0.001) {
+            Some(distance) => {
+                shadow_transmittance(scene, &shadow_ray, 0.001..=distance - 0.001, false)
+            }
+            None => Vec3::ZERO,
+        }
+    } else {
+        match trace_ray(&shadow_ray, scene, 0.001..=f32::INFINITY) {
+            Some(hit) if hit.object == ObjectRef::Sphere(sphere_index) => Vec3::ONE,
+            _ => Vec3::ZERO,
         }
+    };
+    // The BRDF of the diffuse lobe is albedo / PI, the albedo is already in the throughput
+    visibility * scene.sphere_emission(sphere) * n_dot_l / (PI * pdf * selection_pdf)
+}
+
+/// Rough estimate of the light an emissive sphere sends to a position, its brightness times the
+/// solid angle it covers. It ignores the shadows and the cosine with the normal.
+///
+/// It's only 0 when [`sample_sphere_light`] can't sample the sphere, otherwise the light of the
+/// sphere would be missed.
+fn emitter_weight(scene: &Scene, sphere: &Sphere, position: Vec3A) -> f32 {
+    let distance_squared = (Vec3A::from(sphere.position) - position).length_squared();
+    let radius_squared = sphere.radius * sphere.radius;
+    if distance_squared <= radius_squared {
+        return 0.0;
+    }
+    let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+    luminance(scene.sphere_emission(sphere)).max(0.0) * TAU * (1.0 - cos_theta_max)
+}
+
+/// Samples a direction toward the sphere, uniformly in the cone it covers as seen from `position`.
+///
+/// Returns the direction and its probability density per solid angle, or `None` if the position is
+/// inside the sphere.
+///
+/// Reference:
+/// * https://www.pbr-book.org/3ed-2018/Light_Transport_I_Surface_Reflection/Sampling_Light_Sources#Spheres
+fn sample_sphere_light(
+    sphere: &Sphere,
+    position: Vec3A,
+    rng: &mut PcgHashRng,
+) -> Option<(Vec3A, f32)> {
+    let to_center = Vec3A::from(sphere.position) - position;
+    let distance_squared = to_center.length_squared();
+    let radius_squared = sphere.radius * sphere.radius;
+    if distance_squared <= radius_squared {
+        return None;
+    }
+    let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+    let solid_angle = TAU * (1.0 - cos_theta_max);
+    if solid_angle <= 0.0 {
+        // Too far away to be sampled with a f32
+        return None;
+    }
+
+    let cos_theta = 1.0 - rng.gen::<f32>() * (1.0 - cos_theta_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = TAU * rng.gen::<f32>();
+    let axis = to_center / distance_squared.sqrt();
+    let (tangent, bitangent) = tangent_frame(axis, Vec3A::X);
+    let direction =
+        tangent * sin_theta * phi.cos() + bitangent * sin_theta * phi.sin() + axis * cos_theta;
+    Some((direction, 1.0 / solid_angle))
+}
+
+/// Light received from the sun of the sky in a direction sampled like its cosine lobe, weighted by
+/// the cosine with the normal and divided by the probability of sampling it.
+///
+/// The sun is small and bright, so the diffuse bounces rarely find it and the surfaces it lights
+/// are noisy. The bounces still find it when they escape to the sky, so when `mis` is set both
+/// are weighted with the power heuristic, see [`power_heuristic`]. `mis` is unset when the path
+/// ends at this surface, this sample is then the only one.
+///
+/// The shadow ray is blocked by anything a bounce would hit, so both find the same light. The light
+/// going through glass is only found by the bounces, unless `caustic_boost` is set, see
+/// [`Renderer::caustic_boost`].
+fn sample_sun(
+    scene: &Scene,
+    payload: &HitPayload,
+    up: Vec3A,
+    mis: bool,
+    caustic_boost: bool,
+    rng: &mut PcgHashRng,
+) -> Vec3 {
+    let Some(sun_direction) = sun_direction(scene) else {
+        return Vec3::ZERO;
+    };
+    let focus = scene.sky.sun_focus.max(0.0);
+    // Inverse of the cumulative distribution of the cosine lobe
+    let cos_theta = rng.gen::<f32>().powf(1.0 / (focus + 1.0));
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = TAU * rng.gen::<f32>();
+    let (tangent, bitangent) = tangent_frame(sun_direction, Vec3A::X);
+    let direction = tangent * sin_theta * phi.cos()
+        + bitangent * sin_theta * phi.sin()
+        + sun_direction * cos_theta;
+
+    let normal = Vec3A::from(payload.world_normal);
+    let n_dot_l = normal.dot(direction);
+    let radiance = sun_radiance(scene, direction, up);
+    if n_dot_l <= 0.0 || radiance == Vec3::ZERO {
+        return Vec3::ZERO;
+    }
+
+    let shadow_ray = Ray {
+        // Offset along the normal so the shadow ray doesn't hit the surface it starts from
+        origin: Vec3A::from(payload.world_position) + normal * 0.0001,
+        direction,
+        inv_direction: 1.0 / direction,
+    };
+    let visibility = if caustic_boost {
+        shadow_transmittance(scene, &shadow_ray, 0.001..=f32::INFINITY, false)
+    } else if trace_ray(&shadow_ray, scene, 0.001..=f32::INFINITY).is_some() {
+        Vec3::ZERO
+    } else {
+        Vec3::ONE
+    };
+    if visibility == Vec3::ZERO {
+        return Vec3::ZERO;
     }
-    light_intensity
+
+    let pdf = sun_pdf(scene, direction);
+    // The diffuse bounces are cosine weighted
+    let weight = if mis {
+        power_heuristic(pdf, n_dot_l / PI)
+    } else {
+        1.0
+    };
+    // The BRDF of the diffuse lobe is albedo / PI, the albedo is already in the throughput
+    visibility * radiance * n_dot_l / (PI * pdf) * weight
 }
 
 fn find_closest_sphere(
@@ -213,7 +2159,7 @@ fn find_closest_sphere(
     let mut hit_distance = f32::MAX;
     let mut closest_sphere: Option<usize> = None;
     for (i, sphere) in scene.spheres.iter().enumerate() {
-        if let Some(closest_hit) = sphere_intersect(ray, sphere) {
+        if let Some(closest_hit) = sphere_intersect(ray, sphere, *hit_distance_range.start()) {
             // Sphere intersection was found
             if hit_distance_range.contains(&closest_hit) && closest_hit < hit_distance {
                 hit_distance = closest_hit;
@@ -228,69 +2174,130 @@ fn find_closest_triangle(
     ray: &Ray,
     scene: &Scene,
     hit_distance_range: RangeInclusive<f32>,
-) -> (Option<usize>, f32, Vec3A) {
+) -> (Option<usize>, f32, Vec3A, Vec2, Vec3A) {
     let mut hit_distance = f32::MAX;
     let mut normal = Vec3A::ZERO;
+    let mut uv = Vec2::ZERO;
+    let mut tangent = Vec3A::ZERO;
     let mut mesh_id: Option<usize> = None;
     for (i, mesh) in scene.meshes.iter().enumerate() {
+        // The asset is loaded and the BVH is built in the background,
+        // the mesh isn't rendered until both are ready
+        let (Some(mesh_data), Some(bvh)) = (&mesh.mesh, &mesh.bvh) else {
+            continue;
+        };
+        // This checks every vertex for every ray, so it's only enabled when debugging
+        #[cfg(feature = "validate_aabbs")]
+        debug_assert!(
+            mesh.aabb_contains_vertices(),
+            "The AABB of mesh {i} doesn't contain all its vertices"
+        );
+
+        // The vertices are in the space of the mesh, so the ray is brought in that space instead of
+        // transforming every vertex. The direction isn't normalized, this keeps the hit distances
+        // the same in both spaces.
+        let world_to_local = mesh.world_to_local();
+        let local_direction = world_to_local.transform_vector3a(ray.direction);
+        let local_ray = Ray {
+            origin: world_to_local.transform_point3a(ray.origin),
+            direction: local_direction,
+            inv_direction: 1.0 / local_direction,
+        };
+        let ray = &local_ray;
+
         // Check the AABB first to avoid unnecessary checks
-        if !aabb_intersect(ray, mesh.aabb) {
+        // Boxes behind the closest hit so far can't contain a closer triangle
+        let max_distance = hit_distance.min(*hit_distance_range.end());
+        if bvh.nodes.is_empty() || !aabb_intersect(ray, mesh.aabb, max_distance) {
             continue;
         }
 
         // get vertex positions
-        let Some(positions) = mesh
-            .mesh
+        let Some(positions) = mesh_data
             .attribute(Mesh::ATTRIBUTE_POSITION)
             .and_then(|x| x.as_float3())
         else {
             panic!("Vertex positions attribute should exist and be float3");
         };
         // get vertex normals
-        let Some(normals) = mesh
-            .mesh
+        let Some(normals) = mesh_data
             .attribute(Mesh::ATTRIBUTE_NORMAL)
             .and_then(|x| x.as_float3())
         else {
             panic!("Vertex normals attribute should exist and be float3");
         };
+        // texture coordinates are optional
+        let uvs = match mesh_data.attribute(Mesh::ATTRIBUTE_UV_0) {
+            Some(VertexAttributeValues::Float32x2(uvs)) => Some(uvs),
+            _ => None,
+        };
+        let triangle_uvs = |indices: &[u32; 3]| match uvs {
+            Some(uvs) => indices.map(|i| Vec2::from(uvs[i as usize])),
+            None => [Vec2::ZERO; 3],
+        };
+
+        // traverse the BVH using a fixed size stack to avoid allocating for every ray
+        let mut stack = [0; BVH_STACK_SIZE];
+        let mut stack_len = 1;
+        while stack_len > 0 {
+            stack_len -= 1;
+            let node = &bvh.nodes[stack[stack_len]];
+            if !aabb_intersect(ray, node.aabb, hit_distance.min(*hit_distance_range.end())) {
+                continue;
+            }
 
-        // get indices
-        let Some(Indices::U32(indices)) = mesh.mesh.indices() else {
-            panic!("Only U32 indices are supported")
-        };
-
-        // loop triangles
-        for triangle in indices.chunks(3) {
-            let [i0, i1, i2] = triangle else { unreachable!() };
-            // TODO handle transform
-            if let Some((closest_hit, hit_normal)) = triangle_intersect(
-                ray,
-                positions[*i0 as usize].into(),
-                positions[*i1 as usize].into(),
-                positions[*i2 as usize].into(),
-                normals[*i0 as usize].into(),
-                normals[*i1 as usize].into(),
-                normals[*i2 as usize].into(),
-            ) {
-                // Triangle intersection was found
-                if hit_distance_range.contains(&closest_hit) && closest_hit < hit_distance {
-                    hit_distance = closest_hit;
-                    normal = hit_normal;
-                    mesh_id = Some(i);
+            if !node.is_leaf() {
+                stack[stack_len] = node.first as usize;
+                stack[stack_len + 1] = node.first as usize + 1;
+                stack_len += 2;
+                continue;
+            }
+
+            // loop triangles
+            for triangle in bvh.node_triangles(node) {
+                let [i0, i1, i2] = triangle;
+                if let Some((closest_hit, hit_normal, hit_uv)) = triangle_intersect(
+                    ray,
+                    positions[*i0 as usize].into(),
+                    positions[*i1 as usize].into(),
+                    positions[*i2 as usize].into(),
+                    normals[*i0 as usize].into(),
+                    normals[*i1 as usize].into(),
+                    normals[*i2 as usize].into(),
+                    triangle_uvs(triangle),
+                ) {
+                    // Triangle intersection was found
+                    if hit_distance_range.contains(&closest_hit) && closest_hit < hit_distance {
+                        hit_distance = closest_hit;
+                        // Normals are transformed by the inverse transpose, so they stay
+                        // perpendicular to the surface when the scale isn't uniform
+                        normal = (world_to_local.matrix3.transpose() * hit_normal).normalize();
+                        uv = hit_uv;
+                        let vertices = triangle.map(|i| Vec3A::from(positions[i as usize]));
+                        tangent = mesh.transform.compute_affine().matrix3
+                            * triangle_tangent(vertices, triangle_uvs(triangle));
+                        mesh_id = Some(i);
+                    }
                 }
             }
         }
     }
-    (mesh_id, hit_distance, normal)
+    (mesh_id, hit_distance, normal, uv, tangent)
 }
 
-fn trace_ray(ray: &Ray, scene: &Scene) -> Option<HitPayload> {
-    // handle spheres and triangles separately and then keep the closest one at the end
+fn trace_ray(
+    ray: &Ray,
+    scene: &Scene,
+    hit_distance_range: RangeInclusive<f32>,
+) -> Option<HitPayload> {
+    // handle spheres and triangles separately and then keep the closest one at the end.
+    // Triangles behind the closest sphere are skipped, along with the BVH nodes behind it.
     let (closest_sphere, sphere_hit_distance) =
-        find_closest_sphere(ray, scene, 0.001..=f32::INFINITY);
-    let (closest_mesh, triangle_hit_distance, triangle_normal) =
-        find_closest_triangle(ray, scene, 0.001..=f32::INFINITY);
+        find_closest_sphere(ray, scene, hit_distance_range.clone());
+    let triangle_range =
+        *hit_distance_range.start()..=sphere_hit_distance.min(*hit_distance_range.end());
+    let (closest_mesh, triangle_hit_distance, triangle_normal, triangle_uv, triangle_tangent) =
+        find_closest_triangle(ray, scene, triangle_range);
 
     // a sphere was hit
     if let Some(sphere_index) = closest_sphere {
@@ -299,11 +2306,27 @@ fn trace_ray(ray: &Ray, scene: &Scene) -> Option<HitPayload> {
             let sphere = scene.spheres[sphere_index];
             let origin = Vec3::from(ray.origin) - sphere.position;
             let hit_position = origin + Vec3::from(ray.direction) * sphere_hit_distance;
+            let local_normal = hit_position.normalize();
+            let world_normal = if sphere.inside_out {
+                -local_normal
+            } else {
+                local_normal
+            };
+            // longitude and latitude, v goes down from the top of the sphere like in images
+            let uv = Vec2::new(
+                0.5 + local_normal.x.atan2(local_normal.z) / TAU,
+                local_normal.y.clamp(-1.0, 1.0).acos() / PI,
+            );
+            // derivative of the position along the longitude, zero at the poles
+            let tangent = Vec3::new(local_normal.z, 0.0, -local_normal.x);
             return Some(HitPayload {
                 hit_distance: sphere_hit_distance,
                 material_id: sphere.material_id,
                 world_position: hit_position + sphere.position,
-                world_normal: hit_position.normalize(),
+                world_normal,
+                uv,
+                tangent,
+                object: ObjectRef::Sphere(sphere_index),
             });
         }
     }
@@ -313,14 +2336,15 @@ fn trace_ray(ray: &Ray, scene: &Scene) -> Option<HitPayload> {
         // the triangle is in front of a sphere
         if triangle_hit_distance < sphere_hit_distance {
             let mesh = &scene.meshes[mesh_index];
-            let translation = mesh.transform.translation;
-            let origin = Vec3::from(ray.origin) - translation;
-            let hit_position = origin + Vec3::from(ray.direction) * triangle_hit_distance;
+            let hit_position = ray.origin + ray.direction * triangle_hit_distance;
             return Some(HitPayload {
                 hit_distance: triangle_hit_distance,
                 material_id: mesh.material_id,
-                world_position: hit_position + translation,
+                world_position: hit_position.into(),
                 world_normal: triangle_normal.into(),
+                uv: triangle_uv,
+                tangent: triangle_tangent.into(),
+                object: ObjectRef::Mesh(mesh_index),
             });
         }
     }
@@ -330,11 +2354,14 @@ fn trace_ray(ray: &Ray, scene: &Scene) -> Option<HitPayload> {
 
 /// Computes the intersection between a ray and a sphere.
 ///
-/// Returns `None` if no intersection is found.
+/// Returns the closest intersection at least `min_distance` along the ray, so a ray starting
+/// inside the sphere hits its far side, or `None` if no intersection is found.
+///
+/// Inside-out spheres only return the far intersection since only their inner surface is visible.
 ///
 /// Reference:
 /// * https://github.com/TheCherno/RayTracing/blob/d13e0e07f13157c4711d664240717e0f9ec79f30/RayTracing/src/Renderer.cpp#L158
-fn sphere_intersect(ray: &Ray, sphere: &Sphere) -> Option<f32> {
+fn sphere_intersect(ray: &Ray, sphere: &Sphere, min_distance: f32) -> Option<f32> {
     let origin = ray.origin - Vec3A::from(sphere.position);
 
     let a = ray.direction.dot(ray.direction);
@@ -347,19 +2374,25 @@ fn sphere_intersect(ray: &Ray, sphere: &Sphere) -> Option<f32> {
     }
 
     let closest_t = (-b - discriminant.sqrt()) / (2.0 * a);
-    // let _t0 = (-b + discriminant.sqrt()) / (2.0 * a);
-    Some(closest_t)
+    let far_t = (-b + discriminant.sqrt()) / (2.0 * a);
+    if !sphere.inside_out && closest_t >= min_distance {
+        return Some(closest_t);
+    }
+    // The ray starts inside the sphere, like the camera in a large ground sphere or a ray
+    // refracted by glass
+    (far_t >= min_distance).then_some(far_t)
 }
 
 /// Computes the intersection between a ray and a triangle.
 ///
-/// Returns `None` if no intersection is found.
+/// Returns the hit distance with the normal and UV interpolated at the hit, or `None` if no
+/// intersection is found.
 ///
 /// References:
 /// * Scratch a pixel: https://www.scratchapixel.com/lessons/3d-basic-rendering/ray-tracing-rendering-a-triangle/moller-trumbore-ray-triangle-intersection.html
 /// * Sebastian Lague: https://youtu.be/Qz0KTGYJtUk?t=1419
 /// * Muller-Trumbore intersection: https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
-#[allow(non_snake_case)]
+#[allow(non_snake_case, clippy::too_many_arguments)]
 fn triangle_intersect(
     ray: &Ray,
     v0: Vec3A,
@@ -368,7 +2401,8 @@ fn triangle_intersect(
     n0: Vec3A,
     n1: Vec3A,
     n2: Vec3A,
-) -> Option<(f32, Vec3A)> {
+    [uv0, uv1, uv2]: [Vec2; 3],
+) -> Option<(f32, Vec3A, Vec2)> {
     let v0v1 = v1 - v0;
     let v0v2 = v2 - v0;
     let p_vec = ray.direction.cross(v0v2);
@@ -396,24 +2430,39 @@ fn triangle_intersect(
     let t = v0v2.dot(q_vec) * inv_det;
     // ray intersection
     if t > f32::EPSILON {
-        // compute normal vector
+        // interpolate the vertex attributes with the barycentric coordinates
         let w = 1.0 - u - v;
         let N = (n0 * w + n1 * u + n2 * v).normalize();
-        Some((t, N))
+        let uv = uv0 * w + uv1 * u + uv2 * v;
+        Some((t, N, uv))
     } else {
         // This means that there is a line intersection but not a ray intersection.
         None
     }
 }
 
+/// Returns the direction in which u increases on a triangle, or zero if the UVs are degenerate.
+///
+/// Reference:
+/// * https://learnopengl.com/Advanced-Lighting/Normal-Mapping
+fn triangle_tangent([v0, v1, v2]: [Vec3A; 3], [uv0, uv1, uv2]: [Vec2; 3]) -> Vec3A {
+    let (edge1, edge2) = (v1 - v0, v2 - v0);
+    let (delta_uv1, delta_uv2) = (uv1 - uv0, uv2 - uv0);
+    let det = delta_uv1.x * delta_uv2.y - delta_uv2.x * delta_uv1.y;
+    if det.abs() < f32::EPSILON {
+        return Vec3A::ZERO;
+    }
+    (edge1 * delta_uv2.y - edge2 * delta_uv1.y) / det
+}
+
 /// Computes the intersection between a ray and an AABB.
 ///
+/// Returns false if the box is entirely beyond `tmax`, like behind a closer hit.
+///
 /// Reference:
 /// * https://tavianator.com/2022/ray_box_boundary.html
-fn aabb_intersect(ray: &Ray, aabb: Aabb) -> bool {
+fn aabb_intersect(ray: &Ray, aabb: Aabb, mut tmax: f32) -> bool {
     let mut tmin: f32 = 0.0;
-    // TODO consider passing tmax as a parameter to avoid checking boxes that are too far
-    let mut tmax: f32 = f32::INFINITY;
 
     for i in 0..3 {
         let t1 = (Vec3::from(aabb.min())[i] - ray.origin[i]) * ray.inv_direction[i];
@@ -423,7 +2472,8 @@ fn aabb_intersect(ray: &Ray, aabb: Aabb) -> bool {
         tmax = t1.min(tmax).max(t2.min(tmax));
     }
 
-    tmin < tmax
+    // Flat boxes, like the one of a plane, have tmin == tmax
+    tmin <= tmax
 }
 
 trait Vec4Ext {
@@ -437,12 +2487,601 @@ impl Vec4Ext for Vec4 {
         u32::from_le_bytes(self.as_u8_array())
     }
 
+    /// Rounds to the nearest value, truncating would make the encoded image slightly too dark,
+    /// e.g. a linear 0.5 is 187.5 in sRGB and must become 188
     fn as_u8_array(&self) -> [u8; 4] {
-        [
-            (self.x * 255.0) as u8,
-            (self.y * 255.0) as u8,
-            (self.z * 255.0) as u8,
-            (self.w * 255.0) as u8,
-        ]
+        (*self * 255.0).round().to_array().map(|x| x as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::{mesh::Indices, render_resource::PrimitiveTopology};
+
+    use super::*;
+    use crate::{
+        bvh::Bvh,
+        scene::{Sky, TriangleMesh},
+    };
+
+    fn test_ray(origin: Vec3, direction: Vec3) -> Ray {
+        let direction = Vec3A::from(direction.normalize());
+        Ray {
+            origin: origin.into(),
+            direction,
+            inv_direction: direction.recip(),
+        }
+    }
+
+    /// A diffuse and a glass sphere on a ground sphere, lit by the sky and its sun
+    fn test_scene() -> Scene {
+        Scene {
+            materials: vec![
+                Material {
+                    albedo: Vec3::splat(0.8),
+                    roughness: 1.0,
+                    ..default()
+                },
+                Material {
+                    albedo: Vec3::ONE,
+                    roughness: 0.0,
+                    transmission: 1.0,
+                    ..default()
+                },
+            ],
+            spheres: vec![
+                Sphere {
+                    position: Vec3::new(-0.6, 0.0, 0.0),
+                    ..default()
+                },
+                Sphere {
+                    position: Vec3::new(0.6, 0.0, 0.0),
+                    material_id: 1,
+                    ..default()
+                },
+                Sphere {
+                    position: Vec3::new(0.0, -100.5, 0.0),
+                    radius: 100.0,
+                    ..default()
+                },
+            ],
+            ..default()
+        }
+    }
+
+    fn render_frames(scene: &Scene, frame_seed_offset: u32) -> Vec<[u8; 4]> {
+        let mut camera = CustomCamera::new(45.0, 0.1, 100.0);
+        camera.resize(32, 24);
+        let mut renderer = Renderer::new(32, 24);
+        renderer.frame_seed_offset = frame_seed_offset;
+        for _ in 0..3 {
+            renderer.render(&camera, scene);
+        }
+        renderer.image_data.clone()
+    }
+
+    #[test]
+    fn same_seed_renders_same_image() {
+        // The threads render the tiles in any order, the seed of a sample must only depend on
+        // its pixel, frame and index
+        let scene = test_scene();
+        let image = render_frames(&scene, 7);
+        assert!(
+            image.iter().any(|pixel| *pixel != image[0]),
+            "nothing was rendered"
+        );
+        assert!(image == render_frames(&scene, 7));
+        assert!(render_frames(&scene, 7) != render_frames(&scene, 8));
+
+        // A black emitter only shows its emission, so only the jitter and the lens are random
+        let scene = Scene {
+            materials: vec![Material {
+                albedo: Vec3::ZERO,
+                emissive_color: Vec3::ONE,
+                emissive_power: 1.0,
+                ..default()
+            }],
+            spheres: vec![Sphere::default()],
+            ..default()
+        };
+        let camera_samples = |anti_aliasing: bool, aperture: f32, frame_seed_offset: u32| {
+            let mut camera = CustomCamera::new(45.0, 0.1, 100.0);
+            camera.resize(32, 24);
+            camera.aperture = aperture;
+            let mut renderer = Renderer::new(32, 24);
+            renderer.anti_aliasing = anti_aliasing;
+            renderer.frame_seed_offset = frame_seed_offset;
+            renderer.render(&camera, &scene);
+            renderer.image_data
+        };
+        assert!(camera_samples(false, 0.0, 7) == camera_samples(false, 0.0, 8));
+        assert!(camera_samples(true, 0.0, 7) != camera_samples(true, 0.0, 8));
+        assert!(camera_samples(false, 0.5, 7) != camera_samples(false, 0.5, 8));
+    }
+
+    #[test]
+    fn invalid_material_keeps_neighbors() {
+        // Only the sky is around the sphere, so the pixels it doesn't cover never see it
+        let sphere = Sphere {
+            radius: 0.5,
+            ..default()
+        };
+        let nan_material = Material {
+            albedo: Vec3::NAN,
+            ..default()
+        };
+        let scene = Scene {
+            materials: vec![nan_material],
+            spheres: vec![sphere],
+            ..default()
+        };
+        let mut camera = CustomCamera::new(45.0, 0.1, 100.0);
+        camera.resize(32, 24);
+        let render = |scene: &Scene| {
+            let mut renderer = Renderer::new(32, 24);
+            for _ in 0..3 {
+                renderer.render(&camera, scene);
+            }
+            renderer
+        };
+        let with_sphere = render(&scene);
+        let without_sphere = render(&Scene {
+            spheres: Vec::new(),
+            ..scene.clone()
+        });
+
+        // The jittered samples can land up to a pixel outside of the rectangle of the sphere
+        let (min, max) = camera
+            .project_bounding_sphere(sphere.position, sphere.radius)
+            .unwrap();
+        let to_pixel =
+            |ndc: Vec2| Vec2::new(ndc.x + 1.0, 1.0 - ndc.y) / 2.0 * Vec2::new(32.0, 24.0);
+        let (min, max) = (
+            to_pixel(Vec2::new(min.x, max.y)) - 2.0,
+            to_pixel(Vec2::new(max.x, min.y)) + 2.0,
+        );
+        let mut covered = 0;
+        for pixel_index in 0..32 * 24 {
+            let color = with_sphere.averaged_pixel(pixel_index);
+            assert!(color.is_finite(), "{pixel_index}: {color}");
+            let pixel = Vec2::new((pixel_index % 32) as f32, (pixel_index / 32) as f32);
+            if pixel.cmpge(min).all() && pixel.cmple(max).all() {
+                covered += 1;
+            } else {
+                assert_eq!(
+                    color,
+                    without_sphere.averaged_pixel(pixel_index),
+                    "{pixel_index}"
+                );
+            }
+        }
+        assert!(covered > 0 && covered < 32 * 24 / 4, "{covered}");
+
+        // An infinite power would make every path reaching the emitter invalid
+        for emissive_power in [f32::INFINITY, f32::NAN, f32::MAX] {
+            let material = Material {
+                emissive_color: Vec3::ONE,
+                emissive_power,
+                ..default()
+            };
+            let emission = material.get_emission();
+            assert!(emission.is_finite(), "{emissive_power}: {emission}");
+            assert!(emission.max_element() <= Material::MAX_EMISSIVE_POWER);
+        }
+    }
+
+    #[test]
+    fn bucket_renders_same_pixels() {
+        let scene = test_scene();
+        let mut camera = CustomCamera::new(45.0, 0.1, 100.0);
+        camera.resize(32, 24);
+        let render = |bucket| {
+            let mut renderer = Renderer::new(32, 24);
+            renderer.set_bucket(bucket);
+            for _ in 0..3 {
+                renderer.render(&camera, &scene);
+            }
+            renderer
+        };
+        let full = render(None);
+        // Not aligned with the tiles so they are cropped
+        let bucket = Tile {
+            x: 5,
+            y: 3,
+            width: 20,
+            height: 18,
+        };
+        let cropped = render(Some(bucket));
+        let inside: Vec<_> = bucket.pixel_indices(32).collect();
+        for pixel_index in 0..32 * 24 {
+            if inside.contains(&pixel_index) {
+                assert_eq!(cropped.sample_counts[pixel_index], 3);
+                assert_eq!(
+                    cropped.averaged_pixel(pixel_index),
+                    full.averaged_pixel(pixel_index)
+                );
+            } else {
+                assert_eq!(cropped.sample_counts[pixel_index], 0, "{pixel_index}");
+            }
+        }
+    }
+
+    #[test]
+    fn furnace_converges_to_sky_color() {
+        // The white sphere neither absorbs nor creates light, and the light it reflects all comes
+        // from the uniform sky, so it converges to the sky color. A biased sampler or a wrong pdf
+        // makes it brighter or darker.
+        let sky_color = Vec3::splat(0.5);
+        let scene = Scene::furnace(sky_color);
+        let mut camera = CustomCamera::new(45.0, 0.1, 100.0);
+        camera.resize(24, 24);
+        // The sphere fills the view, the pixels on its edges would also average the sky
+        let mut settings = camera.settings();
+        settings.position = Vec3::new(0.0, 0.0, 1.5);
+        camera.apply_settings(&settings);
+        let mut renderer = Renderer::new(24, 24);
+        for _ in 0..16 {
+            renderer.render(&camera, &scene);
+        }
+        let (average, max_error) = renderer.compare_to_color(sky_color).unwrap();
+        assert!(average.abs_diff_eq(sky_color, 1e-3), "{average}");
+        assert!(max_error < 1e-3, "{max_error}");
+    }
+
+    /// A hit on a floor facing up at the origin, for the light sampling
+    fn floor_hit() -> HitPayload {
+        HitPayload {
+            hit_distance: 1.0,
+            world_position: Vec3::ZERO,
+            world_normal: Vec3::Y,
+            uv: Vec2::ZERO,
+            tangent: Vec3::X,
+            material_id: 0,
+            object: ObjectRef::Mesh(0),
+        }
+    }
+
+    #[test]
+    fn sun_sampling_matches_bounces() {
+        // A wide sun, so the uniform reference converges quickly
+        let scene = Scene {
+            sky: Sky {
+                sun_direction: Vec3::new(0.5, 1.0, 0.2),
+                sun_focus: 8.0,
+                sun_intensity: 2.0,
+                ..default()
+            },
+            ..default()
+        };
+        let payload = floor_hit();
+        let up = Vec3A::Y;
+        let normal = Vec3A::Y;
+        let samples = 100_000;
+        let mut rng = PcgHashRng::new(1);
+
+        // Light reflected by a white diffuse surface, the directions are uniform on the hemisphere
+        let mut reference = Vec3::ZERO;
+        for _ in 0..samples {
+            let direction = random::on_unit_sphere(Vec2::new(rng.gen(), rng.gen()));
+            let direction = direction * direction.dot(normal).signum();
+            reference += sun_radiance(&scene, direction, up) * direction.dot(normal) / PI * TAU;
+        }
+
+        let mut sampled = Vec3::ZERO;
+        let mut combined = Vec3::ZERO;
+        for _ in 0..samples {
+            sampled += sample_sun(&scene, &payload, up, false, false, &mut rng);
+            // The cosine of the bounce cancels with its pdf
+            combined += sample_sun(&scene, &payload, up, true, false, &mut rng);
+            let direction =
+                random::cosine_weighted_hemisphere(normal, Vec2::new(rng.gen(), rng.gen()));
+            let weight = power_heuristic(direction.dot(normal) / PI, sun_pdf(&scene, direction));
+            combined += sun_radiance(&scene, direction, up) * weight;
+        }
+
+        let [reference, sampled, combined] =
+            [reference, sampled, combined].map(|sum| sum / samples as f32);
+        assert!(reference.x > 0.1, "{reference}");
+        let tolerance = reference.x * 0.02;
+        assert!(
+            sampled.abs_diff_eq(reference, tolerance),
+            "{sampled} {reference}"
+        );
+        assert!(
+            combined.abs_diff_eq(reference, tolerance),
+            "{combined} {reference}"
+        );
+    }
+
+    #[test]
+    fn emitter_selection_is_unbiased() {
+        // A bright sphere close to the surface and a dimmer one further away, both light the
+        // surface enough for a wrong selection probability to show up
+        let emissive = |power| Material {
+            emissive_color: Vec3::ONE,
+            emissive_power: power,
+            ..default()
+        };
+        let scene = Scene {
+            materials: vec![emissive(20.0), emissive(10.0)],
+            spheres: vec![
+                Sphere {
+                    position: Vec3::new(-1.0, 1.5, 0.0),
+                    ..default()
+                },
+                Sphere {
+                    position: Vec3::new(2.5, 2.5, 0.0),
+                    material_id: 1,
+                    ..default()
+                },
+            ],
+            ..default()
+        };
+        let payload = floor_hit();
+        let samples = 50_000;
+        let mut rng = PcgHashRng::new(1);
+        let mut average = |emitters: &[usize]| {
+            (0..samples)
+                .map(|_| sample_emissive_spheres(&scene, emitters, &payload, false, &mut rng))
+                .sum::<Vec3>()
+                / samples as f32
+        };
+
+        // Each sphere is the only choice when it's sampled alone
+        let reference = average(&[0]) + average(&[1]);
+        let combined = average(&[0, 1]);
+        assert!(
+            combined.abs_diff_eq(reference, reference.x * 0.02),
+            "{combined} {reference}"
+        );
+    }
+
+    #[test]
+    fn caustic_boost_goes_through_glass() {
+        let scene = Scene {
+            materials: vec![
+                Material {
+                    emissive_color: Vec3::ONE,
+                    emissive_power: 10.0,
+                    ..default()
+                },
+                Material {
+                    albedo: Vec3::new(1.0, 0.5, 0.5),
+                    roughness: 0.0,
+                    transmission: 1.0,
+                    ..default()
+                },
+            ],
+            spheres: vec![
+                Sphere {
+                    position: Vec3::new(0.0, 4.0, 0.0),
+                    ..default()
+                },
+                // Between the surface and the emitter
+                Sphere {
+                    position: Vec3::new(0.0, 2.0, 0.0),
+                    radius: 1.0,
+                    material_id: 1,
+                    ..default()
+                },
+            ],
+            ..default()
+        };
+        let payload = floor_hit();
+        let light = |scene: &Scene, caustic_boost| {
+            let mut rng = PcgHashRng::new(1);
+            (0..100)
+                .map(|_| sample_emissive_spheres(scene, &[0], &payload, caustic_boost, &mut rng))
+                .sum::<Vec3>()
+        };
+
+        assert_eq!(light(&scene, false), Vec3::ZERO);
+        let boosted = light(&scene, true);
+        // Tinted by the glass
+        assert!(boosted.x > 0.0 && boosted.y < boosted.x, "{boosted}");
+
+        // Without the glass in the way, the boost doesn't change anything
+        let mut clear = scene.clone();
+        clear.spheres.pop();
+        assert_eq!(light(&clear, true), light(&clear, false));
+    }
+
+    #[test]
+    fn pixel_filter_distributions() {
+        // A regular grid over the unit square, so the moments are exact up to the grid resolution
+        let steps = 200;
+        let offsets = |filter: PixelFilter| {
+            (0..steps * steps).map(move |i| {
+                let point =
+                    (Vec2::new((i % steps) as f32, (i / steps) as f32) + 0.5) / steps as f32;
+                filter.sample(point)
+            })
+        };
+        // Variance of the offsets along x for each filter
+        for (filter, variance, max_offset) in [
+            (PixelFilter::Box, 1.0 / 12.0, 0.5),
+            (PixelFilter::Tent, 1.0 / 6.0, 1.0),
+            (PixelFilter::Gaussian, 0.25, f32::INFINITY),
+        ] {
+            let count = (steps * steps) as f32;
+            let mean = offsets(filter).sum::<Vec2>() / count;
+            assert!(mean.abs_diff_eq(Vec2::ZERO, 1e-3), "{filter:?}: {mean}");
+            let measured = offsets(filter)
+                .map(|offset| offset.x * offset.x)
+                .sum::<f32>()
+                / count;
+            assert!((measured - variance).abs() < 0.01, "{filter:?}: {measured}");
+            assert!(offsets(filter).all(|offset| offset.abs().max_element() <= max_offset));
+        }
+    }
+
+    #[test]
+    fn sample_dimensions_are_decorrelated() {
+        let count = 256;
+        for sampler in [Sampler::Halton, Sampler::Sobol] {
+            let bounce = sample_points(sampler, SampleDimension::Bounce, count);
+            for dimension in [SampleDimension::PixelJitter, SampleDimension::Lens] {
+                let points = sample_points(sampler, dimension, count);
+                // Pearson correlation of the first coordinates, it's 1 when the dimensions share
+                // their points
+                let mean = |values: &[f32]| values.iter().sum::<f32>() / values.len() as f32;
+                let xs: Vec<_> = points.iter().map(|p| p.x).collect();
+                let ys: Vec<_> = bounce.iter().map(|p| p.x).collect();
+                let (mean_x, mean_y) = (mean(&xs), mean(&ys));
+                let covariance: f32 = xs
+                    .iter()
+                    .zip(&ys)
+                    .map(|(x, y)| (x - mean_x) * (y - mean_y))
+                    .sum();
+                let variance = |values: &[f32], mean: f32| {
+                    values.iter().map(|v| (v - mean).powi(2)).sum::<f32>()
+                };
+                let correlation =
+                    covariance / (variance(&xs, mean_x) * variance(&ys, mean_y)).sqrt();
+                assert!(
+                    correlation.abs() < 0.1,
+                    "{sampler:?} {dimension:?}: {correlation}"
+                );
+            }
+        }
+
+        // The shuffled Sobol points still have exactly one point in each cell of a 16x16 grid
+        for dimension in SampleDimension::ALL {
+            let mut cells = [false; 256];
+            for point in sample_points(Sampler::Sobol, dimension, count) {
+                let cell = (point * 16.0).floor();
+                let cell = &mut cells[cell.y as usize * 16 + cell.x as usize];
+                assert!(!*cell, "{dimension:?}");
+                *cell = true;
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_encodes_mid_gray() {
+        let encoded = OutputColorSpace::Srgb.encode(Vec4::new(0.5, 0.5, 0.5, 0.5));
+        // The alpha isn't encoded, 127.5 rounds up too
+        assert_eq!(encoded, [188, 188, 188, 128]);
+        let encoded = OutputColorSpace::Srgb.encode(Vec4::new(0.0, 1.0, 2.0, 1.0));
+        assert_eq!(encoded, [0, 255, 255, 255]);
+    }
+
+    /// A 2x2 quad in the XY plane facing +Z
+    fn quad(transform: Transform) -> TriangleMesh {
+        let positions = vec![
+            [-1.0, -1.0, 0.0],
+            [1.0, -1.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [-1.0, 1.0, 0.0],
+        ];
+        let indices = vec![0, 1, 2, 0, 2, 3];
+        let mut data = Mesh::new(PrimitiveTopology::TriangleList);
+        data.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 0.0, 1.0]; 4]);
+        data.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions.clone());
+        data.set_indices(Some(Indices::U32(indices.clone())));
+
+        let mut mesh = TriangleMesh::new(Handle::default(), transform, 0);
+        mesh.mesh = Some(data);
+        mesh.recompute_aabb();
+        mesh.bvh = Some(Bvh::build(&positions, &indices));
+        mesh
+    }
+
+    #[test]
+    fn rotated_mesh_hit() {
+        let mut scene = Scene {
+            meshes: vec![quad(Transform::from_rotation(Quat::from_rotation_y(
+                std::f32::consts::FRAC_PI_4,
+            )))],
+            ..default()
+        };
+        // The quad goes back by as much as the ray is moved to the right
+        let ray = test_ray(Vec3::new(0.5, 0.0, 5.0), Vec3::NEG_Z);
+        let expected_normal = Vec3A::new(1.0, 0.0, 1.0).normalize();
+        let (mesh, distance, normal, ..) = find_closest_triangle(&ray, &scene, 0.0..=f32::MAX);
+        assert_eq!(mesh, Some(0));
+        assert!((distance - 5.5).abs() < 1e-4, "{distance}");
+        assert!(normal.abs_diff_eq(expected_normal, 1e-4), "{normal}");
+
+        // The cached inverse isn't used after the transform is edited, until it's updated
+        scene.meshes[0].transform.rotation = Quat::from_rotation_y(-std::f32::consts::FRAC_PI_4);
+        for update in [false, true] {
+            if update {
+                scene.meshes[0].update_world_to_local();
+            }
+            let (_, distance, normal, ..) = find_closest_triangle(&ray, &scene, 0.0..=f32::MAX);
+            assert!((distance - 4.5).abs() < 1e-4, "{distance}");
+            let expected_normal = Vec3A::new(-1.0, 0.0, 1.0).normalize();
+            assert!(normal.abs_diff_eq(expected_normal, 1e-4), "{normal}");
+        }
+    }
+
+    fn unit_sphere(inside_out: bool) -> Sphere {
+        Sphere {
+            radius: 1.0,
+            inside_out,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn sphere_hit_from_outside() {
+        let ray = test_ray(Vec3::new(0.0, 0.0, -3.0), Vec3::Z);
+        let hit = sphere_intersect(&ray, &unit_sphere(false), 0.0001).unwrap();
+        assert!((hit - 2.0).abs() < 1e-5, "{hit}");
+    }
+
+    #[test]
+    fn sphere_hit_from_inside() {
+        // The near root is behind the origin, the far side of the sphere is hit
+        let ray = test_ray(Vec3::ZERO, Vec3::Z);
+        let hit = sphere_intersect(&ray, &unit_sphere(false), 0.0001).unwrap();
+        assert!((hit - 1.0).abs() < 1e-5, "{hit}");
+    }
+
+    #[test]
+    fn inside_out_sphere_only_hits_far_side() {
+        let ray = test_ray(Vec3::new(0.0, 0.0, -3.0), Vec3::Z);
+        let hit = sphere_intersect(&ray, &unit_sphere(true), 0.0001).unwrap();
+        assert!((hit - 4.0).abs() < 1e-5, "{hit}");
+    }
+
+    #[test]
+    fn ray_inside_inside_out_sphere() {
+        let ray = test_ray(Vec3::ZERO, Vec3::Z);
+        let hit = sphere_intersect(&ray, &unit_sphere(true), 0.0001).unwrap();
+        assert!((hit - 1.0).abs() < 1e-5, "{hit}");
+
+        // The inside is the visible side, so the normal points back toward the center
+        let scene = Scene {
+            spheres: vec![unit_sphere(true)],
+            ..default()
+        };
+        let payload = trace_ray(&ray, &scene, 0.0001..=f32::INFINITY).unwrap();
+        assert!((payload.hit_distance - 1.0).abs() < 1e-5);
+        assert!(
+            payload.world_normal.abs_diff_eq(Vec3::NEG_Z, 1e-5),
+            "{}",
+            payload.world_normal
+        );
+    }
+
+    #[test]
+    fn sphere_near_root_below_min_distance() {
+        // A ray leaving the surface, like a refraction entering the sphere, must not hit the
+        // surface it starts on
+        let ray = test_ray(Vec3::new(0.0, 0.0, -1.0), Vec3::Z);
+        let hit = sphere_intersect(&ray, &unit_sphere(false), 0.001).unwrap();
+        assert!((hit - 2.0).abs() < 1e-5, "{hit}");
+
+        // Both roots are behind the start of the range
+        let ray = test_ray(Vec3::new(0.0, 0.0, 3.0), Vec3::Z);
+        assert!(sphere_intersect(&ray, &unit_sphere(false), 0.001).is_none());
+    }
+
+    #[test]
+    fn sphere_miss() {
+        let ray = test_ray(Vec3::new(0.0, 2.0, -3.0), Vec3::Z);
+        assert!(sphere_intersect(&ray, &unit_sphere(false), 0.0001).is_none());
     }
 }
@@ -1,82 +1,509 @@
-use bevy::{math::vec3, prelude::*, render::primitives::Aabb};
+use std::sync::{
+    atomic::{AtomicU32, AtomicU64, Ordering},
+    Arc,
+};
 
-#[derive(Debug, Default, Clone, Resource)]
+use bevy::{
+    asset::LoadState,
+    math::{vec3, Affine3A},
+    prelude::*,
+    render::{
+        mesh::{Indices, VertexAttributeValues},
+        primitives::Aabb,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bvh::Bvh,
+    environment_map::EnvironmentMap,
+    load_error::{read_ron, write_ron, LoadError, LoadErrors},
+    renderer::Renderer,
+    texture::Texture,
+};
+
+#[derive(Debug, Default, Clone, Resource, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Scene {
     pub sky: Sky,
     pub materials: Vec<Material>,
     pub spheres: Vec<Sphere>,
+    /// Meshes aren't serialized yet
+    #[serde(skip)]
     pub meshes: Vec<TriangleMesh>,
     pub lights: Vec<Light>,
+    pub point_lights: Vec<PointLight>,
+    /// Images used by the materials
+    pub textures: Vec<Texture>,
+    pub fog: Fog,
+}
+
+impl Scene {
+    /// Saves the scene alone, without the camera and render settings of a project, so it can be
+    /// shared. Meshes aren't saved.
+    pub fn save_ron(&self, path: &str) -> Result<(), LoadError> {
+        write_ron(path, self)
+    }
+
+    /// Loads a scene saved by [`Scene::save_ron`]. The loaded scene has no meshes.
+    pub fn load_ron(path: &str) -> Result<Self, LoadError> {
+        read_ron(path)
+    }
+
+    /// Replaces the scene with one loaded from `path`, keeping the current meshes since they
+    /// aren't saved.
+    ///
+    /// Nothing is changed if an object of the loaded scene, or a kept mesh, uses a material that
+    /// isn't in the loaded scene.
+    pub fn replace_keep_meshes(&mut self, mut loaded: Scene, path: &str) -> Result<(), LoadError> {
+        std::mem::swap(&mut loaded.meshes, &mut self.meshes);
+        if let Err(message) = loaded.check_material_ids() {
+            std::mem::swap(&mut loaded.meshes, &mut self.meshes);
+            return Err(LoadError::Parse {
+                path: path.to_string(),
+                message,
+            });
+        }
+        // The loaded ids must not be handed out again, and the kept meshes can't reuse them
+        let max_id = loaded.spheres.iter().map(|sphere| sphere.id).max();
+        NEXT_OBJECT_ID.fetch_max(max_id.unwrap_or(0) + 1, Ordering::Relaxed);
+        for mesh in &mut loaded.meshes {
+            if loaded.spheres.iter().any(|sphere| sphere.id == mesh.id) {
+                mesh.id = next_object_id();
+            }
+        }
+        *self = loaded;
+        Ok(())
+    }
+
+    /// Checks that every object uses one of the materials, the renderer indexes them without
+    /// checking.
+    pub fn check_material_ids(&self) -> Result<(), String> {
+        let objects = self
+            .spheres
+            .iter()
+            .enumerate()
+            .map(|(i, sphere)| (format!("sphere {i}"), sphere.material_id))
+            .chain(
+                self.meshes
+                    .iter()
+                    .enumerate()
+                    .map(|(i, mesh)| (format!("mesh {i}"), mesh.material_id)),
+            );
+        for (object, material_id) in objects {
+            if material_id >= self.materials.len() {
+                return Err(format!(
+                    "{object} uses material {material_id} but there are {} materials",
+                    self.materials.len()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The id written in the object ID pass for an object, 0 if it doesn't exist
+    pub fn object_id(&self, object: ObjectRef) -> u32 {
+        match object {
+            ObjectRef::Sphere(i) => self.spheres.get(i).map_or(0, |sphere| sphere.id),
+            ObjectRef::Mesh(i) => self.meshes.get(i).map_or(0, |mesh| mesh.id),
+        }
+    }
+
+    /// A white diffuse sphere lit by a uniform sky, with no emitters.
+    /// If the materials don't lose or create energy, every pixel converges to the sky color.
+    pub fn furnace(sky_color: Vec3) -> Self {
+        Self {
+            sky: Sky {
+                mode: SkyMode::Solid(sky_color),
+                ..default()
+            },
+            materials: vec![Material {
+                albedo: Vec3::ONE,
+                roughness: 1.0,
+                ..default()
+            }],
+            spheres: vec![Sphere {
+                radius: 1.0,
+                ..default()
+            }],
+            ..default()
+        }
+    }
+
+    /// Returns the center and radius of a sphere containing the whole object
+    pub fn object_bounds(&self, object: ObjectRef) -> Option<(Vec3, f32)> {
+        match object {
+            ObjectRef::Sphere(i) => self.spheres.get(i).map(Sphere::bounds),
+            ObjectRef::Mesh(i) => self.meshes.get(i).map(TriangleMesh::bounds),
+        }
+    }
+
+    /// Box containing every sphere and mesh, `None` if the scene is empty
+    pub fn bounds(&self) -> Option<Aabb> {
+        let spheres = self.spheres.iter().map(|sphere| {
+            (
+                sphere.position - sphere.radius,
+                sphere.position + sphere.radius,
+            )
+        });
+        let meshes = self.meshes.iter().map(|mesh| {
+            // The box of a rotated mesh is the one containing the corners of its rotated box
+            let center = mesh.transform.transform_point(mesh.aabb.center.into());
+            let axes = Mat3::from_quat(mesh.transform.rotation)
+                * Mat3::from_diagonal(mesh.transform.scale);
+            let half_extents =
+                Mat3::from_cols(axes.x_axis.abs(), axes.y_axis.abs(), axes.z_axis.abs())
+                    * Vec3::from(mesh.aabb.half_extents);
+            (center - half_extents, center + half_extents)
+        });
+        spheres
+            .chain(meshes)
+            .reduce(|(min_a, max_a), (min_b, max_b)| (min_a.min(min_b), max_a.max(max_b)))
+            .map(|(min, max)| Aabb::from_min_max(min, max))
+    }
+
+    /// Emission added to an object regardless of its material
+    pub fn emissive_override(&self, object: ObjectRef) -> Option<Vec3> {
+        match object {
+            ObjectRef::Sphere(i) => self.spheres.get(i)?.emissive_override,
+            ObjectRef::Mesh(i) => self.meshes.get(i)?.emissive_override,
+        }
+    }
+
+    /// Whether the sphere emits light and is sampled directly by the renderer.
+    /// Inside out spheres surround the scene so they are left to the bounces, like the sky.
+    pub fn is_emissive_sphere(&self, index: usize) -> bool {
+        let sphere = &self.spheres[index];
+        !sphere.inside_out && self.sphere_emission(sphere) != Vec3::ZERO
+    }
+
+    /// Indices of the spheres for which [`Scene::is_emissive_sphere`] is true
+    pub fn emissive_spheres(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.spheres.len()).filter(|i| self.is_emissive_sphere(*i))
+    }
+
+    /// Light emitted by the sphere, from its material and its override
+    pub fn sphere_emission(&self, sphere: &Sphere) -> Vec3 {
+        self.materials[sphere.material_id].get_emission()
+            + sphere.emissive_override.unwrap_or(Vec3::ZERO)
+    }
+
+    /// Returns the position of an object, or `None` if it doesn't exist
+    pub fn object_position_mut(&mut self, object: ObjectRef) -> Option<&mut Vec3> {
+        match object {
+            ObjectRef::Sphere(i) => self.spheres.get_mut(i).map(|sphere| &mut sphere.position),
+            ObjectRef::Mesh(i) => self
+                .meshes
+                .get_mut(i)
+                .map(|mesh| &mut mesh.transform.translation),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Reference to an object of the scene by its index in its list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectRef {
+    Sphere(usize),
+    Mesh(usize),
+}
+
+/// 0 is kept for the background of the object ID pass
+static NEXT_OBJECT_ID: AtomicU32 = AtomicU32::new(1);
+
+/// Returns an id that no other object got, see [`Sphere::id`]
+pub fn next_object_id() -> u32 {
+    NEXT_OBJECT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Sky {
+    pub mode: SkyMode,
     pub ground_color: Vec3,
     pub horizon_color: Vec3,
     pub zenith_color: Vec3,
-    // pub sun_focus: f32,
-    // pub sun_intensity: f32,
-    // pub sun_direction: Vec3,
+    /// Points toward the sun, it's only drawn by [`SkyMode::Gradient`] above the horizon
+    pub sun_direction: Vec3,
+    /// Exponent of the cosine with the sun direction, the higher the smaller the sun
+    pub sun_focus: f32,
+    /// 0.0 disables the sun
+    pub sun_intensity: f32,
+    /// Image used by [`SkyMode::Equirect`], loaded by
+    /// [`crate::environment_map::sync_environment_map`]
+    pub environment_path: String,
+    /// The pixels are shared by the copies of the scene instead of being saved with it
+    #[serde(skip)]
+    pub environment: Option<Arc<EnvironmentMap>>,
 }
 
 impl Sky {
     #[allow(unused)]
     pub const BLACK: Self = Self {
+        mode: SkyMode::Solid(Vec3::ZERO),
         zenith_color: Vec3::ZERO,
         horizon_color: Vec3::ZERO,
         ground_color: Vec3::ZERO,
-        // sun_focus: 1.0,
-        // sun_intensity: 1.0,
-        // sun_direction: Vec3::ONE,
+        sun_direction: Vec3::ONE,
+        sun_focus: 512.0,
+        sun_intensity: 0.0,
+        environment_path: String::new(),
+        environment: None,
     };
 }
 impl Default for Sky {
     fn default() -> Self {
         Self {
+            mode: SkyMode::Gradient,
             ground_color: vec3(0.2, 0.2, 0.2),
             horizon_color: Vec3::ONE,
             zenith_color: Vec3::ZERO,
-            // sun_focus: 1.0,
-            // sun_intensity: 1.0,
-            // sun_direction: Vec3::ONE,
+            sun_direction: Vec3::ONE,
+            sun_focus: 512.0,
+            sun_intensity: 0.0,
+            environment_path: String::new(),
+            environment: None,
+        }
+    }
+}
+
+/// How the color of rays that don't hit anything is computed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SkyMode {
+    /// Blends between the ground, horizon and zenith colors of the [`Sky`]
+    Gradient,
+    /// The same color in every direction, useful for product renders
+    Solid(Vec3),
+    /// An HDR photo of a real environment, lighting the scene like it would be there.
+    /// The sky is black until [`Sky::environment_path`] is loaded.
+    Equirect,
+}
+
+/// Exponential distance fog
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fog {
+    pub color: Vec3,
+    /// How much of the light is absorbed per unit of distance, 0.0 disables the fog
+    pub density: f32,
+}
+
+impl Fog {
+    /// Distance travelled through the fog by a ray going straight up to the sky.
+    /// Rays closer to the horizon travel further.
+    pub const SKY_DISTANCE: f32 = 1.0;
+
+    /// The fraction of light that goes through the fog over the given distance
+    pub fn transmittance(&self, distance: f32) -> f32 {
+        (-self.density * distance).exp()
+    }
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            color: vec3(0.7, 0.7, 0.7),
+            density: 0.0,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Material {
     pub albedo: Vec3,
+    /// Index in [`Scene::textures`] of an image multiplying the albedo
+    pub albedo_texture: Option<usize>,
+    /// Index in [`Scene::textures`] of a tangent space normal map, it should be non-color data
+    pub normal_texture: Option<usize>,
     pub roughness: f32,
+    /// Squares the roughness before using it as the GGX alpha, like most PBR renderers do.
+    /// This spreads the visible changes evenly along the roughness range,
+    /// so authored values match the ones of other tools.
+    pub perceptual_roughness: bool,
     pub metallic: f32,
+    /// Probability of the light going through the surface instead of being diffused, like glass.
+    /// Metals never transmit light.
+    pub transmission: f32,
+    /// Index of refraction of the inside of a transmissive object
+    pub ior: f32,
+    /// Reflectance of the non-metallic part at normal incidence, known as F0.
+    /// A negative value uses [`Material::DEFAULT_F0`], which suits most dielectrics.
     pub specular: f32,
     pub emissive_color: Vec3,
     pub emissive_power: f32,
+    /// Stretches the specular highlight along `tangent`, 0.0 is isotropic.
+    /// This is used to get the look of brushed metal.
+    pub anisotropy: f32,
+    /// Direction of the anisotropy in world space, projected on the surface
+    pub tangent: Vec3,
+    /// Replaces the renderer's bounce count for the paths hitting this material: they continue
+    /// until they took this many bounces, so it can be higher than the global count, like 16 for
+    /// glass while the walls stop at 3. `None` uses the renderer's count.
+    pub max_bounce: Option<u8>,
+    /// Thickness in nanometers of an iridescent coating, 0.0 disables it
+    pub film_thickness: f32,
+    pub film_ior: f32,
 }
 
 impl Default for Material {
     fn default() -> Self {
         Self {
             albedo: Vec3::ONE,
+            albedo_texture: None,
+            normal_texture: None,
             roughness: 1.0,
+            perceptual_roughness: true,
             metallic: 0.0,
+            transmission: 0.0,
+            ior: 1.5,
             specular: -1.0,
             emissive_color: Vec3::ZERO,
             emissive_power: 0.0,
+            anisotropy: 0.0,
+            tangent: Vec3::X,
+            max_bounce: None,
+            film_thickness: 0.0,
+            film_ior: 1.33,
         }
     }
 }
 
+/// A display name and a constructor for a [`Material`] preset
+pub type MaterialPreset = (&'static str, fn() -> Material);
+
 impl Material {
+    pub const MAX_EMISSIVE_POWER: f32 = 1000.0;
+    /// F0 of most dielectrics like plastic, wood or stone
+    pub const DEFAULT_F0: f32 = 0.04;
+    /// Index of refraction of what's under the thin film coating
+    pub const FILM_SUBSTRATE_IOR: f32 = 1.5;
+
+    pub const PRESETS: [MaterialPreset; 6] = [
+        ("Matte Plastic", Self::matte_plastic),
+        ("Polished Metal", Self::polished_metal),
+        ("Gold", Self::gold),
+        ("Glass", Self::glass),
+        ("Mirror", Self::mirror),
+        ("Emissive", Self::emissive),
+    ];
+
+    pub fn matte_plastic() -> Self {
+        Self {
+            albedo: vec3(0.8, 0.8, 0.8),
+            roughness: 1.0,
+            ..default()
+        }
+    }
+
+    pub fn polished_metal() -> Self {
+        Self {
+            albedo: vec3(0.9, 0.9, 0.9),
+            roughness: 0.1,
+            metallic: 1.0,
+            ..default()
+        }
+    }
+
+    pub fn gold() -> Self {
+        Self {
+            albedo: vec3(1.0, 0.766, 0.336),
+            roughness: 0.2,
+            metallic: 1.0,
+            ..default()
+        }
+    }
+
+    pub fn glass() -> Self {
+        Self {
+            albedo: Vec3::ONE,
+            roughness: 0.0,
+            transmission: 1.0,
+            ior: 1.5,
+            ..default()
+        }
+    }
+
+    pub fn mirror() -> Self {
+        Self {
+            albedo: vec3(0.95, 0.95, 0.95),
+            roughness: 0.0,
+            metallic: 1.0,
+            ..default()
+        }
+    }
+
+    pub fn emissive() -> Self {
+        Self {
+            emissive_color: Vec3::ONE,
+            emissive_power: 5.0,
+            ..default()
+        }
+    }
+
+    pub fn f0(&self) -> f32 {
+        if self.specular < 0.0 {
+            Self::DEFAULT_F0
+        } else {
+            self.specular.min(1.0)
+        }
+    }
+
+    /// The alpha parameter of the GGX distribution
+    pub fn alpha(&self) -> f32 {
+        if self.perceptual_roughness {
+            self.roughness * self.roughness
+        } else {
+            self.roughness
+        }
+    }
+
     pub fn get_emission(&self) -> Vec3 {
-        self.emissive_color * self.emissive_power
+        // A single path hitting an extremely bright emitter can overflow the accumulation
+        let power = if self.emissive_power.is_finite() {
+            self.emissive_power.clamp(0.0, Self::MAX_EMISSIVE_POWER)
+        } else {
+            0.0
+        };
+        self.emissive_color * power
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Sphere {
     pub position: Vec3,
+    /// Should always be positive, use `inside_out` to render the inside of a sphere
     pub radius: f32,
     pub material_id: usize,
+    /// Flips the normal and only keeps the far intersection.
+    /// This is useful to use a sphere as a sky dome or a room surrounding the scene.
+    pub inside_out: bool,
+    /// Emission added on top of the material, to make any object glow without a new material
+    pub emissive_override: Option<Vec3>,
+    /// Written in the object ID pass. It's saved with the scene and doesn't depend on the position
+    /// of the sphere in the list, so masks made from a render still match after adding or removing
+    /// objects.
+    pub id: u32,
+}
+
+impl Sphere {
+    /// Smallest radius allowed when editing a sphere
+    pub const MIN_RADIUS: f32 = 0.001;
+
+    pub fn bounds(&self) -> (Vec3, f32) {
+        (self.position, self.radius)
+    }
+
+    /// Tessellates the sphere as a UV sphere centered on the origin.
+    /// This is used to compare the triangle intersection with the analytic one.
+    pub fn tessellate(&self, sectors: usize) -> Mesh {
+        shape::UVSphere {
+            radius: self.radius,
+            sectors,
+            stacks: (sectors / 2).max(2),
+        }
+        .into()
+    }
 }
 
 impl Default for Sphere {
@@ -85,6 +512,9 @@ impl Default for Sphere {
             position: Vec3::ZERO,
             radius: 0.5,
             material_id: 0,
+            inside_out: false,
+            emissive_override: None,
+            id: next_object_id(),
         }
     }
 }
@@ -92,13 +522,230 @@ impl Default for Sphere {
 #[derive(Debug, Clone)]
 pub struct TriangleMesh {
     pub transform: Transform,
-    pub mesh: Mesh,
+    /// The asset the mesh data is loaded from
+    pub handle: Handle<Mesh>,
+    /// Copied from the asset by [`sync_mesh_assets`], `None` until it's loaded
+    pub mesh: Option<Mesh>,
     pub material_id: usize,
+    /// Same as [`Sphere::emissive_override`]
+    pub emissive_override: Option<Vec3>,
+    /// Same as [`Sphere::id`], but meshes aren't saved so it's only kept during a session
+    pub id: u32,
     pub aabb: Aabb,
+    /// Set when the vertices changed, the AABB is then recomputed by [`crate::bvh::build_mesh_bvhs`]
+    pub aabb_dirty: bool,
+    /// Built in the background by [`crate::bvh::build_mesh_bvhs`], `None` until it's ready
+    pub bvh: Option<Bvh>,
+    /// Changes every time the vertices change, a BVH built in the background is only attached to a
+    /// mesh with the same revision as the vertices it was built from
+    pub revision: u64,
+    /// Inverse of `transform` with the transform it was computed from, see
+    /// [`TriangleMesh::world_to_local`]
+    world_to_local: (Transform, Affine3A),
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Revisions are unique across every mesh so a BVH can't be attached to another mesh that
+/// replaced the one it was built for
+fn next_mesh_revision() -> u64 {
+    static NEXT_REVISION: AtomicU64 = AtomicU64::new(0);
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
+}
+
+impl TriangleMesh {
+    /// Center and radius of a sphere containing the mesh, based on its AABB
+    pub fn bounds(&self) -> (Vec3, f32) {
+        (
+            self.transform.transform_point(self.aabb.center.into()),
+            self.aabb.half_extents.length() * self.transform.scale.abs().max_element(),
+        )
+    }
+
+    pub fn new(handle: Handle<Mesh>, transform: Transform, material_id: usize) -> Self {
+        Self {
+            transform,
+            handle,
+            mesh: None,
+            material_id,
+            emissive_override: None,
+            id: next_object_id(),
+            aabb: Aabb::default(),
+            aabb_dirty: true,
+            bvh: None,
+            revision: next_mesh_revision(),
+            world_to_local: (transform, transform.compute_affine().inverse()),
+        }
+    }
+
+    /// Must be called after editing the vertices, the AABB and the BVH are stale
+    pub fn mark_changed(&mut self) {
+        self.aabb_dirty = true;
+        self.bvh = None;
+        self.revision = next_mesh_revision();
+    }
+
+    pub fn recompute_aabb(&mut self) {
+        self.aabb = self
+            .mesh
+            .as_ref()
+            .and_then(Mesh::compute_aabb)
+            .unwrap_or_default();
+        self.aabb_dirty = false;
+    }
+
+    /// Brings world space positions into the space of the vertices.
+    ///
+    /// Every ray tested against the mesh needs it, so the inverse is cached by
+    /// [`Self::update_world_to_local`]. It's only recomputed here when the transform was edited
+    /// since the last update.
+    pub fn world_to_local(&self) -> Affine3A {
+        let (transform, world_to_local) = self.world_to_local;
+        if transform == self.transform {
+            world_to_local
+        } else {
+            self.transform.compute_affine().inverse()
+        }
+    }
+
+    pub fn update_world_to_local(&mut self) {
+        if self.world_to_local.0 != self.transform {
+            self.world_to_local = (self.transform, self.transform.compute_affine().inverse());
+        }
+    }
+
+    /// Whether the stored AABB contains every vertex, used to catch stale AABBs
+    #[cfg(feature = "validate_aabbs")]
+    pub fn aabb_contains_vertices(&self) -> bool {
+        let Some(positions) = self
+            .mesh
+            .as_ref()
+            .and_then(|mesh| mesh.attribute(Mesh::ATTRIBUTE_POSITION))
+            .and_then(|positions| positions.as_float3())
+        else {
+            return true;
+        };
+        // Some slack for the rounding of the center and half extents
+        let min = Vec3::from(self.aabb.min()) - 1e-4;
+        let max = Vec3::from(self.aabb.max()) + 1e-4;
+        positions.iter().all(|position| {
+            let position = Vec3::from(*position);
+            position.cmpge(min).all() && position.cmple(max).all()
+        })
+    }
+}
+
+/// Checks that a mesh has the attributes used by the renderer, the `u16` indices are converted to
+/// `u32` indices
+fn prepare_mesh(mut mesh: Mesh) -> Result<Mesh, String> {
+    for (attribute, name) in [
+        (Mesh::ATTRIBUTE_POSITION, "positions"),
+        (Mesh::ATTRIBUTE_NORMAL, "normals"),
+    ] {
+        if mesh
+            .attribute(attribute)
+            .and_then(VertexAttributeValues::as_float3)
+            .is_none()
+        {
+            return Err(format!("the vertex {name} are missing or aren't 3 floats"));
+        }
+    }
+    match mesh.indices() {
+        Some(Indices::U32(_)) => {}
+        Some(Indices::U16(indices)) => {
+            let indices = indices.iter().map(|i| *i as u32).collect();
+            mesh.set_indices(Some(Indices::U32(indices)));
+        }
+        None => return Err("meshes without indices aren't supported".to_string()),
+    }
+    Ok(mesh)
+}
+
+/// Copies the meshes loaded by the asset server into the scene.
+///
+/// The renderer only has access to the [`Scene`], so it skips meshes until their asset is loaded.
+/// When an asset is modified, for example by hot reloading, its copy and BVH are updated.
+/// Meshes that fail to load or that the renderer can't use are reported in [`LoadErrors`].
+pub fn sync_mesh_assets(
+    mut scene: ResMut<Scene>,
+    mesh_assets: Res<Assets<Mesh>>,
+    mut asset_events: EventReader<AssetEvent<Mesh>>,
+    mut renderer: ResMut<Renderer>,
+    asset_server: Res<AssetServer>,
+    mut load_errors: ResMut<LoadErrors>,
+    // Meshes that failed to load are only reported once, until their asset changes
+    mut rejected: Local<Vec<Handle<Mesh>>>,
+) {
+    let modified: Vec<_> = asset_events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { handle } => Some(handle),
+            _ => None,
+        })
+        .collect();
+    rejected.retain(|handle| !modified.contains(&handle));
+    if modified.is_empty()
+        && scene
+            .meshes
+            .iter()
+            .all(|mesh| mesh.mesh.is_some() || rejected.contains(&mesh.handle))
+    {
+        return;
+    }
+
+    for (i, mesh) in scene.meshes.iter_mut().enumerate() {
+        if (mesh.mesh.is_some() || rejected.contains(&mesh.handle))
+            && !modified.contains(&&mesh.handle)
+        {
+            continue;
+        }
+        let path = asset_server.get_handle_path(&mesh.handle).map_or_else(
+            || format!("mesh {i}"),
+            |path| path.path().display().to_string(),
+        );
+        if asset_server.get_load_state(&mesh.handle) == LoadState::Failed {
+            load_errors.push(LoadError::Asset { path });
+            rejected.push(mesh.handle.clone());
+            continue;
+        }
+        let Some(asset) = mesh_assets.get(&mesh.handle) else {
+            continue;
+        };
+        mesh.mesh = match prepare_mesh(asset.clone()) {
+            Ok(asset) => Some(asset),
+            Err(message) => {
+                load_errors.push(LoadError::Unsupported { path, message });
+                rejected.push(mesh.handle.clone());
+                None
+            }
+        };
+        mesh.mark_changed();
+        renderer.reset_frame_index();
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Light {
+    /// Points toward the light, the light shines in the opposite direction
     pub direction: Vec3,
+    /// Irradiance of a surface facing the light, a white diffuse surface reflects `intensity / PI`
     pub intensity: f32,
 }
+
+/// A light shining in every direction from a single point
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PointLight {
+    pub position: Vec3,
+    pub color: Vec3,
+    /// Irradiance at a distance of 1.0, it falls off with the square of the distance.
+    /// Like [`Light::intensity`], a white diffuse surface reflects `intensity / PI`.
+    pub intensity: f32,
+}
+
+impl Default for PointLight {
+    fn default() -> Self {
+        Self {
+            position: vec3(0.0, 2.0, 0.0),
+            color: Vec3::ONE,
+            intensity: 5.0,
+        }
+    }
+}
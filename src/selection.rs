@@ -0,0 +1,77 @@
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+
+use crate::{
+    camera::CustomCamera,
+    renderer::Renderer,
+    scene::{ObjectRef, Scene},
+};
+
+#[derive(Debug, Resource)]
+pub struct Selection {
+    pub object: Option<ObjectRef>,
+    /// Distance moved by a single nudge, holding shift moves 10 times further
+    pub nudge_step: f32,
+    /// Axis of the translate gizmo handle being dragged
+    pub dragged_axis: Option<Vec3>,
+}
+
+impl Default for Selection {
+    fn default() -> Self {
+        Self {
+            object: None,
+            nudge_step: 0.05,
+            dragged_axis: None,
+        }
+    }
+}
+
+/// Moves the selected object along the world axes.
+/// The arrows move it on the horizontal plane and page up/down move it along the camera's up axis.
+pub fn nudge_selection(
+    mut egui_context: EguiContexts,
+    keyboard_input: Res<Input<KeyCode>>,
+    selection: Res<Selection>,
+    mut scene: ResMut<Scene>,
+    mut renderer: ResMut<Renderer>,
+    camera: Res<CustomCamera>,
+) {
+    let Some(object) = selection.object else {
+        return;
+    };
+    // Don't steal the arrows from text fields
+    if egui_context.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+
+    let up = camera.world_up();
+    // -Z when Y is up and Y when Z is up
+    let away = up.cross(Vec3::X);
+    let mut direction = Vec3::ZERO;
+    for (key, axis) in [
+        (KeyCode::Left, Vec3::NEG_X),
+        (KeyCode::Right, Vec3::X),
+        (KeyCode::Up, away),
+        (KeyCode::Down, -away),
+        (KeyCode::PageUp, up),
+        (KeyCode::PageDown, -up),
+    ] {
+        if keyboard_input.just_pressed(key) {
+            direction += axis;
+        }
+    }
+    if direction == Vec3::ZERO {
+        return;
+    }
+
+    let mut step = selection.nudge_step;
+    if keyboard_input.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+        step *= 10.0;
+    }
+
+    let before = scene.object_bounds(object);
+    if let Some(position) = scene.object_position_mut(object) {
+        *position += direction * step;
+        renderer.reset_moved_object(&camera, before, scene.object_bounds(object));
+    }
+}
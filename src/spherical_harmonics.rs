@@ -0,0 +1,81 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+
+/// Number of latitude steps used to integrate the radiance, there are twice as many longitude steps
+const INTEGRATION_STEPS: usize = 32;
+
+/// Irradiance of a distant environment stored as the first 9 spherical harmonics coefficients.
+///
+/// This is only accurate for very low frequency lighting like a sky gradient, but it's really cheap
+/// to evaluate for any normal.
+///
+/// Reference:
+/// * Ramamoorthi and Hanrahan, An Efficient Representation for Irradiance Environment Maps:
+///   https://cseweb.ucsd.edu/~ravir/papers/envmap/envmap.pdf
+#[derive(Debug, Clone, Default)]
+pub struct ShIrradiance {
+    coefficients: [Vec3; 9],
+}
+
+impl ShIrradiance {
+    /// Projects the radiance coming from every direction on the spherical harmonics basis
+    pub fn from_radiance(radiance: impl Fn(Vec3) -> Vec3) -> Self {
+        let mut coefficients = [Vec3::ZERO; 9];
+        let theta_step = PI / INTEGRATION_STEPS as f32;
+        let phi_step = 2.0 * PI / (2 * INTEGRATION_STEPS) as f32;
+        for i in 0..INTEGRATION_STEPS {
+            let theta = (i as f32 + 0.5) * theta_step;
+            let solid_angle = theta.sin() * theta_step * phi_step;
+            for j in 0..2 * INTEGRATION_STEPS {
+                let phi = (j as f32 + 0.5) * phi_step;
+                let direction = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let radiance = radiance(direction) * solid_angle;
+                for (coefficient, basis) in coefficients.iter_mut().zip(sh_basis(direction)) {
+                    *coefficient += radiance * basis;
+                }
+            }
+        }
+
+        // Convolution with the clamped cosine lobe, this turns radiance into irradiance
+        const BAND_FACTORS: [f32; 3] = [PI, 2.0 * PI / 3.0, PI / 4.0];
+        for (i, coefficient) in coefficients.iter_mut().enumerate() {
+            let band = match i {
+                0 => 0,
+                1..=3 => 1,
+                _ => 2,
+            };
+            *coefficient *= BAND_FACTORS[band];
+        }
+        Self { coefficients }
+    }
+
+    /// Irradiance received by a surface facing `normal`
+    pub fn irradiance(&self, normal: Vec3) -> Vec3 {
+        self.coefficients
+            .iter()
+            .zip(sh_basis(normal))
+            .map(|(coefficient, basis)| *coefficient * basis)
+            .sum::<Vec3>()
+            .max(Vec3::ZERO)
+    }
+}
+
+/// The real spherical harmonics basis up to the second band
+fn sh_basis(n: Vec3) -> [f32; 9] {
+    [
+        0.282095,
+        0.488603 * n.y,
+        0.488603 * n.z,
+        0.488603 * n.x,
+        1.092548 * n.x * n.y,
+        1.092548 * n.y * n.z,
+        0.315392 * (3.0 * n.z * n.z - 1.0),
+        1.092548 * n.x * n.z,
+        0.546274 * (n.x * n.x - n.y * n.y),
+    ]
+}
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    image_utils::{bilinear, open_image, LinearImage},
+    load_error::{LoadError, LoadErrors},
+    math_utils::srgb_to_linear,
+    renderer::Renderer,
+    scene::Scene,
+};
+
+/// How a texture is sampled between the centers of its pixels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextureFilter {
+    /// Shows the pixels as squares
+    Nearest,
+    /// Blends the 4 closest pixels
+    #[default]
+    Bilinear,
+}
+
+impl TextureFilter {
+    pub const ALL: [Self; 2] = [Self::Nearest, Self::Bilinear];
+}
+
+/// An image file used by the materials, see [`crate::scene::Material::albedo_texture`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Texture {
+    pub path: String,
+    pub filter: TextureFilter,
+    /// The pixels are used as they are instead of being decoded from sRGB, for data like normal maps
+    pub non_color: bool,
+    /// Loaded by [`sync_textures`], not saved like [`crate::scene::Sky::environment`]
+    #[serde(skip)]
+    pub data: Option<Arc<LinearImage>>,
+}
+
+impl Texture {
+    pub fn new(path: String) -> Self {
+        Self { path, ..default() }
+    }
+
+    /// Returns the linear color at a UV coordinate, the texture repeats outside of [0, 1].
+    /// Textures that aren't loaded yet are white so they don't change the albedo.
+    pub fn sample(&self, uv: Vec2) -> Vec3 {
+        let Some(data) = &self.data else {
+            return Vec3::ONE;
+        };
+        let position = uv * Vec2::new(data.width as f32, data.height as f32);
+        match self.filter {
+            TextureFilter::Nearest => {
+                data.wrapped(position.x.floor() as isize, position.y.floor() as isize)
+            }
+            TextureFilter::Bilinear => bilinear(position, |x, y| data.wrapped(x, y)),
+        }
+    }
+
+    /// Loads the 8-bit image of the texture, decoded from sRGB unless it's non-color data
+    pub fn load(&self) -> Result<LinearImage, LoadError> {
+        let image = open_image(&self.path)?.into_rgb8();
+        Ok(LinearImage {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            pixels: image
+                .pixels()
+                .map(|pixel| {
+                    Vec3::from(pixel.0.map(|channel| {
+                        let value = channel as f32 / 255.0;
+                        if self.non_color {
+                            value
+                        } else {
+                            srgb_to_linear(value)
+                        }
+                    }))
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Loads the textures of the scene that don't have their pixels yet, for example after loading a
+/// project. A path that fails to load is only reported once.
+pub fn sync_textures(
+    mut scene: ResMut<Scene>,
+    mut renderer: ResMut<Renderer>,
+    mut load_errors: ResMut<LoadErrors>,
+    mut failed_paths: Local<Vec<String>>,
+) {
+    if scene
+        .textures
+        .iter()
+        .all(|texture| texture.data.is_some() || failed_paths.contains(&texture.path))
+    {
+        return;
+    }
+
+    for texture in &mut scene.textures {
+        if texture.data.is_some() || failed_paths.contains(&texture.path) {
+            continue;
+        }
+        match texture.load() {
+            Ok(data) => {
+                texture.data = Some(Arc::new(data));
+                renderer.reset_frame_index();
+            }
+            Err(err) => {
+                load_errors.push(err);
+                failed_paths.push(texture.path.clone());
+            }
+        }
+    }
+}
@@ -1,16 +1,32 @@
 use crate::{
-    camera::CustomCamera,
+    accumulation::AccumulationPrecision,
+    camera::{CameraBookmark, CameraBookmarks, CameraProjection, CustomCamera},
+    denoiser::MAX_DENOISER_ITERATIONS,
     egui_utils::{
-        drag_f32, drag_f32_clamp, drag_u8, drag_usize, drag_vec3, drag_vec3_color,
-        fmt_usize_separator,
+        drag_f32_clamp, drag_index, drag_u8, drag_usize, drag_vec3, drag_vec3_clamp,
+        drag_vec3_color, fmt_usize_separator, persisted_path_edit,
     },
-    renderer::Renderer,
-    scene::Scene,
+    export::{copy_to_clipboard, save_exr, save_object_id_png, save_pfm, save_png, save_ppm},
+    gizmos::{draw_aabbs, draw_gizmos, draw_path, translate_gizmo, PathDebug, ShowGizmos},
+    gltf_import::import_gltf,
+    load_error::LoadErrors,
+    project::{load_project, save_project, ProjectPath, Scenes},
+    renderer::{
+        pick, probe, sample_points, OutputColorSpace, PixelFilter, QualityPreset, RenderMode,
+        RenderOutput, Renderer, SampleDimension, Sampler, Tonemapping,
+    },
+    scene::{Material, ObjectRef, PointLight, Scene, SkyMode, Sphere, TriangleMesh},
+    selection::Selection,
+    texture::{Texture, TextureFilter},
     Frametimes, RenderScale, ViewportEguiTexture, ViewportSize,
 };
 
+use std::fs;
+
 use bevy::{
+    app::AppExit,
     diagnostic::{Diagnostic, Diagnostics, FrameTimeDiagnosticsPlugin},
+    ecs::system::SystemParam,
     prelude::*,
 };
 use bevy_egui::{
@@ -18,18 +34,37 @@ use bevy_egui::{
     EguiContexts,
 };
 use egui_dock::{DockArea, NodeIndex, Style, Tree};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Tabs {
     Viewport,
     Settings,
     Scene,
+    Stats,
 }
 
 #[derive(Deref, DerefMut, Resource)]
 pub struct DockTree(pub Tree<Tabs>);
 
+/// The dock layout is saved to this file on exit, and restored from it on startup
+const DOCK_LAYOUT_PATH: &str = "dock_layout.ron";
+
 pub fn setup_ui(mut commands: Commands) {
+    let tree = fs::read_to_string(DOCK_LAYOUT_PATH)
+        .ok()
+        .and_then(|ron| match ron::from_str(&ron) {
+            Ok(tree) => Some(tree),
+            Err(err) => {
+                warn!("Failed to parse {DOCK_LAYOUT_PATH}, using the default layout: {err}");
+                None
+            }
+        })
+        .unwrap_or_else(default_dock_tree);
+    commands.insert_resource(DockTree(tree));
+}
+
+fn default_dock_tree() -> Tree<Tabs> {
     // Setup dock tree to look like this:
     //  __________________________
     // |               | Scene    |
@@ -40,9 +75,35 @@ pub fn setup_ui(mut commands: Commands) {
 
     let mut tree = Tree::new(vec![Tabs::Viewport]);
     let [_viewport, scene] = tree.split_right(NodeIndex::root(), 0.75, vec![Tabs::Scene]);
-    tree.split_below(scene, 0.85, vec![Tabs::Settings]);
+    tree.split_below(scene, 0.85, vec![Tabs::Settings, Tabs::Stats]);
+    tree
+}
 
-    commands.insert_resource(DockTree(tree));
+/// Saves the dock layout when the app exits so the panels stay where they were moved
+pub fn save_dock_layout(mut exit_events: EventReader<AppExit>, tree: Res<DockTree>) {
+    if exit_events.iter().last().is_none() {
+        return;
+    }
+    let result = ron::to_string(&tree.0)
+        .map_err(|err| err.to_string())
+        .and_then(|ron| fs::write(DOCK_LAYOUT_PATH, ron).map_err(|err| err.to_string()));
+    if let Err(err) = result {
+        warn!("Failed to save the dock layout to {DOCK_LAYOUT_PATH}: {err}");
+    }
+}
+
+/// The resources used to manage the project, grouped to stay under the system param limit
+#[derive(SystemParam)]
+pub struct ProjectParams<'w> {
+    path: ResMut<'w, ProjectPath>,
+    scenes: ResMut<'w, Scenes>,
+    load_errors: ResMut<'w, LoadErrors>,
+}
+
+#[derive(SystemParam)]
+pub struct CameraParams<'w> {
+    camera: ResMut<'w, CustomCamera>,
+    bookmarks: ResMut<'w, CameraBookmarks>,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -53,10 +114,15 @@ pub fn draw_dock_area(
     viewport_egui_texture: Res<ViewportEguiTexture>,
     mut viewport_size: ResMut<ViewportSize>,
     render_dt: Res<Frametimes>,
-    mut camera: ResMut<CustomCamera>,
+    mut camera: CameraParams,
     mut renderer: ResMut<Renderer>,
     mut viewport_scale: ResMut<RenderScale>,
     diagnostics: Res<Diagnostics>,
+    mut project: ProjectParams,
+    mut selection: ResMut<Selection>,
+    mut show_gizmos: ResMut<ShowGizmos>,
+    mut mesh_assets: ResMut<Assets<Mesh>>,
+    mut path_debug: ResMut<PathDebug>,
 ) {
     puffin::profile_function!();
     let frame_time = diagnostics
@@ -71,9 +137,17 @@ pub fn draw_dock_area(
         dt: frame_time as f32,
         frametimes: &render_dt,
         scene: &mut scene,
-        camera: &mut camera,
+        camera: &mut camera.camera,
+        camera_bookmarks: &mut camera.bookmarks.0,
         renderer: &mut renderer,
-        viewport_scale: &mut viewport_scale.0,
+        viewport_scale: &mut viewport_scale,
+        project_path: &mut project.path.0,
+        scenes: &mut project.scenes,
+        load_errors: &mut project.load_errors,
+        selection: &mut selection,
+        show_gizmos: &mut show_gizmos.0,
+        mesh_assets: &mut mesh_assets,
+        path_debug: &mut path_debug,
     };
 
     DockArea::new(&mut tree)
@@ -81,6 +155,19 @@ pub fn draw_dock_area(
         .show(egui_context.ctx_mut(), &mut tab_viewer);
 }
 
+/// Gray level of the sky of the furnace test scene
+const FURNACE_SKY_COLOR: f32 = 0.5;
+
+/// Whether the hover probe is shown, stored in the egui memory
+fn probe_id() -> egui::Id {
+    egui::Id::new("show_hover_probe")
+}
+
+/// Whether the bounding boxes are drawn over the viewport, stored in the egui memory
+fn aabbs_id() -> egui::Id {
+    egui::Id::new("show_aabbs")
+}
+
 pub struct TabViewer<'a> {
     pub viewport_texture: TextureId,
     pub viewport_size: &'a mut Vec2,
@@ -88,8 +175,49 @@ pub struct TabViewer<'a> {
     pub frametimes: &'a Frametimes,
     pub scene: &'a mut Scene,
     pub camera: &'a mut CustomCamera,
+    pub camera_bookmarks: &'a mut Vec<CameraBookmark>,
     pub renderer: &'a mut Renderer,
-    pub viewport_scale: &'a mut f32,
+    pub viewport_scale: &'a mut RenderScale,
+    pub project_path: &'a mut String,
+    pub scenes: &'a mut Scenes,
+    pub load_errors: &'a mut LoadErrors,
+    pub selection: &'a mut Selection,
+    pub show_gizmos: &'a mut bool,
+    pub mesh_assets: &'a mut Assets<Mesh>,
+    pub path_debug: &'a mut PathDebug,
+}
+
+impl<'a> TabViewer<'a> {
+    /// Shows the hovered pixel and what the camera ray going through it hits in a corner of the
+    /// viewport
+    fn draw_probe(&self, ui: &egui::Ui, rect: egui::Rect, hover: egui::Pos2) {
+        let uv = (hover - rect.min) / rect.size();
+        let x = ((uv.x * self.renderer.width as f32) as usize)
+            .min(self.renderer.width.saturating_sub(1));
+        let y = ((uv.y * self.renderer.height as f32) as usize)
+            .min(self.renderer.height.saturating_sub(1));
+        let ndc = Vec2::new(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+
+        let mut text = format!("Pixel: {x} {y}");
+        match probe(self.scene, self.camera, ndc) {
+            Some(hit) => {
+                let p = hit.position;
+                text += &format!(
+                    "\nPosition: {:.3} {:.3} {:.3}\nMaterial: {}\nDistance: {:.3}",
+                    p.x, p.y, p.z, hit.material_id, hit.distance
+                );
+            }
+            None => text += "\nSky",
+        }
+
+        let painter = ui.painter_at(rect);
+        let galley =
+            painter.layout_no_wrap(text, egui::FontId::monospace(12.0), egui::Color32::WHITE);
+        let text_rect =
+            egui::Rect::from_min_size(rect.min + egui::vec2(8.0, 8.0), galley.size()).expand(4.0);
+        painter.rect_filled(text_rect, 2.0, egui::Color32::from_black_alpha(160));
+        painter.galley(text_rect.min + egui::vec2(4.0, 4.0), galley);
+    }
 }
 
 impl<'a> egui_dock::TabViewer for TabViewer<'a> {
@@ -100,9 +228,130 @@ impl<'a> egui_dock::TabViewer for TabViewer<'a> {
         match tab {
             Tabs::Viewport => {
                 *self.viewport_size = Vec2::from_array(ui.available_size().into());
-                ui.image(self.viewport_texture, ui.available_size());
+                let response = ui.add(
+                    egui::Image::new(self.viewport_texture, ui.available_size())
+                        .sense(egui::Sense::click_and_drag()),
+                );
+                if *self.show_gizmos {
+                    let painter = ui.painter_at(response.rect);
+                    draw_gizmos(&painter, self.camera, self.scene, self.selection);
+                    let before = self
+                        .selection
+                        .object
+                        .and_then(|object| self.scene.object_bounds(object));
+                    if translate_gizmo(ui, &response, self.camera, self.scene, self.selection) {
+                        let after = self
+                            .selection
+                            .object
+                            .and_then(|object| self.scene.object_bounds(object));
+                        self.renderer.reset_moved_object(self.camera, before, after);
+                    }
+                }
+                let show_aabbs =
+                    ui.data_mut(|data| *data.get_persisted_mut_or_default::<bool>(aabbs_id()));
+                if show_aabbs {
+                    draw_aabbs(&ui.painter_at(response.rect), self.camera, self.scene);
+                }
+                if self.path_debug.enabled {
+                    draw_path(&ui.painter_at(response.rect), self.camera, self.path_debug);
+                }
+                let show_probe =
+                    ui.data_mut(|data| *data.get_persisted_mut_or_default::<bool>(probe_id()));
+                if let Some(hover) = response.hover_pos().filter(|_| show_probe) {
+                    self.draw_probe(ui, response.rect, hover);
+                }
+                if response.clicked() {
+                    if let Some(pointer) = response.interact_pointer_pos() {
+                        let uv = (pointer - response.rect.min) / response.rect.size();
+                        if self.path_debug.enabled {
+                            let x = ((uv.x * self.renderer.width as f32) as usize)
+                                .min(self.renderer.width.saturating_sub(1));
+                            let y = ((uv.y * self.renderer.height as f32) as usize)
+                                .min(self.renderer.height.saturating_sub(1));
+                            self.path_debug.uv = Some(Vec2::new(uv.x, uv.y));
+                            self.path_debug.path = self.renderer.debug_path(
+                                self.scene,
+                                self.camera,
+                                y * self.renderer.width + x,
+                            );
+                        } else {
+                            // Not rounded to a pixel so it also works when the render scale is low
+                            let ndc = Vec2::new(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+                            self.selection.object = pick(self.scene, self.camera, ndc);
+                        }
+                    }
+                }
             }
             Tabs::Scene => {
+                ui.horizontal(|ui| {
+                    let active = self.scenes.active();
+                    let mut selected = active;
+                    egui::ComboBox::from_id_source("scenes")
+                        .selected_text(self.scenes.list[active].name.clone())
+                        .show_ui(ui, |ui| {
+                            for (i, named_scene) in self.scenes.list.iter().enumerate() {
+                                ui.selectable_value(&mut selected, i, &named_scene.name);
+                            }
+                        });
+                    ui.text_edit_singleline(&mut self.scenes.list[active].name);
+                    if selected != active {
+                        self.scenes
+                            .switch_to(selected, self.scene, self.camera, self.renderer);
+                        self.selection.object = None;
+                    }
+                    if ui.button("New Scene").clicked() {
+                        self.scenes.add_new(self.scene, self.camera, self.renderer);
+                        self.selection.object = None;
+                    }
+                    if ui.button("Duplicate Scene").clicked() {
+                        self.scenes
+                            .duplicate_active(self.scene, self.camera, self.renderer);
+                        self.selection.object = None;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("File");
+                    let path = persisted_path_edit(ui, "scene_path", "scene.ron");
+                    if ui
+                        .button("Save")
+                        .on_hover_text("Only the scene, meshes aren't saved")
+                        .clicked()
+                    {
+                        if let Err(err) = self.scene.save_ron(&path) {
+                            self.load_errors.push(err);
+                        }
+                    }
+                    if ui.button("Load").clicked() {
+                        match Scene::load_ron(&path)
+                            .and_then(|scene| self.scene.replace_keep_meshes(scene, &path))
+                        {
+                            Ok(()) => {
+                                self.selection.object = None;
+                                reset = true;
+                            }
+                            Err(err) => self.load_errors.push(err),
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("glTF");
+                    let path = persisted_path_edit(ui, "gltf_path", "scene.glb");
+                    if ui
+                        .button("Import")
+                        .on_hover_text("Replaces the objects, materials and lights of the scene")
+                        .clicked()
+                    {
+                        match import_gltf(&path, self.scene, self.camera, self.mesh_assets) {
+                            Ok(()) => {
+                                self.selection.object = None;
+                                reset = true;
+                            }
+                            Err(err) => self.load_errors.push(err),
+                        }
+                    }
+                });
+                ui.separator();
+
                 ui.heading("Camera");
                 egui::Grid::new("camera_grid")
                     .num_columns(2)
@@ -110,30 +359,191 @@ impl<'a> egui_dock::TabViewer for TabViewer<'a> {
                         ui.label("Position");
                         reset |= drag_vec3(ui, &mut self.camera.position, 0.1);
                         ui.end_row();
+
+                        let mut near_clip = self.camera.near_clip();
+                        let mut far_clip = self.camera.far_clip();
+                        ui.label("Near Clip");
+                        let mut clip_changed =
+                            drag_f32_clamp(ui, &mut near_clip, 0.01, 0.001..=far_clip);
+                        ui.end_row();
+                        ui.label("Far Clip");
+                        clip_changed |=
+                            drag_f32_clamp(ui, &mut far_clip, 0.1, near_clip..=f32::MAX);
+                        ui.end_row();
+                        if clip_changed {
+                            self.camera.set_clip(near_clip, far_clip);
+                            reset = true;
+                        }
+
+                        ui.label("Projection");
+                        ui.horizontal(|ui| {
+                            let projection_mode = self.camera.projection_mode();
+                            // Switching keeps the same area in view at the focus distance
+                            let distance = self.camera.focus_distance;
+                            let perspective = CameraProjection::Perspective {
+                                vertical_fov: projection_mode.vertical_fov_at(distance),
+                            };
+                            let orthographic = CameraProjection::Orthographic {
+                                height: projection_mode.height_at(distance),
+                            };
+                            let is_perspective =
+                                matches!(projection_mode, CameraProjection::Perspective { .. });
+                            if ui.radio(is_perspective, "Perspective").clicked() && !is_perspective
+                            {
+                                self.camera.set_projection_mode(perspective);
+                                reset = true;
+                            }
+                            if ui.radio(!is_perspective, "Orthographic").clicked() && is_perspective
+                            {
+                                self.camera.set_projection_mode(orthographic);
+                                reset = true;
+                            }
+                        });
+                        ui.end_row();
+
+                        match self.camera.projection_mode() {
+                            CameraProjection::Perspective { mut vertical_fov } => {
+                                ui.label("Vertical FOV");
+                                if drag_f32_clamp(ui, &mut vertical_fov, 0.5, 10.0..=120.0) {
+                                    self.camera.set_fov(vertical_fov);
+                                    reset = true;
+                                }
+                            }
+                            CameraProjection::Orthographic { mut height } => {
+                                ui.label("Height");
+                                if drag_f32_clamp(ui, &mut height, 0.05, 0.01..=f32::MAX) {
+                                    self.camera.set_projection_mode(
+                                        CameraProjection::Orthographic { height },
+                                    );
+                                    reset = true;
+                                }
+                            }
+                        }
+                        ui.end_row();
+
+                        ui.label("Up Axis");
+                        ui.horizontal(|ui| {
+                            let mut world_up = self.camera.world_up();
+                            let mut changed = ui.radio_value(&mut world_up, Vec3::Y, "Y").changed();
+                            changed |= ui.radio_value(&mut world_up, Vec3::Z, "Z").changed();
+                            if changed {
+                                self.camera.set_world_up(world_up);
+                                reset = true;
+                            }
+                        });
+                        ui.end_row();
+
+                        ui.label("Aperture")
+                            .on_hover_text("Diameter of the lens, 0 keeps everything in focus");
+                        reset |= drag_f32_clamp(ui, &mut self.camera.aperture, 0.005, 0.0..=10.0);
+                        ui.end_row();
+
+                        ui.label("Focus Distance");
+                        reset |= drag_f32_clamp(
+                            ui,
+                            &mut self.camera.focus_distance,
+                            0.05,
+                            0.01..=f32::MAX,
+                        );
+                        ui.end_row();
                     });
+                ui.horizontal(|ui| {
+                    let bounds = self.scene.bounds();
+                    if ui
+                        .add_enabled(bounds.is_some(), egui::Button::new("Frame Scene"))
+                        .on_hover_text("Moves the camera back until every object is in view")
+                        .clicked()
+                    {
+                        if let Some(bounds) = bounds {
+                            self.camera.frame_bounds(&bounds);
+                            reset = true;
+                        }
+                    }
+                    if ui
+                        .button("Copy as Code")
+                        .on_hover_text("Copies the Rust code recreating this camera")
+                        .clicked()
+                    {
+                        let snippet = self.camera.rust_snippet();
+                        ui.output_mut(|output| output.copied_text = snippet);
+                    }
+                });
                 ui.separator();
 
                 ui.heading("Sky");
                 egui::Grid::new("sky_grid").num_columns(2).show(ui, |ui| {
-                    ui.label("Ground Color");
-                    reset |= drag_vec3_color(ui, &mut self.scene.sky.ground_color);
+                    let sky = &mut self.scene.sky;
+                    ui.label("Mode");
+                    ui.horizontal(|ui| {
+                        let is_gradient = sky.mode == SkyMode::Gradient;
+                        let is_solid = matches!(sky.mode, SkyMode::Solid(_));
+                        let is_equirect = sky.mode == SkyMode::Equirect;
+                        if ui.radio(is_gradient, "Gradient").clicked() && !is_gradient {
+                            sky.mode = SkyMode::Gradient;
+                            reset = true;
+                        }
+                        if ui.radio(is_solid, "Solid").clicked() && !is_solid {
+                            sky.mode = SkyMode::Solid(sky.horizon_color);
+                            reset = true;
+                        }
+                        if ui.radio(is_equirect, "Environment").clicked() && !is_equirect {
+                            sky.mode = SkyMode::Equirect;
+                            reset = true;
+                        }
+                    });
                     ui.end_row();
-                    ui.label("Horizon Color");
-                    reset |= drag_vec3_color(ui, &mut self.scene.sky.horizon_color);
+                    match &mut sky.mode {
+                        SkyMode::Gradient => {
+                            ui.label("Ground Color");
+                            reset |= drag_vec3_color(ui, &mut sky.ground_color);
+                            ui.end_row();
+                            ui.label("Horizon Color");
+                            reset |= drag_vec3_color(ui, &mut sky.horizon_color);
+                            ui.end_row();
+                            ui.label("Zenith Color");
+                            reset |= drag_vec3_color(ui, &mut sky.zenith_color);
+                            ui.end_row();
+                            ui.label("Sun Direction");
+                            reset |= drag_vec3(ui, &mut sky.sun_direction, 0.025);
+                            ui.end_row();
+                            ui.label("Sun Focus");
+                            reset |= drag_f32_clamp(ui, &mut sky.sun_focus, 1.0, 1.0..=10000.0);
+                            ui.end_row();
+                            ui.label("Sun Intensity");
+                            reset |= drag_f32_clamp(ui, &mut sky.sun_intensity, 0.05, 0.0..=1000.0);
+                            ui.end_row();
+                        }
+                        SkyMode::Solid(color) => {
+                            ui.label("Color");
+                            reset |= drag_vec3_color(ui, color);
+                            ui.end_row();
+                        }
+                        SkyMode::Equirect => {
+                            ui.label("Image");
+                            ui.horizontal(|ui| {
+                                let path = persisted_path_edit(
+                                    ui,
+                                    "environment_path",
+                                    &sky.environment_path,
+                                );
+                                if ui
+                                    .button("Load")
+                                    .on_hover_text("Equirectangular HDR or EXR image")
+                                    .clicked()
+                                {
+                                    sky.environment_path = path;
+                                    sky.environment = None;
+                                }
+                            });
+                            ui.end_row();
+                        }
+                    }
+                    ui.label("Fog Color");
+                    reset |= drag_vec3_color(ui, &mut self.scene.fog.color);
                     ui.end_row();
-                    ui.label("Zenith Color");
-                    reset |= drag_vec3_color(ui, &mut self.scene.sky.zenith_color);
+                    ui.label("Fog Density");
+                    reset |= drag_f32_clamp(ui, &mut self.scene.fog.density, 0.001, 0.0..=1.0);
                     ui.end_row();
-
-                    // ui.label("Direction");
-                    // reset |= drag_vec3(ui, &mut self.scene.sky.sun_direction, 0.025);
-                    // ui.end_row();
-                    // ui.label("Focus");
-                    // reset |= drag_f32(ui, &mut self.scene.sky.sun_focus, 0.025);
-                    // ui.end_row();
-                    // ui.label("Intensity");
-                    // reset |= drag_f32_clamp(ui, &mut self.scene.sky.sun_intensity, 0.005, 0..=1);
-                    // ui.end_row();
                 });
                 ui.separator();
 
@@ -153,23 +563,141 @@ impl<'a> egui_dock::TabViewer for TabViewer<'a> {
                     ui.separator();
                 }
 
+                ui.heading("Point Lights");
+                let mut removed = None;
+                for (i, light) in self.scene.point_lights.iter_mut().enumerate() {
+                    egui::Grid::new(format!("point_light_grid_{i}"))
+                        .num_columns(2)
+                        .show(ui, |ui| {
+                            ui.label("Position");
+                            reset |= drag_vec3(ui, &mut light.position, 0.05);
+                            ui.end_row();
+
+                            ui.label("Color");
+                            reset |= drag_vec3_color(ui, &mut light.color);
+                            ui.end_row();
+
+                            ui.label("Intensity");
+                            reset |= drag_f32_clamp(ui, &mut light.intensity, 0.05, 0.0..=f32::MAX);
+                            ui.end_row();
+                        });
+                    if ui.button("Remove").clicked() {
+                        removed = Some(i);
+                    }
+                    ui.separator();
+                }
+                if let Some(i) = removed {
+                    self.scene.point_lights.remove(i);
+                    reset = true;
+                }
+                if ui.button("Add Point Light").clicked() {
+                    self.scene.point_lights.push(PointLight::default());
+                    reset = true;
+                }
+                ui.separator();
+
+                ui.heading("Textures");
+                for texture in &mut self.scene.textures {
+                    ui.label(&texture.path);
+                    ui.horizontal(|ui| {
+                        ui.label("Filter");
+                        for filter in TextureFilter::ALL {
+                            reset |= ui
+                                .radio_value(&mut texture.filter, filter, format!("{filter:?}"))
+                                .changed();
+                        }
+                        if ui
+                            .checkbox(&mut texture.non_color, "Non-color")
+                            .on_hover_text("Use the pixels as they are, for normal maps")
+                            .changed()
+                        {
+                            // sync_textures loads it again with the new decoding
+                            texture.data = None;
+                        }
+                    });
+                }
+                ui.horizontal(|ui| {
+                    let path = persisted_path_edit(ui, "texture_path", "");
+                    if ui.button("Add Texture").clicked() && !path.is_empty() {
+                        self.scene.textures.push(Texture::new(path));
+                    }
+                });
+                ui.separator();
+
                 ui.heading("Materials");
+                let bounces = self.renderer.bounces;
+                let textures = &self.scene.textures;
                 for (i, material) in self.scene.materials.iter_mut().enumerate() {
                     egui::Grid::new(format!("material_grid_{i}"))
                         .num_columns(2)
                         .show(ui, |ui| {
+                            ui.label("Preset");
+                            egui::ComboBox::from_id_source(format!("material_preset_{i}"))
+                                .selected_text("Select...")
+                                .show_ui(ui, |ui| {
+                                    for (name, preset) in Material::PRESETS {
+                                        if ui.selectable_label(false, name).clicked() {
+                                            *material = preset();
+                                            reset = true;
+                                        }
+                                    }
+                                });
+                            ui.end_row();
+
                             ui.label("Albedo");
                             reset |= drag_vec3_color(ui, &mut material.albedo);
                             ui.end_row();
 
+                            ui.label("Albedo Texture");
+                            reset |= texture_combo(
+                                ui,
+                                format!("albedo_texture_{i}"),
+                                textures,
+                                &mut material.albedo_texture,
+                            );
+                            ui.end_row();
+
+                            ui.label("Normal Texture");
+                            reset |= texture_combo(
+                                ui,
+                                format!("normal_texture_{i}"),
+                                textures,
+                                &mut material.normal_texture,
+                            );
+                            ui.end_row();
+
                             ui.label("Roughness");
                             reset |= drag_f32_clamp(ui, &mut material.roughness, 0.025, 0.0..=1.0);
                             ui.end_row();
 
+                            ui.label("Perceptual Roughness");
+                            reset |= ui
+                                .checkbox(&mut material.perceptual_roughness, "")
+                                .on_hover_text("Squares the roughness, like most PBR renderers")
+                                .changed();
+                            ui.end_row();
+
                             ui.label("Metallic");
                             reset |= drag_f32_clamp(ui, &mut material.metallic, 0.025, 0.0..=1.0);
                             ui.end_row();
 
+                            ui.label("Transmission");
+                            reset |=
+                                drag_f32_clamp(ui, &mut material.transmission, 0.025, 0.0..=1.0);
+                            ui.end_row();
+
+                            ui.label("IOR");
+                            reset |= drag_f32_clamp(ui, &mut material.ior, 0.01, 1.0..=3.0);
+                            ui.end_row();
+
+                            ui.label("Anisotropy");
+                            reset |= drag_f32_clamp(ui, &mut material.anisotropy, 0.025, 0.0..=1.0);
+                            ui.end_row();
+
+                            ui.label("Tangent");
+                            reset |= drag_vec3(ui, &mut material.tangent, 0.025);
+                            ui.end_row();
+
                             ui.label("Emissive Color");
                             reset |= drag_vec3_color(ui, &mut material.emissive_color);
                             ui.end_row();
@@ -179,63 +707,204 @@ impl<'a> egui_dock::TabViewer for TabViewer<'a> {
                                 ui,
                                 &mut material.emissive_power,
                                 0.025,
-                                0.0..=1000.0,
+                                0.0..=Material::MAX_EMISSIVE_POWER,
                             );
                             ui.end_row();
-                            // ui.label("Specular");
-                            // reset |= drag_f32_clamp(ui, &mut material.specular, 1.0, 0.0..=1000.0);
-                            // ui.end_row();
+
+                            ui.label("Film Thickness");
+                            reset |=
+                                drag_f32_clamp(ui, &mut material.film_thickness, 1.0, 0.0..=2000.0);
+                            ui.end_row();
+
+                            ui.label("Film IOR");
+                            reset |= drag_f32_clamp(ui, &mut material.film_ior, 0.005, 1.0..=3.0);
+                            ui.end_row();
+
+                            ui.label("Max Bounce");
+                            ui.horizontal(|ui| {
+                                let mut enabled = material.max_bounce.is_some();
+                                if ui.checkbox(&mut enabled, "").changed() {
+                                    material.max_bounce = enabled.then_some(bounces);
+                                    reset = true;
+                                }
+                                if let Some(max_bounce) = &mut material.max_bounce {
+                                    reset |= drag_u8(ui, max_bounce, 0.25);
+                                }
+                            });
+                            ui.end_row();
+                            ui.label("Specular");
+                            // Shows the default F0 when the specular isn't set
+                            let mut f0 = material.f0();
+                            if drag_f32_clamp(ui, &mut f0, 0.005, 0.0..=1.0) {
+                                material.specular = f0;
+                                reset = true;
+                            }
+                            ui.end_row();
                         });
                     ui.separator();
                 }
 
                 ui.heading("Spheres");
+                let mut sphere_to_convert = None;
+                // Objects whose position or size changed, with their bounds before the change
+                let mut moved_objects = vec![];
                 for (i, sphere) in self.scene.spheres.iter_mut().enumerate() {
                     egui::Grid::new(format!("sphere_grid_{i}"))
                         .num_columns(2)
                         .show(ui, |ui| {
+                            ui.label("Selected");
+                            selection_checkbox(ui, self.selection, ObjectRef::Sphere(i));
+                            ui.end_row();
+
                             ui.label("Position");
-                            reset |= drag_vec3(ui, &mut sphere.position, 0.1);
+                            let bounds = sphere.bounds();
+                            if drag_vec3(ui, &mut sphere.position, 0.1) {
+                                moved_objects.push((ObjectRef::Sphere(i), bounds));
+                            }
                             ui.end_row();
 
                             ui.label("Radius");
-                            reset |= drag_f32(ui, &mut sphere.radius, 0.025);
+                            if drag_f32_clamp(
+                                ui,
+                                &mut sphere.radius,
+                                0.025,
+                                Sphere::MIN_RADIUS..=f32::MAX,
+                            ) {
+                                moved_objects.push((ObjectRef::Sphere(i), bounds));
+                            }
+                            ui.end_row();
+
+                            ui.label("Inside Out");
+                            reset |= ui.checkbox(&mut sphere.inside_out, "").changed();
+                            ui.end_row();
+
+                            ui.label("Emissive Override");
+                            reset |= emissive_override(ui, &mut sphere.emissive_override);
                             ui.end_row();
 
                             ui.label("Material id");
-                            reset |= drag_usize(
-                                ui,
-                                &mut sphere.material_id,
-                                1.0,
-                                self.scene.materials.len() - 1,
-                            );
+                            reset |=
+                                drag_index(ui, &mut sphere.material_id, self.scene.materials.len());
+                            ui.end_row();
+
+                            ui.label("Sectors");
+                            ui.horizontal(|ui| {
+                                let sectors = ui.data_mut(|data| {
+                                    *data.get_persisted_mut_or(egui::Id::new("sphere_sectors"), 32)
+                                });
+                                let mut new_sectors = sectors;
+                                ui.add(egui::DragValue::new(&mut new_sectors).clamp_range(3..=512));
+                                if new_sectors != sectors {
+                                    ui.data_mut(|data| {
+                                        data.insert_persisted(
+                                            egui::Id::new("sphere_sectors"),
+                                            new_sectors,
+                                        );
+                                    });
+                                }
+                                if ui.button("Convert to Mesh").clicked() {
+                                    sphere_to_convert = Some((i, new_sectors));
+                                }
+                            });
                             ui.end_row();
                         });
                     ui.separator();
                 }
+                if let Some((i, sectors)) = sphere_to_convert {
+                    let sphere = self.scene.spheres.remove(i);
+                    let handle = self.mesh_assets.add(sphere.tessellate(sectors));
+                    let mut mesh = TriangleMesh::new(
+                        handle,
+                        Transform::from_translation(sphere.position),
+                        sphere.material_id,
+                    );
+                    // It's the same object for compositing
+                    mesh.id = sphere.id;
+                    self.scene.meshes.push(mesh);
+                    // The indices of the following spheres changed, select the new mesh instead
+                    self.selection.object = Some(ObjectRef::Mesh(self.scene.meshes.len() - 1));
+                    reset = true;
+                }
 
                 ui.heading("Meshes");
                 for (i, mesh) in self.scene.meshes.iter_mut().enumerate() {
                     egui::Grid::new(format!("mesh_grid_{i}"))
                         .num_columns(2)
                         .show(ui, |ui| {
+                            ui.label("Selected");
+                            selection_checkbox(ui, self.selection, ObjectRef::Mesh(i));
+                            ui.end_row();
+
+                            ui.label("BVH");
+                            match &mesh.bvh {
+                                Some(bvh) => ui.label(format!("{} nodes", bvh.nodes.len())),
+                                None => {
+                                    ui.horizontal(|ui| {
+                                        ui.spinner();
+                                        ui.label("Building...");
+                                    })
+                                    .response
+                                }
+                            };
+                            ui.end_row();
+
                             ui.label("Position");
-                            reset |= drag_vec3(ui, &mut mesh.transform.translation, 0.1);
+                            let bounds = mesh.bounds();
+                            if drag_vec3(ui, &mut mesh.transform.translation, 0.1) {
+                                moved_objects.push((ObjectRef::Mesh(i), bounds));
+                            }
                             ui.end_row();
 
                             ui.label("Material id");
-                            reset |= drag_usize(
-                                ui,
-                                &mut mesh.material_id,
-                                1.0,
-                                self.scene.materials.len() - 1,
-                            );
+                            reset |=
+                                drag_index(ui, &mut mesh.material_id, self.scene.materials.len());
+                            ui.end_row();
+
+                            ui.label("Emissive Override");
+                            reset |= emissive_override(ui, &mut mesh.emissive_override);
                             ui.end_row();
                         });
                     ui.separator();
                 }
+                ui.horizontal(|ui| {
+                    ui.label("OBJ");
+                    let path = persisted_path_edit(ui, "obj_path", "mesh.obj");
+                    let material_id = egui::Id::new("obj_material");
+                    let mut material = ui
+                        .data_mut(|data| *data.get_persisted_mut_or_default::<usize>(material_id));
+                    ui.label("Material id");
+                    if drag_index(ui, &mut material, self.scene.materials.len()) {
+                        ui.data_mut(|data| data.insert_persisted(material_id, material));
+                    }
+                    if ui
+                        .button("Add Mesh")
+                        .on_hover_text("Creates a default material if the scene has none")
+                        .clicked()
+                    {
+                        let material = if self.scene.materials.is_empty() {
+                            self.scene.materials.push(Material::default());
+                            0
+                        } else {
+                            // The materials can be removed after picking one
+                            material.min(self.scene.materials.len() - 1)
+                        };
+                        match TriangleMesh::from_obj(&path, material, self.mesh_assets) {
+                            Ok(mesh) => {
+                                self.scene.meshes.push(mesh);
+                                reset = true;
+                            }
+                            Err(err) => self.load_errors.push(err),
+                        }
+                    }
+                });
+
+                for (object, before) in moved_objects {
+                    let after = self.scene.object_bounds(object);
+                    self.renderer
+                        .reset_moved_object(self.camera, Some(before), after);
+                }
             }
-            Tabs::Settings => {
+            Tabs::Stats => {
                 ui.label(format!(
                     "Viewport size: {}x{} ({} pixels)",
                     self.viewport_size.x,
@@ -252,23 +921,542 @@ impl<'a> egui_dock::TabViewer for TabViewer<'a> {
                     "Image copy dt: {:.2}ms",
                     self.frametimes.image_copy * 1000.0
                 ));
-                ui.label(format!("Samples: {}", self.renderer.samples));
+                ui.separator();
+
+                let stats = self.renderer.stats;
+                egui::Grid::new("stats_grid").num_columns(2).show(ui, |ui| {
+                    ui.label("Samples");
+                    if self.renderer.is_converged() {
+                        ui.label(format!("{} (converged)", self.renderer.samples));
+                    } else {
+                        ui.label(self.renderer.samples.to_string());
+                    }
+                    ui.end_row();
+
+                    ui.label("Noise");
+                    ui.label(match self.renderer.noise_estimate {
+                        Some(noise) => format!("{:.3}%", noise * 100.0),
+                        None => "-".to_string(),
+                    });
+                    ui.end_row();
+
+                    ui.label("Paths/s");
+                    let paths_per_second = if self.frametimes.render > 0.0 {
+                        (stats.paths as f32 / self.frametimes.render) as usize
+                    } else {
+                        0
+                    };
+                    ui.label(fmt_usize_separator(paths_per_second));
+                    ui.end_row();
+
+                    ui.label("Primary hit rate");
+                    ui.label(format!("{:.1}%", stats.primary_hit_rate() * 100.0));
+                    ui.end_row();
+
+                    ui.label("Average path length");
+                    ui.label(format!("{:.2}", stats.average_path_length()));
+                    ui.end_row();
 
+                    ui.label("Spheres");
+                    ui.label(self.scene.spheres.len().to_string());
+                    ui.end_row();
+
+                    ui.label("Triangles");
+                    let triangles = self
+                        .scene
+                        .meshes
+                        .iter()
+                        .filter_map(|mesh| mesh.mesh.as_ref()?.indices())
+                        .map(|indices| indices.len() / 3)
+                        .sum();
+                    ui.label(fmt_usize_separator(triangles));
+                    ui.end_row();
+
+                    ui.label("Buffers memory");
+                    ui.label(format!(
+                        "{:.2} MiB",
+                        self.renderer.buffers_size() as f32 / (1024.0 * 1024.0)
+                    ));
+                    ui.end_row();
+                });
+            }
+            Tabs::Settings => {
+                ui.horizontal(|ui| {
+                    ui.label("Mode");
+                    let mode = &mut self.renderer.mode;
+                    reset |= ui
+                        .radio_value(mode, RenderMode::PathTraced, "Path Traced")
+                        .changed();
+                    reset |= ui
+                        .radio_value(mode, RenderMode::FastPreview, "Fast Preview")
+                        .changed();
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Tonemapping");
+                    let mut changed = false;
+                    egui::ComboBox::from_id_source("tonemapping")
+                        .selected_text(format!("{:?}", self.renderer.tonemapping))
+                        .show_ui(ui, |ui| {
+                            for tonemapping in Tonemapping::ALL {
+                                changed |= ui
+                                    .selectable_value(
+                                        &mut self.renderer.tonemapping,
+                                        tonemapping,
+                                        format!("{tonemapping:?}"),
+                                    )
+                                    .changed();
+                            }
+                        });
+                    ui.add_enabled_ui(
+                        self.renderer.tonemapping == Tonemapping::ReinhardExtended,
+                        |ui| {
+                            ui.label("White Point");
+                            changed |= drag_f32_clamp(
+                                ui,
+                                &mut self.renderer.white_point,
+                                0.05,
+                                0.1..=100.0,
+                            );
+                        },
+                    );
+                    if changed {
+                        self.renderer.resolve_image();
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Output");
+                    let mut changed = false;
+                    egui::ComboBox::from_id_source("output_color_space")
+                        .selected_text(format!("{:?}", self.renderer.output_color_space))
+                        .show_ui(ui, |ui| {
+                            for color_space in OutputColorSpace::ALL {
+                                changed |= ui
+                                    .selectable_value(
+                                        &mut self.renderer.output_color_space,
+                                        color_space,
+                                        format!("{color_space:?}"),
+                                    )
+                                    .changed();
+                            }
+                        });
+                    // Only the display changes, there's no need to reset the accumulation
+                    if changed {
+                        self.renderer.resolve_image();
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Pass");
+                    let mut changed = false;
+                    for output in RenderOutput::ALL {
+                        changed |= ui
+                            .radio_value(&mut self.renderer.output, output, format!("{output:?}"))
+                            .changed();
+                    }
+                    if changed {
+                        self.renderer.resolve_image();
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Sampler");
+                    for sampler in Sampler::ALL {
+                        reset |= ui
+                            .radio_value(
+                                &mut self.renderer.sampler,
+                                sampler,
+                                format!("{sampler:?}"),
+                            )
+                            .changed();
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Seed Offset")
+                        .on_hover_text("Renders another noise pattern with the same settings");
+                    reset |= ui
+                        .add(egui::DragValue::new(&mut self.renderer.frame_seed_offset))
+                        .changed();
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Quality");
+                    let current = QualityPreset::from_renderer(self.renderer);
+                    egui::ComboBox::from_id_source("quality_preset")
+                        .selected_text(current.map_or("Custom".to_string(), |q| format!("{q:?}")))
+                        .show_ui(ui, |ui| {
+                            for preset in QualityPreset::ALL {
+                                if ui
+                                    .selectable_label(
+                                        current == Some(preset),
+                                        format!("{preset:?}"),
+                                    )
+                                    .clicked()
+                                {
+                                    preset.apply(self.renderer);
+                                }
+                            }
+                        });
+                });
                 ui.horizontal(|ui| {
                     ui.label("Bounces");
                     reset |= drag_u8(ui, &mut self.renderer.bounces, 0.25);
                 });
+                ui.horizontal(|ui| {
+                    ui.label("Specular Bounces")
+                        .on_hover_text("Extra bounces on near mirror surfaces");
+                    reset |= drag_u8(ui, &mut self.renderer.specular_bounces, 0.25);
+                });
                 ui.horizontal(|ui| {
                     ui.label("Rays per pixel");
                     reset |= drag_u8(ui, &mut self.renderer.rays_per_pixel, 0.25);
                 });
+                ui.horizontal(|ui| {
+                    ui.label("Max Samples");
+                    let mut enabled = self.renderer.max_samples.is_some();
+                    if ui.checkbox(&mut enabled, "").changed() {
+                        self.renderer.max_samples = enabled.then_some(self.renderer.samples);
+                    }
+                    if let Some(max_samples) = &mut self.renderer.max_samples {
+                        drag_usize(ui, max_samples, 1.0, usize::MAX);
+                        // 0 would mean no limit, that's what the checkbox is for
+                        *max_samples = (*max_samples).max(1);
+                    }
+                });
+                reset |= ui
+                    .checkbox(&mut self.renderer.anti_aliasing, "Anti-aliasing")
+                    .on_hover_text("Jitters the camera rays inside their pixel")
+                    .changed();
+                ui.add_enabled_ui(self.renderer.anti_aliasing, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Pixel Filter")
+                            .on_hover_text("How the jittered rays are spread around the pixel");
+                        for filter in PixelFilter::ALL {
+                            reset |= ui
+                                .radio_value(
+                                    &mut self.renderer.pixel_filter,
+                                    filter,
+                                    format!("{filter:?}"),
+                                )
+                                .changed();
+                        }
+                    });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Firefly Clamp")
+                        .on_hover_text("Maximum luminance of a single sample");
+                    let mut enabled = self.renderer.firefly_clamp.is_some();
+                    if ui.checkbox(&mut enabled, "").changed() {
+                        self.renderer.firefly_clamp = enabled.then_some(10.0);
+                        reset = true;
+                    }
+                    if let Some(max_luminance) = &mut self.renderer.firefly_clamp {
+                        reset |= drag_f32_clamp(ui, max_luminance, 0.1, 0.1..=1000.0);
+                    }
+                });
+                reset |= ui
+                    .checkbox(&mut self.renderer.caustic_boost, "Caustic boost")
+                    .on_hover_text(
+                        "Lights the surfaces behind glass with the sun and the emissive spheres. \
+                        This is biased, the light isn't bent by the glass.",
+                    )
+                    .changed();
+                ui.horizontal(|ui| {
+                    ui.label("Accumulation");
+                    let mut precision = self.renderer.accumulation_data.precision();
+                    egui::ComboBox::from_id_source("accumulation_precision")
+                        .selected_text(format!("{precision:?}"))
+                        .show_ui(ui, |ui| {
+                            for value in AccumulationPrecision::ALL {
+                                ui.selectable_value(&mut precision, value, format!("{value:?}"));
+                            }
+                        })
+                        .response
+                        .on_hover_text(
+                            "Half precision uses half the memory but can band after many samples",
+                        );
+                    if precision != self.renderer.accumulation_data.precision() {
+                        self.renderer.set_accumulation_precision(precision);
+                    }
+                });
 
                 ui.checkbox(&mut self.renderer.accumulate, "Accumulate");
+                ui.checkbox(&mut self.renderer.partial_reset, "Partial reset on move")
+                    .on_hover_text("Only reset the pixels around an object when it moves");
+                ui.horizontal(|ui| {
+                    ui.label("Preview Scale").on_hover_text(
+                        "Traces blocks of this many pixels as one while the camera moves",
+                    );
+                    drag_u8(ui, &mut self.renderer.preview_scale, 0.1);
+                });
+                reset |= ui
+                    .checkbox(
+                        &mut self.renderer.transparent_background,
+                        "Transparent background",
+                    )
+                    .changed();
+                ui.horizontal(|ui| {
+                    let lens_flare = &mut self.renderer.lens_flare;
+                    reset |= ui.checkbox(&mut lens_flare.enabled, "Lens flare").changed();
+                    ui.add_enabled_ui(lens_flare.enabled, |ui| {
+                        ui.label("Intensity");
+                        reset |= drag_f32_clamp(ui, &mut lens_flare.intensity, 0.005, 0.0..=1.0);
+                        ui.label("Threshold");
+                        reset |= drag_f32_clamp(ui, &mut lens_flare.threshold, 0.05, 0.0..=100.0);
+                    });
+                });
+                ui.horizontal(|ui| {
+                    let denoiser = &mut self.renderer.denoiser;
+                    let mut changed = ui
+                        .checkbox(&mut denoiser.enabled, "Denoise")
+                        .on_hover_text("Only the displayed image, the HDR exports stay noisy")
+                        .changed();
+                    ui.add_enabled_ui(denoiser.enabled, |ui| {
+                        ui.label("Iterations");
+                        changed |=
+                            drag_usize(ui, &mut denoiser.iterations, 0.05, MAX_DENOISER_ITERATIONS);
+                    });
+                    if changed {
+                        self.renderer.resolve_image();
+                    }
+                });
                 reset |= ui.button("Reset").clicked();
 
+                ui.separator();
+                ui.heading("Camera Bookmarks");
+                let mut removed = None;
+                for (i, bookmark) in self.camera_bookmarks.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(&bookmark.name);
+                        if ui.button("Go").clicked() {
+                            self.camera.restore_bookmark(bookmark);
+                            reset = true;
+                        }
+                        if ui.button("Remove").clicked() {
+                            removed = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = removed {
+                    self.camera_bookmarks.remove(i);
+                }
+                ui.horizontal(|ui| {
+                    let id = egui::Id::new("bookmark_name");
+                    let mut name = ui.data_mut(|data| {
+                        data.get_persisted_mut_or_insert_with(id, String::new)
+                            .clone()
+                    });
+                    if ui.text_edit_singleline(&mut name).changed() {
+                        ui.data_mut(|data| data.insert_persisted(id, name.clone()));
+                    }
+                    if ui.button("Save View").clicked() {
+                        if name.is_empty() {
+                            name = format!("View {}", self.camera_bookmarks.len() + 1);
+                        }
+                        self.camera_bookmarks.push(self.camera.bookmark(name));
+                    }
+                });
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.label("Nudge Step");
+                    drag_f32_clamp(ui, &mut self.selection.nudge_step, 0.005, 0.001..=10.0);
+                });
+
                 ui.horizontal(|ui| {
                     ui.label("Viewport Scale");
-                    reset |= drag_f32_clamp(ui, self.viewport_scale, 0.05, 0.1..=1.0);
+                    reset |= drag_f32_clamp(ui, &mut self.viewport_scale.scale, 0.05, 0.1..=1.0);
+                    ui.checkbox(
+                        &mut self.viewport_scale.bilinear_upscale,
+                        "Bilinear Upscale",
+                    )
+                    .on_hover_text("Smooths a scaled down render when it's stretched");
+                });
+                ui.checkbox(self.show_gizmos, "Show Gizmos");
+                let mut show_probe =
+                    ui.data_mut(|data| *data.get_persisted_mut_or_default::<bool>(probe_id()));
+                if ui
+                    .checkbox(&mut show_probe, "Show Hover Probe")
+                    .on_hover_text("Shows what's under the cursor in the viewport")
+                    .changed()
+                {
+                    ui.data_mut(|data| data.insert_persisted(probe_id(), show_probe));
+                }
+                let mut show_aabbs =
+                    ui.data_mut(|data| *data.get_persisted_mut_or_default::<bool>(aabbs_id()));
+                if ui
+                    .checkbox(&mut show_aabbs, "Show AABBs")
+                    .on_hover_text("Green boxes are hit by the ray going through the center")
+                    .changed()
+                {
+                    ui.data_mut(|data| data.insert_persisted(aabbs_id(), show_aabbs));
+                }
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button("Copy to Clipboard").clicked() {
+                        if let Err(err) = copy_to_clipboard(self.renderer) {
+                            self.load_errors.push(err);
+                        }
+                    }
+                    let flip_id = egui::Id::new("export_flip_y");
+                    let mut flip_y =
+                        ui.data_mut(|data| *data.get_persisted_mut_or_default::<bool>(flip_id));
+                    if ui.button("Save PNG").clicked() {
+                        if let Err(err) = save_png(self.renderer, flip_y) {
+                            self.load_errors.push(err);
+                        }
+                    }
+                    if ui.button("Save PPM").clicked() {
+                        if let Err(err) = save_ppm(self.renderer, flip_y) {
+                            self.load_errors.push(err);
+                        }
+                    }
+                    if ui
+                        .checkbox(&mut flip_y, "Flip Y")
+                        .on_hover_text("Writes the rows from bottom to top, PFM is always flipped")
+                        .changed()
+                    {
+                        ui.data_mut(|data| data.insert_persisted(flip_id, flip_y));
+                    }
+                    if ui.button("Save PFM").clicked() {
+                        if let Err(err) = save_pfm(self.renderer) {
+                            self.load_errors.push(err);
+                        }
+                    }
+                    if ui
+                        .button("Save EXR")
+                        .on_hover_text("32-bit float render, before tonemapping")
+                        .clicked()
+                    {
+                        if let Err(err) = save_exr(self.renderer) {
+                            self.load_errors.push(err);
+                        }
+                    }
+                    if ui
+                        .button("Save Object IDs")
+                        .on_hover_text("16-bit PNG of the object seen through each pixel")
+                        .clicked()
+                    {
+                        if let Err(err) = save_object_id_png(self.renderer, flip_y) {
+                            self.load_errors.push(err);
+                        }
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Project");
+                    ui.text_edit_singleline(self.project_path);
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Save Project").clicked() {
+                        if let Err(err) =
+                            save_project(self.project_path, self.scene, self.camera, self.renderer)
+                        {
+                            self.load_errors.push(err);
+                        }
+                    }
+                    if ui.button("Load Project").clicked() {
+                        if let Err(err) =
+                            load_project(self.project_path, self.scene, self.camera, self.renderer)
+                        {
+                            self.load_errors.push(err);
+                        }
+                    }
+                });
+                ui.collapsing("Furnace Test", |ui| {
+                    if ui
+                        .button("Load Furnace Scene")
+                        .on_hover_text("Replaces the scene with a white sphere under a gray sky")
+                        .clicked()
+                    {
+                        *self.scene = Scene::furnace(Vec3::splat(FURNACE_SKY_COLOR));
+                        self.selection.object = None;
+                        reset = true;
+                    }
+                    // Every pixel should converge to the sky color when there are no emitters
+                    if let SkyMode::Solid(expected) = self.scene.sky.mode {
+                        match self.renderer.compare_to_color(expected) {
+                            Some((average, max_error)) => {
+                                ui.label(format!(
+                                    "Object average: {:.4} {:.4} {:.4}",
+                                    average.x, average.y, average.z
+                                ));
+                                ui.label(format!(
+                                    "Expected: {:.4} {:.4} {:.4}",
+                                    expected.x, expected.y, expected.z
+                                ));
+                                ui.label(format!("Max pixel error: {max_error:.4}"));
+                            }
+                            None => {
+                                ui.label("No object is visible");
+                            }
+                        }
+                    } else {
+                        ui.label("The comparison needs a solid sky");
+                    }
+                });
+
+                ui.collapsing("Path Debug", |ui| {
+                    ui.checkbox(
+                        &mut self.path_debug.enabled,
+                        "Click a pixel to trace its path",
+                    );
+                    egui::Grid::new("path_debug_grid")
+                        .num_columns(4)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            ui.label("Hit");
+                            ui.label("Material");
+                            ui.label("Throughput");
+                            ui.label("Radiance");
+                            ui.end_row();
+                            let fmt_vec3 = |v: Vec3| format!("{:.3} {:.3} {:.3}", v.x, v.y, v.z);
+                            for vertex in &self.path_debug.path {
+                                ui.label(fmt_vec3(vertex.position));
+                                ui.label(match vertex.material_id {
+                                    Some(id) => id.to_string(),
+                                    None => "Sky".to_string(),
+                                });
+                                ui.label(fmt_vec3(vertex.throughput));
+                                ui.label(fmt_vec3(vertex.radiance));
+                                ui.end_row();
+                            }
+                        });
+                });
+
+                ui.collapsing("Sample Distribution", |ui| {
+                    let id = egui::Id::new("sample_distribution_dimension");
+                    let mut dimension_index =
+                        ui.data_mut(|data| *data.get_persisted_mut_or_default::<usize>(id));
+                    ui.horizontal(|ui| {
+                        for (i, dimension) in SampleDimension::ALL.iter().enumerate() {
+                            ui.radio_value(&mut dimension_index, i, format!("{dimension:?}"));
+                        }
+                    });
+                    ui.data_mut(|data| data.insert_persisted(id, dimension_index));
+                    let dimension = SampleDimension::ALL[dimension_index];
+                    ui.label(format!(
+                        "First {SAMPLE_DISTRIBUTION_POINTS} points of the {:?} sampler",
+                        self.renderer.sampler
+                    ));
+                    let points =
+                        sample_points(self.renderer.sampler, dimension, SAMPLE_DISTRIBUTION_POINTS);
+                    ui.horizontal(|ui| {
+                        scatter_plot(ui, &points);
+                        // The dimensions are decorrelated when this looks as even as the points
+                        // of a single dimension
+                        if dimension != SampleDimension::Bounce {
+                            let bounce = sample_points(
+                                self.renderer.sampler,
+                                SampleDimension::Bounce,
+                                SAMPLE_DISTRIBUTION_POINTS,
+                            );
+                            let against_bounce: Vec<_> = points
+                                .iter()
+                                .zip(&bounce)
+                                .map(|(point, bounce)| Vec2::new(point.x, bounce.x))
+                                .collect();
+                            scatter_plot(ui, &against_bounce)
+                                .on_hover_text("First coordinate against the one of the bounce");
+                        }
+                    });
                 });
             }
         };
@@ -281,3 +1469,65 @@ impl<'a> egui_dock::TabViewer for TabViewer<'a> {
         format!("{tab:?}").into()
     }
 }
+
+/// Number of points shown by the sample distribution plots
+const SAMPLE_DISTRIBUTION_POINTS: u32 = 256;
+
+/// Draws points of the unit square, with the origin at the bottom left
+fn scatter_plot(ui: &mut egui::Ui, points: &[Vec2]) -> egui::Response {
+    let (response, painter) = ui.allocate_painter(egui::Vec2::splat(160.0), egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_stroke(rect, 0.0, ui.visuals().widgets.noninteractive.bg_stroke);
+    for point in points {
+        let position = rect.left_bottom() + egui::vec2(point.x, -point.y) * rect.size();
+        painter.circle_filled(position, 1.5, ui.visuals().text_color());
+    }
+    response
+}
+
+/// Picks one of the textures of the scene, or none
+fn texture_combo(
+    ui: &mut egui::Ui,
+    id: String,
+    textures: &[Texture],
+    value: &mut Option<usize>,
+) -> bool {
+    let texture_name = |index: Option<usize>| match index.and_then(|index| textures.get(index)) {
+        Some(texture) => texture.path.clone(),
+        None => "None".to_string(),
+    };
+    let mut changed = false;
+    egui::ComboBox::from_id_source(id)
+        .selected_text(texture_name(*value))
+        .show_ui(ui, |ui| {
+            for index in std::iter::once(None).chain((0..textures.len()).map(Some)) {
+                changed |= ui
+                    .selectable_value(value, index, texture_name(index))
+                    .changed();
+            }
+        });
+    changed
+}
+
+/// Edits the emission added to an object on top of its material
+fn emissive_override(ui: &mut egui::Ui, value: &mut Option<Vec3>) -> bool {
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        let mut enabled = value.is_some();
+        if ui.checkbox(&mut enabled, "").changed() {
+            *value = enabled.then_some(Vec3::ONE);
+            changed = true;
+        }
+        if let Some(emission) = value {
+            changed |= drag_vec3_clamp(ui, emission, 0.05, 0.0..=Material::MAX_EMISSIVE_POWER);
+        }
+    });
+    changed
+}
+
+fn selection_checkbox(ui: &mut egui::Ui, selection: &mut Selection, object: ObjectRef) {
+    let mut selected = selection.object == Some(object);
+    if ui.checkbox(&mut selected, "").changed() {
+        selection.object = selected.then_some(object);
+    }
+}